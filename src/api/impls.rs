@@ -12,8 +12,12 @@ impl Display for JudgeState {
         let output = match self {
             Self::Nominated => "Nominated".into(),
             Self::Accepted => "Accepted".into(),
+            Self::Committed => "Committed".into(),
+            Self::Standby => "Standby".into(),
+            Self::Replaced => "Replaced".into(),
             Self::Refused => "Refused".into(),
             Self::Resolved(decision) => format!("Resolved({})", decision),
+            Self::ResolvedScalar(value) => format!("ResolvedScalar({})", value),
         };
         write!(f, "{}", output)
     }
@@ -25,22 +29,86 @@ impl Display for MarketState {
             Self::Trading => "Trading".into(),
             Self::Stopped => "Stopped".into(),
             Self::WaitingForDecision => "WaitingForDecision".into(),
+            Self::WaitingForDisputes(outcome) => format!("WaitingForDisputes({})", outcome),
+            Self::Disputed(outcome) => format!("Disputed({})", outcome),
             Self::Resolved(outcome) => format!("Resolved({})", outcome),
+            Self::ResolvedScalar(value) => format!("ResolvedScalar({})", value),
             Self::Refunded(reason) => format!("Refunded({})", reason),
         };
         write!(f, "{}", output)
     }
 }
+impl MarketState {
+    /// Whether the bets of the market were already paid out or refunded
+    pub fn is_settled(&self) -> bool {
+        matches!(
+            self,
+            Self::Resolved(_) | Self::ResolvedScalar(_) | Self::Refunded(_)
+        )
+    }
+}
+impl BooksResponse {
+    /// Whether every transaction is balanced and the stored balances match
+    /// the journal
+    pub fn is_balanced(&self) -> bool {
+        self.unbalanced_transactions.is_empty() && self.balance_mismatches.is_empty()
+    }
+}
+impl ReconciliationResponse {
+    /// Whether the wallet covers all liabilities and every payment state
+    /// agrees with the funding source
+    pub fn is_reconciled(&self) -> bool {
+        self.discrepancy == 0 && self.mismatched_txs.is_empty()
+    }
+}
 impl Display for RefundReason {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let output = match self {
             Self::Insolvency => "Insolvency",
             Self::TimeForDecisionRanOut => "TimeForDecisionRanOut",
             Self::Tie => "Tie",
+            Self::NoConsensus => "NoConsensus",
+            Self::NotEnoughJudges => "NotEnoughJudges",
+            Self::Cancelled => "Cancelled",
+            Self::Admin => "Admin",
+        };
+        write!(f, "{}", output)
+    }
+}
+impl Display for Account {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let output = match self {
+            Self::User(user) => format!("User({})", user),
+            Self::Escrow(prediction) => format!("Escrow({})", prediction),
+            Self::JudgePool(prediction) => format!("JudgePool({})", prediction),
+            Self::Fees => "Fees".into(),
+            Self::Funding => "Funding".into(),
+            Self::Adjustments => "Adjustments".into(),
+            Self::Dust => "Dust".into(),
+        };
+        write!(f, "{}", output)
+    }
+}
+impl Display for OrderSide {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let output = match self {
+            Self::Bid => "Bid",
+            Self::Ask => "Ask",
         };
         write!(f, "{}", output)
     }
 }
+impl FromStr for OrderSide {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "Bid" => Ok(Self::Bid),
+            "Ask" => Ok(Self::Ask),
+            e => bail!("Couldn't deserialize to OrderSide: {}", e),
+        }
+    }
+}
 impl Display for UserRole {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let output = match self {
@@ -64,8 +132,3 @@ impl FromStr for UserRole {
         }
     }
 }
-impl Default for UserRole {
-    fn default() -> Self {
-        UserRole::User
-    }
-}
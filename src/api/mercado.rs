@@ -1,43 +1,218 @@
+use chrono::{DateTime, Utc};
 use rust_decimal::{prelude::ToPrimitive, Decimal};
+use secp256k1::hashes::{sha256, Hash};
 use serde::{Deserialize, Serialize};
 
 pub type Sats = i64;
 pub type UserPubKey = secp256k1::PublicKey;
 pub type RowId = i64;
 pub type Payment = String;
+/// Index into the outcomes of a prediction
+pub type Outcome = u32;
+/// Shares of an outcome in a market maker prediction. Every winning share
+/// pays out one sat.
+pub type Shares = i64;
+
+/// Outcomes of predictions that were created without explicit outcomes.
+/// `Yes` is outcome `0` and `No` is outcome `1`.
+pub const DEFAULT_OUTCOMES: [&str; 2] = ["Yes", "No"];
+/// Outcomes of scalar predictions. Bets on these are long and short positions
+/// on the value the judges decide on.
+pub const SCALAR_OUTCOMES: [&str; 2] = ["Long", "Short"];
+pub const SCALAR_LONG: Outcome = 0;
+pub const SCALAR_SHORT: Outcome = 1;
+/// Sats a matched order book contract pays out if its outcome wins.
+/// Order prices are in sats per contract and lie between `1` and `CONTRACT_SATS - 1`.
+pub const CONTRACT_SATS: Sats = 100;
+pub const DEFAULT_PAGE_LIMIT: u32 = 100;
+pub const MAX_PAGE_LIMIT: u32 = 1000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Bet {
     pub id: RowId,
     pub user: UserPubKey,
     pub prediction: RowId,
-    pub bet: bool,
+    pub outcome: Outcome,
     pub amount: Sats,
+    /// Price the owner offers the bet for on the secondary market
+    pub sale_price: Option<Sats>,
+    #[serde(default)]
+    pub status: BetStatus,
+}
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BetStatus {
+    #[default]
+    Open,
+    /// The prediction resolved, see the ledger for the payout
+    Settled,
+    Refunded,
+}
+/// What sats of a prediction were booked for
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LedgerKind {
+    /// Sats of a bet or order book position that went into the pool. Also
+    /// what market maker shares cost and the subsidy of the market maker.
+    Stake,
+    /// Winnings of a bet, order book position or market maker shares. Also
+    /// what selling market maker shares paid.
+    Payout,
+    JudgeFee,
+    CreatorFee,
+    PlatformFee,
+    /// Slashed judge or dispute bond
+    Bond,
+    /// Share of slashed bonds
+    Slash,
+    Refund,
+    /// What is left of the market maker subsidy after resolution
+    Subsidy,
+    /// Sats of a settlement that nobody is entitled to, e.g. because nobody
+    /// bet on the outcome
+    Remainder,
+}
+/// Account of the double-entry journal. Postings add to the balance of their
+/// account and the postings of every transaction add up to zero.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Account {
+    /// Balance of a user
+    User(UserPubKey),
+    /// Sats of a prediction that wait for settlement
+    Escrow(RowId),
+    /// Judge fees of a prediction on their way to the judges
+    JudgePool(RowId),
+    /// Platform and creator fees on their way to the house and the creators
+    Fees,
+    /// Sats that entered or left through the funding source. Its balance is
+    /// the negative of what the funding source should hold.
+    Funding,
+    /// Balance adjustments by admins
+    Adjustments,
+    /// Remainders of settlements if there is no house account to take them
+    Dust,
+}
+/// Balance change of a user from the settlement of a prediction. Stakes and
+/// slashed bonds are negative.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LedgerEntry {
+    pub id: RowId,
+    pub user: UserPubKey,
+    pub prediction: RowId,
+    pub bet: Option<RowId>,
+    pub kind: LedgerKind,
+    pub amount: Sats,
+    pub time: DateTime<Utc>,
+}
+/// Sale of a bet from one user to another
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BetTransfer {
+    pub bet: RowId,
+    pub seller: UserPubKey,
+    pub buyer: UserPubKey,
+    pub price: Sats,
+    pub time: DateTime<Utc>,
+}
+/// Shares a user holds of one outcome in a market maker prediction
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ShareHolding {
+    pub user: UserPubKey,
+    pub prediction: RowId,
+    pub outcome: Outcome,
+    pub shares: Shares,
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OrderSide {
+    Bid,
+    Ask,
+}
+/// Limit order on one outcome of a yes/no prediction.
+///
+/// A bid on one outcome is the same as an ask on the other outcome at
+/// [`CONTRACT_SATS`] minus the price, so both end up in the same order book.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Order {
+    pub id: RowId,
+    pub user: UserPubKey,
+    pub prediction: RowId,
+    pub outcome: Outcome,
+    pub side: OrderSide,
+    pub price: Sats,
+    pub quantity: i64,
+    pub filled: i64,
+    pub cancelled: bool,
+}
+/// Matched contracts a user holds of one outcome and the sats paid for them
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Position {
+    pub user: UserPubKey,
+    pub prediction: RowId,
+    pub outcome: Outcome,
+    pub contracts: i64,
+    pub cost: Sats,
 }
 #[derive(Debug, Clone, Serialize, Deserialize, Copy)]
 pub struct Judge {
     pub user: UserPubKey,
     pub prediction: RowId,
     pub state: JudgeState,
+    #[serde(default)]
+    pub reputation: Option<JudgeReputation>,
+}
+/// Track record of a judge over all predictions they were nominated for
+#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq, Eq)]
+pub struct JudgeReputation {
+    pub user: UserPubKey,
+    pub nominations: u32,
+    pub accepted: u32,
+    pub refused: u32,
+    /// Votes cast before the decision period ran out
+    pub votes: u32,
+    pub majority_votes: u32,
+    pub minority_votes: u32,
+    /// Predictions that got refunded while the judge still hadn't voted
+    pub missed_decisions: u32,
+    /// Resolved predictions the judge voted on
+    pub predictions_judged: u32,
+    /// Sats that were at stake in the predictions the judge resolved
+    pub sats_judged: Sats,
+    /// Share of majority votes among all votes that could be compared to a
+    /// majority and all missed decisions. `None` without any of those.
+    pub score_ppm: Option<u32>,
 }
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct JudgePublic {
     pub user: UserPubKey,
     pub prediction: RowId,
+    /// Last time the creator changed the terms of the prediction. Accepted
+    /// nominations are reset when that happens.
+    #[serde(default)]
+    pub terms_changed: Option<DateTime<Utc>>,
 }
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Copy)]
 pub enum JudgeState {
     Nominated,
     Accepted,
+    /// The judge committed to a decision that isn't revealed yet
+    Committed,
+    /// The judge accepted after enough judges were found and replaces judges
+    /// that don't vote in time
+    Standby,
+    /// The judge didn't vote in time and got replaced by a standby judge
+    Replaced,
     Refused,
-    Resolved(bool),
+    Resolved(Outcome),
+    ResolvedScalar(i64),
 }
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Copy, Eq)]
 pub enum MarketState {
     WaitingForJudges,
     Trading,
     WaitingForDecision,
-    Resolved(bool),
+    /// The judges decided on the outcome but it can still be disputed
+    WaitingForDisputes(Outcome),
+    /// The decision of the judges was disputed and the appeal judges decide
+    Disputed(Outcome),
+    Resolved(Outcome),
+    ResolvedScalar(i64),
     Refunded(RefundReason),
     Stopped,
 }
@@ -46,48 +221,166 @@ pub enum RefundReason {
     Insolvency,
     TimeForDecisionRanOut,
     Tie,
+    /// No outcome got the supermajority the prediction requires
+    NoConsensus,
+    NotEnoughJudges,
+    /// The creator cancelled the prediction before trading started
+    Cancelled,
+    /// An admin refunded the prediction after stopping it
+    Admin,
+}
+/// Details of a prediction that tell bettors and judges what exactly it is
+/// about and how it gets decided
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PredictionMetadata {
+    /// Longer explanation of the prediction in markdown
+    #[serde(default)]
+    pub description: String,
+    /// Where the judges look up the outcome
+    #[serde(default)]
+    pub resolution_source: String,
+    /// How the judges decide on the outcome
+    #[serde(default)]
+    pub resolution_criteria: String,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub image_url: Option<String>,
+}
+/// Range in which judges decide on the value of a scalar prediction
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ScalarRange {
+    pub min: i64,
+    pub max: i64,
+}
+/// How the votes of the judges turn into a decision
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResolutionRules {
+    /// Share of the votes cast the winning outcome needs. `0` means a simple
+    /// majority is enough.
+    #[serde(default)]
+    pub supermajority_ppm: u32,
+    /// Votes that need to be cast to decide with only those votes once the
+    /// decision period is over. Without a quorum every judge has to vote.
+    #[serde(default)]
+    pub quorum: u32,
+    #[serde(default)]
+    pub on_no_consensus: NoConsensusAction,
 }
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// What happens when the judges don't reach the required majority or quorum
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum NoConsensusAction {
+    #[default]
+    Refund,
+    /// Extends the decision period by its length once. Judges vote again if
+    /// they didn't reach the required majority.
+    Extend,
+}
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 pub enum UserRole {
+    #[default]
     User,
     Admin,
     Root,
 }
-pub fn calculate_user_cash_out(
-    bet_amount: i64,
-    outcome_amount: i64,
-    non_outcome_amount: i64,
-    judge_share_ppm: u32,
-) -> Sats {
-    //! If the calculation of shares leads to decimals we truncate to not give
-    //! out to many sats by accident which would lead to an insolvent market.
-    //! We keep the sats that don't get handed back to the user.
+/// Splits `amount` proportionally to `weights` with the largest remainder
+/// method.
+///
+/// Every share gets truncated and the sats left over go one by one to the
+/// shares with the largest remainders, ties go to the earlier weight. The
+/// shares add up to `amount` exactly unless all weights are zero, then
+/// nothing is handed out.
+pub fn distribute(amount: Sats, weights: &[Sats]) -> Vec<Sats> {
+    let total: i128 = weights.iter().map(|weight| *weight as i128).sum();
+    if amount <= 0 || total <= 0 {
+        return vec![0; weights.len()];
+    }
+    let mut shares = Vec::with_capacity(weights.len());
+    let mut remainders = Vec::with_capacity(weights.len());
+    for (index, weight) in weights.iter().enumerate() {
+        let product = amount as i128 * *weight as i128;
+        shares.push((product / total) as Sats);
+        remainders.push((product % total, index));
+    }
+    let left_over = amount - shares.iter().sum::<Sats>();
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, index) in remainders.into_iter().take(left_over as usize) {
+        shares[index] += 1;
+    }
+    shares
+}
+/// Outcome of a bet or decision from before categorical markets, which
+/// used `true` for `Yes` and `false` for `No`
+pub fn outcome_from_bool(value: bool) -> Outcome {
+    if value {
+        0
+    } else {
+        1
+    }
+}
+pub fn calculate_fee(pool_amount: Sats, fee_ppm: u32) -> Sats {
+    //! Truncates, the sats that are cut off stay with the bettors
+    let fee_share = Decimal::new(fee_ppm.into(), 6);
+    (Decimal::from(pool_amount) * fee_share)
+        .trunc()
+        .to_i64()
+        .unwrap()
+}
+/// Liquidity parameter `b` of the logarithmic market scoring rule.
+///
+/// The market maker can lose at most `b * ln(outcomes)` which gets covered by
+/// the subsidy after the judge share and platform fee are taken out of it.
+pub fn lmsr_liquidity(subsidy: Sats, fee_ppm: u32, outcomes: usize) -> f64 {
+    let fee_share = fee_ppm as f64 / 1_000_000.0;
+    subsidy as f64 * (1.0 - fee_share) / (outcomes as f64).ln()
+}
+/// Cost function `C(q) = b * ln(sum(exp(q_i / b)))` of the market maker
+pub fn lmsr_cost(shares: &[Shares], liquidity: f64) -> f64 {
+    let exponents: Vec<f64> = shares.iter().map(|q| *q as f64 / liquidity).collect();
+    let max = exponents.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let sum: f64 = exponents.iter().map(|e| (e - max).exp()).sum();
+    liquidity * (max + sum.ln())
+}
+/// Current price of one share of each outcome. The prices add up to 1.
+pub fn lmsr_prices(shares: &[Shares], liquidity: f64) -> Vec<f64> {
+    let exponents: Vec<f64> = shares.iter().map(|q| *q as f64 / liquidity).collect();
+    let max = exponents.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let weights: Vec<f64> = exponents.iter().map(|e| (e - max).exp()).collect();
+    let sum: f64 = weights.iter().sum();
+    weights.iter().map(|w| w / sum).collect()
+}
+pub fn calculate_share_trade(
+    shares: &[Shares],
+    outcome: Outcome,
+    amount: Shares,
+    liquidity: f64,
+) -> Option<Sats> {
+    //! Sats the market maker charges for buying a positive `amount` of shares
+    //! or pays out for selling a negative `amount` of shares, which then is a
+    //! negative result.
     //!
-    //! This ends up being a few sats for calculating the judge share
-    //! and usually at least one sat for each user because user_share calculation
-    //! almost always leads to decimals.
-    let total_amount = Decimal::from(outcome_amount + non_outcome_amount);
-    let outcome_amount = Decimal::from(outcome_amount);
-    let bet_amount = Decimal::from(bet_amount);
-    let user_share = bet_amount / outcome_amount;
-    let judge_share = Decimal::new(judge_share_ppm.into(), 6);
-
-    let mut out = (total_amount - total_amount * judge_share).trunc();
-    out = (out * user_share).trunc();
-    out.to_i64().unwrap()
-}
-pub fn calculate_judge_cash_out(
-    outcome_judges: u32,
-    outcome_amount: i64,
-    non_outcome_amount: i64,
-    judge_share_ppm: u32,
-) -> Sats {
-    //! See [`calculate_user_cash_out()`]
-    let total_amount = Decimal::from(outcome_amount + non_outcome_amount);
-    let outcome_judges = Decimal::from(outcome_judges);
-    let judge_share = Decimal::new(judge_share_ppm.into(), 6);
-
-    let mut out = (total_amount * judge_share).trunc();
-    out = (out / outcome_judges).trunc();
-    out.to_i64().unwrap()
+    //! Buys are rounded up and sells rounded down so the market maker never
+    //! hands out more than it can cover. `None` if the cost isn't a finite
+    //! amount of sats, e.g. because the liquidity is zero.
+    let mut after = shares.to_vec();
+    after[outcome as usize] += amount;
+    let cost = (lmsr_cost(&after, liquidity) - lmsr_cost(shares, liquidity)).ceil();
+    if !cost.is_finite() || cost < Sats::MIN as f64 || cost >= Sats::MAX as f64 {
+        return None;
+    }
+    Some(cost as Sats)
+}
+/// Commitment a judge submits before revealing the `decision` on a prediction
+/// that uses commit-reveal voting. `decision` is the outcome or the value of a
+/// scalar prediction.
+pub fn calculate_decision_commitment(
+    prediction: RowId,
+    judge: UserPubKey,
+    decision: i64,
+    salt: &str,
+) -> String {
+    sha256::Hash::hash(format!("{}:{}:{}:{}", prediction, judge, decision, salt).as_bytes())
+        .to_string()
 }
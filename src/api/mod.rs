@@ -6,7 +6,6 @@ mod responses;
 mod util;
 
 pub use self::mercado::*;
-pub use impls::*;
 pub use payment::*;
 pub use requests::*;
 pub use responses::*;
@@ -1,6 +1,6 @@
 use chrono::{DateTime, Utc};
 use secp256k1::ecdsa::Signature;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use super::*;
 
@@ -30,11 +30,72 @@ pub struct UpdateUserRequest {
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct NewPredictionRequest {
     pub prediction: String,
+    /// Possible outcomes of the prediction. Defaults to [`DEFAULT_OUTCOMES`] if empty.
+    #[serde(default)]
+    pub outcomes: Vec<String>,
+    /// Makes this a scalar prediction where judges decide on a value in this range.
+    /// Bets are then placed on [`SCALAR_OUTCOMES`].
+    #[serde(default)]
+    pub scalar: Option<ScalarRange>,
     pub judges: Vec<UserPubKey>,
     pub judge_share_ppm: u32,
     pub trading_end: DateTime<Utc>,
     pub decision_period_sec: u32,
     pub judge_count: u32,
+    /// Sats every judge has to lock when accepting the nomination. Bonds of
+    /// judges that vote against the majority or don't vote get slashed.
+    #[serde(default)]
+    pub judge_bond: Sats,
+    /// Time after the judges decided in which bettors can dispute the
+    /// decision. Without it the decision is final right away.
+    #[serde(default)]
+    pub dispute_period_sec: u32,
+    /// Sats a bettor has to lock to dispute the decision
+    #[serde(default)]
+    pub dispute_bond: Sats,
+    /// Judges that decide on disputes. They can't be judges of the prediction.
+    #[serde(default)]
+    pub appeal_judges: Vec<UserPubKey>,
+    /// Time after the decision period in which judges reveal their committed
+    /// decisions. Judges have to commit to their decisions if this is set.
+    #[serde(default)]
+    pub reveal_period_sec: u32,
+    /// Time into the decision period after which judges that didn't vote get
+    /// replaced by standby judges. Replacements are disabled if this is `0`.
+    #[serde(default)]
+    pub judge_replacement_sec: u32,
+    /// Share of the pool the creator of the prediction gets on resolution
+    #[serde(default)]
+    pub creator_fee_ppm: u32,
+    #[serde(default)]
+    pub metadata: PredictionMetadata,
+    #[serde(default)]
+    pub resolution_rules: ResolutionRules,
+}
+/// Creates a prediction where an automated market maker sells shares of the
+/// outcomes. The creator pays the `subsidy` which funds the market maker.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct NewAmmPredictionRequest {
+    pub creator: UserPubKey,
+    pub subsidy: Sats,
+    pub prediction: NewPredictionRequest,
+}
+/// Changes the terms of a prediction that is still waiting for judges.
+/// Changing the text, metadata or trading end resets all accepted nominations.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AmendPredictionRequest {
+    pub prediction: RowId,
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Can only be later than the current trading end
+    #[serde(default)]
+    pub trading_end: Option<DateTime<Utc>>,
+    /// Judges to nominate in addition to the current ones
+    #[serde(default)]
+    pub new_judges: Vec<UserPubKey>,
+    /// Replaces the metadata and resets accepted nominations like the text
+    #[serde(default)]
+    pub metadata: Option<PredictionMetadata>,
 }
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct NominationRequest {
@@ -45,9 +106,51 @@ pub struct NominationRequest {
 pub struct AddBetRequest {
     pub prediction: RowId,
     pub user: UserPubKey,
-    pub bet: bool,
+    /// Also accepts the boolean `bet` of yes/no predictions
+    #[serde(alias = "bet", deserialize_with = "outcome_or_bool")]
+    pub outcome: Outcome,
     pub amount: Sats,
 }
+/// Hypothetical bet to quote the payout for. With a user the quote covers
+/// the existing bets of the user on the outcome as well.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PayoutQuoteRequest {
+    pub prediction: RowId,
+    pub outcome: Outcome,
+    pub amount: Sats,
+    pub user: Option<UserPubKey>,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TradeSharesRequest {
+    pub prediction: RowId,
+    pub user: UserPubKey,
+    pub outcome: Outcome,
+    pub shares: Shares,
+    /// Most sats to pay when buying or least sats to receive when selling
+    pub limit: Option<Sats>,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlaceOrderRequest {
+    pub prediction: RowId,
+    pub user: UserPubKey,
+    pub outcome: Outcome,
+    pub side: OrderSide,
+    pub price: Sats,
+    pub quantity: i64,
+}
+/// Offers a bet for sale or withdraws the offer if `price` is `None`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OfferBetRequest {
+    pub bet: RowId,
+    pub price: Option<Sats>,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BuyBetRequest {
+    pub bet: RowId,
+    pub buyer: UserPubKey,
+    /// Has to match the price the bet is offered for
+    pub price: Sats,
+}
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CancelBetRequest {
     pub payment: Payment,
@@ -57,17 +160,147 @@ pub struct CancelBetRequest {
 pub struct MakeDecisionRequest {
     pub prediction: RowId,
     pub judge: UserPubKey,
-    pub decision: bool,
+    /// Also accepts the boolean decision of yes/no predictions
+    #[serde(deserialize_with = "outcome_or_bool")]
+    pub decision: Outcome,
+}
+/// Commits to a decision without revealing it, see
+/// [`calculate_decision_commitment()`]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CommitDecisionRequest {
+    pub prediction: RowId,
+    pub judge: UserPubKey,
+    pub commitment: String,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RevealDecisionRequest {
+    pub prediction: RowId,
+    pub judge: UserPubKey,
+    /// Outcome or value of a scalar prediction
+    pub decision: i64,
+    pub salt: String,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DisputeRequest {
+    pub prediction: RowId,
+    pub user: UserPubKey,
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MakeScalarDecisionRequest {
+    pub prediction: RowId,
+    pub judge: UserPubKey,
+    pub value: i64,
 }
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PredictionRequest {
     pub prediction: RowId,
     pub user: Option<UserPubKey>,
 }
+/// Pool history of a prediction in buckets of `bucket_sec` seconds
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PredictionHistoryRequest {
+    pub prediction: RowId,
+    pub bucket_sec: u32,
+    #[serde(default)]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+}
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PredictionJudgesRequest {
+    pub prediction: RowId,
+    /// Include the [`JudgeReputation`] of every judge
+    #[serde(default)]
+    pub reputation: bool,
+}
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PredictionUserRequest {
     pub prediction: Option<RowId>,
     pub user: Option<UserPubKey>,
+    #[serde(default, flatten)]
+    pub page: PageRequest,
+}
+/// Cursor pagination of listings that are ordered by id
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PageRequest {
+    /// Only return entries with a greater id, usually the last id of the previous page
+    #[serde(default)]
+    pub cursor: Option<RowId>,
+    /// Defaults to [`DEFAULT_PAGE_LIMIT`] and is capped at [`MAX_PAGE_LIMIT`]
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PredictionSort {
+    #[default]
+    Newest,
+    /// Most sats bet and traded first
+    Volume,
+    /// Earliest trading end first
+    ClosingSoon,
+}
+/// Query parameters of prediction listings. All filters are optional and combined.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct PredictionListRequest {
+    /// Name of the [`MarketState`] without its value, `Resolved` matches every outcome
+    pub state: Option<String>,
+    pub category: Option<String>,
+    /// Predictions the user is nominated as judge for
+    pub judge: Option<UserPubKey>,
+    pub creator: Option<UserPubKey>,
+    pub trading_end_after: Option<DateTime<Utc>>,
+    pub trading_end_before: Option<DateTime<Utc>>,
+    /// Words that all have to appear in the prediction or its description
+    pub search: Option<String>,
+    #[serde(default)]
+    pub sort: PredictionSort,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    /// Defaults to [`DEFAULT_PAGE_LIMIT`] and is capped at [`MAX_PAGE_LIMIT`]
+    pub limit: Option<u32>,
+}
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BettorRanking {
+    /// Sats won minus sats lost
+    #[default]
+    Profit,
+    /// Profit per staked sat
+    Roi,
+    /// Sats staked
+    Volume,
+    /// Predictions with a payout
+    Correct,
+}
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JudgeRanking {
+    #[default]
+    Fees,
+    /// Share of votes that agreed with the final outcome
+    Agreement,
+}
+/// Query parameters of the bettor leaderboard. Only predictions that were
+/// resolved inside the time window and have the category count.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct BettorLeaderboardRequest {
+    #[serde(default)]
+    pub rank_by: BettorRanking,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub category: Option<String>,
+    /// Defaults to [`DEFAULT_PAGE_LIMIT`] and is capped at [`MAX_PAGE_LIMIT`]
+    pub limit: Option<u32>,
+}
+/// Query parameters of the judge leaderboard, filtered like the
+/// [`BettorLeaderboardRequest`]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct JudgeLeaderboardRequest {
+    #[serde(default)]
+    pub rank_by: JudgeRanking,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub category: Option<String>,
+    /// Defaults to [`DEFAULT_PAGE_LIMIT`] and is capped at [`MAX_PAGE_LIMIT`]
+    pub limit: Option<u32>,
 }
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct JudgeRequest {
@@ -75,6 +308,19 @@ pub struct JudgeRequest {
     pub user: UserPubKey,
 }
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HaltPredictionRequest {
+    pub prediction: RowId,
+    /// Shown to users whose actions fail while the prediction is stopped
+    pub reason: String,
+}
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ResumePredictionRequest {
+    pub prediction: RowId,
+    /// Push back the running deadline by the time the prediction was stopped
+    #[serde(default)]
+    pub extend: bool,
+}
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct AdjustBalanceRequest {
     pub user: UserPubKey,
     pub amount: Sats,
@@ -94,4 +340,20 @@ pub struct DepositRequest {
 pub struct TxsRequest {
     pub user: Option<UserPubKey>,
     pub direction: Option<TxDirection>,
+    #[serde(default, flatten)]
+    pub page: PageRequest,
+}
+/// Reads an outcome that clients of yes/no predictions send as a boolean,
+/// see [`outcome_from_bool()`]
+fn outcome_or_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Outcome, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OutcomeOrBool {
+        Outcome(Outcome),
+        Bool(bool),
+    }
+    Ok(match OutcomeOrBool::deserialize(deserializer)? {
+        OutcomeOrBool::Outcome(outcome) => outcome,
+        OutcomeOrBool::Bool(value) => outcome_from_bool(value),
+    })
 }
@@ -7,12 +7,83 @@ use super::*;
 pub struct PredictionOverviewResponse {
     pub id: RowId,
     pub name: String,
+    /// User that created the prediction
+    pub creator: Option<UserPubKey>,
+    pub metadata: PredictionMetadata,
+    pub outcomes: Vec<String>,
+    pub scalar: Option<ScalarRange>,
     pub state: MarketState,
+    /// Why an admin stopped the prediction
+    pub halt_reason: Option<String>,
     pub judge_share_ppm: u32,
+    /// Share of the pool the operator gets on resolution
+    pub platform_fee_ppm: u32,
+    /// Share of the pool the creator gets on resolution
+    pub creator_fee_ppm: u32,
     pub judge_count: u32,
+    pub judge_bond: Sats,
+    pub dispute_period_sec: u32,
+    pub dispute_bond: Sats,
+    pub reveal_period_sec: u32,
+    pub judge_replacement_sec: u32,
     pub trading_end: DateTime<Utc>,
     pub decision_period_sec: u32,
-    pub ratio: (Sats, Sats),
+    pub resolution_rules: ResolutionRules,
+    /// Whether the decision period was extended because the judges didn't
+    /// reach a decision
+    pub decision_extended: bool,
+    /// Sum of all bets per outcome. For market maker predictions this is the
+    /// amount of shares sold per outcome.
+    pub ratio: Vec<Sats>,
+    /// Subsidy of the market maker if the prediction uses one
+    pub amm_subsidy: Option<Sats>,
+}
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Eq)]
+pub struct PredictionListResponse {
+    pub predictions: Vec<PredictionOverviewResponse>,
+    /// Cursor of the next page, `None` on the last page
+    pub next_cursor: Option<String>,
+}
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct BalanceMismatch {
+    pub user: UserPubKey,
+    /// Balance stored for the user
+    pub cached: Sats,
+    /// Sum of the postings to the account of the user
+    pub journal: Sats,
+}
+/// Result of checking the double-entry journal
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct BooksResponse {
+    /// Transactions whose postings don't add up to zero
+    pub unbalanced_transactions: Vec<RowId>,
+    pub balance_mismatches: Vec<BalanceMismatch>,
+    /// Balances of all accounts except users that aren't zero
+    pub accounts: Vec<(Account, Sats)>,
+}
+/// Payment whose stored state differs from the one reported by the
+/// funding source
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct TxMismatch {
+    pub id: RowId,
+    pub stored: TxStateBolt11,
+    /// None if the funding source doesn't know the payment
+    pub funding: Option<TxStateBolt11>,
+}
+/// Comparison of what the server owes against what the wallet holds
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct ReconciliationResponse {
+    /// Sum of all user balances, including funds reserved by open orders and bonds
+    pub user_balances: Sats,
+    /// Funds held by markets, judge pools, the fee and the dust account
+    pub escrow: Sats,
+    /// Withdrawals that were initiated but haven't settled yet
+    pub pending_withdrawals: Sats,
+    pub liabilities: Sats,
+    pub wallet_balance: Sats,
+    /// Wallet balance minus liabilities, negative if the wallet is short
+    pub discrepancy: Sats,
+    pub mismatched_txs: Vec<TxMismatch>,
 }
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub struct UserResponse {
@@ -20,8 +91,107 @@ pub struct UserResponse {
     pub username: Option<String>,
     pub role: UserRole,
 }
+/// Pools of a prediction during one bucket of its history. Pools are the
+/// sats bet on every outcome like in `get_prediction_ratio`.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Eq)]
+pub struct HistoryBucket {
+    pub start: DateTime<Utc>,
+    /// Pools before the first bet or cancellation in the bucket
+    pub open: Vec<Sats>,
+    /// Pools after the last bet or cancellation in the bucket
+    pub close: Vec<Sats>,
+    /// Sats of the bets placed in the bucket
+    pub volume: Sats,
+    pub bets: u32,
+}
+/// Bets, order book positions and market maker shares of a user in one
+/// prediction and what the user got from its settlement
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct PortfolioPosition {
+    pub prediction: RowId,
+    pub name: String,
+    pub state: MarketState,
+    /// Sats the user paid on every outcome for bets, order book positions
+    /// and market maker shares, minus what selling shares paid
+    pub stakes: Vec<Sats>,
+    /// Matched order book contracts on every outcome
+    pub contracts: Vec<i64>,
+    /// Market maker shares held on every outcome
+    pub shares: Vec<Shares>,
+    /// What the holdings would pay out if the outcome wins. Empty once the
+    /// prediction is settled and for scalar predictions.
+    pub projected_payouts: Vec<Sats>,
+    /// Balance change from the settlement against the stakes including
+    /// refunds and fees. Only fees and bonds count before the settlement.
+    pub realized_pnl: Sats,
+    pub refunds: Sats,
+    pub judge_fees: Sats,
+}
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct PortfolioResponse {
+    pub user: UserPubKey,
+    pub balance: Sats,
+    pub available_balance: Sats,
+    pub positions: Vec<PortfolioPosition>,
+    /// Stakes in predictions that aren't settled yet
+    pub open_stake: Sats,
+    pub realized_pnl: Sats,
+    pub refunds: Sats,
+    pub judge_fees: Sats,
+}
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct BettorRank {
+    pub user: UserPubKey,
+    pub username: String,
+    /// Payouts and returned market maker subsidies minus stakes
+    pub profit: Sats,
+    /// Sats staked
+    pub volume: Sats,
+    /// Profit per staked sat
+    pub roi: f64,
+    /// Predictions with a payout
+    pub correct: u32,
+    pub predictions: u32,
+}
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct JudgeRank {
+    pub user: UserPubKey,
+    pub username: String,
+    pub fees: Sats,
+    /// Votes on predictions with outcomes, scalar votes can't be compared
+    pub votes: u32,
+    pub majority_votes: u32,
+    /// Share of majority votes, `None` without votes
+    pub agreement: Option<f64>,
+}
+/// What a position would pay out if its outcome wins and nobody else bets
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct PayoutQuoteResponse {
+    /// Existing bets and order book positions of the user on the outcome plus
+    /// the quoted amount
+    pub stake: Sats,
+    pub payout: Sats,
+    /// Share of the pool bet on the outcome. `None` while nothing is bet.
+    pub implied_probability: Option<f64>,
+    /// Payout per staked sat after the judge share and fees
+    pub odds: Option<f64>,
+}
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub struct DepositResponse {
     pub invoice: Invoice,
     pub id: RowId,
 }
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct OrderBookLevel {
+    pub price: Sats,
+    pub quantity: i64,
+}
+/// Open orders of a yes/no prediction aggregated by price. Prices are for
+/// outcome `0`, the price for outcome `1` is [`CONTRACT_SATS`] minus that.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct OrderBookResponse {
+    /// Highest price first
+    pub bids: Vec<OrderBookLevel>,
+    /// Lowest price first
+    pub asks: Vec<OrderBookLevel>,
+}
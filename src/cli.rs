@@ -31,9 +31,17 @@ enum Commands {
         #[arg(short, long)]
         prediction: String,
         #[arg(short, long)]
+        outcomes: Vec<String>,
+        #[arg(long, requires = "scalar_max")]
+        scalar_min: Option<i64>,
+        #[arg(long, requires = "scalar_min")]
+        scalar_max: Option<i64>,
+        #[arg(short, long)]
         judges: u32,
         #[arg(short, long)]
         share_ppm: u32,
+        #[arg(long, default_value_t = 0)]
+        judge_bond: Sats,
     },
     AcceptNomination {
         #[arg(short, long)]
@@ -53,18 +61,49 @@ enum Commands {
         #[arg(short, long)]
         judge: UserPubKey,
         #[arg(short, long)]
-        decision_true: bool,
+        outcome: Outcome,
+    },
+    MakeScalarDecision {
+        #[arg(short, long)]
+        prediction: RowId,
+        #[arg(short, long)]
+        judge: UserPubKey,
+        #[arg(short, long)]
+        value: i64,
+    },
+    GetPredictions {
+        #[arg(short, long)]
+        search: Option<String>,
+        #[arg(long)]
+        category: Option<String>,
+        #[arg(long)]
+        cursor: Option<String>,
+        #[arg(short, long)]
+        limit: Option<u32>,
+    },
+    GetLeaderboard {
+        #[arg(short, long)]
+        judges: bool,
+        #[arg(short, long)]
+        category: Option<String>,
+        #[arg(short, long)]
+        limit: Option<u32>,
     },
-    GetPredictions,
     GetPrediction {
         #[arg(short, long)]
         prediction: RowId,
         #[arg(short, long)]
         user: Option<String>,
     },
+    GetPredictionHistory {
+        #[arg(short, long)]
+        prediction: RowId,
+        #[arg(short, long, default_value_t = 3600)]
+        bucket_sec: u32,
+    },
     AddBet {
         #[arg(short, long)]
-        bet_true: bool,
+        outcome: Outcome,
         #[arg(short, long)]
         amount: Sats,
         #[arg(short, long)]
@@ -72,10 +111,46 @@ enum Commands {
         #[arg(short, long)]
         user: UserPubKey,
     },
+    QuotePayout {
+        #[arg(short, long)]
+        prediction: RowId,
+        #[arg(short, long)]
+        outcome: Outcome,
+        #[arg(short, long)]
+        amount: Sats,
+        #[arg(short, long)]
+        user: Option<UserPubKey>,
+    },
     CancelBet {
         #[arg(short, long)]
         id: RowId,
     },
+    GetPortfolio {
+        #[arg(short, long)]
+        user: UserPubKey,
+    },
+    CancelPrediction {
+        #[arg(short, long)]
+        prediction: RowId,
+    },
+    HaltPrediction {
+        #[arg(short, long)]
+        prediction: RowId,
+        #[arg(short, long)]
+        reason: String,
+    },
+    ResumePrediction {
+        #[arg(short, long)]
+        prediction: RowId,
+        #[arg(short, long)]
+        extend: bool,
+    },
+    ForceRefund {
+        #[arg(short, long)]
+        prediction: RowId,
+    },
+    CheckBooks,
+    Reconcile,
     GenerateKeys,
     Login,
     SignEcdsa {
@@ -106,8 +181,12 @@ async fn main() -> Result<()> {
     match cli.command {
         Commands::NewPrediction {
             prediction,
+            outcomes,
+            scalar_min,
+            scalar_max,
             judges,
             share_ppm,
+            judge_bond,
         } => {
             let mut new_judges = vec![];
             for i in 0..judges {
@@ -115,13 +194,26 @@ async fn main() -> Result<()> {
             }
             let request = NewPredictionRequest {
                 prediction,
+                outcomes,
+                scalar: scalar_min
+                    .zip(scalar_max)
+                    .map(|(min, max)| ScalarRange { min, max }),
                 judges: new_judges,
                 judge_share_ppm: share_ppm,
                 trading_end: "2023-12-12T12:12:12Z".parse().unwrap(),
                 decision_period_sec: 86400,
                 judge_count: 3,
+                judge_bond,
+                dispute_period_sec: 0,
+                dispute_bond: 0,
+                appeal_judges: vec![],
+                reveal_period_sec: 0,
+                judge_replacement_sec: 0,
+                creator_fee_ppm: 0,
+                metadata: PredictionMetadata::default(),
+                resolution_rules: ResolutionRules::default(),
             };
-            let rowid = client.new_prediction(request).await?;
+            let rowid = client.new_prediction(request, get_access().await?).await?;
             println!("Created new prediction: {}", rowid);
         }
         Commands::AcceptNomination { prediction, judge } => {
@@ -145,49 +237,119 @@ async fn main() -> Result<()> {
         Commands::MakeDecision {
             prediction,
             judge,
-            decision_true,
+            outcome,
         } => {
             let request = MakeDecisionRequest {
                 prediction,
                 judge,
-                decision: decision_true,
+                decision: outcome,
             };
             client.make_decision(request, get_access().await?).await?;
         }
-        Commands::GetPredictions => {
-            let response = client.get_predictions().await?;
+        Commands::MakeScalarDecision {
+            prediction,
+            judge,
+            value,
+        } => {
+            let request = MakeScalarDecisionRequest {
+                prediction,
+                judge,
+                value,
+            };
+            client
+                .make_scalar_decision(request, get_access().await?)
+                .await?;
+        }
+        Commands::GetPredictions {
+            search,
+            category,
+            cursor,
+            limit,
+        } => {
+            let request = PredictionListRequest {
+                search,
+                category,
+                cursor,
+                limit,
+                ..Default::default()
+            };
+            let response = client.get_predictions(request).await?;
             println!("{:#?}", response);
         }
+        Commands::GetLeaderboard {
+            judges,
+            category,
+            limit,
+        } => {
+            if judges {
+                let request = JudgeLeaderboardRequest {
+                    category,
+                    limit,
+                    ..Default::default()
+                };
+                let response = client.get_judge_leaderboard(request).await?;
+                println!("{:#?}", response);
+            } else {
+                let request = BettorLeaderboardRequest {
+                    category,
+                    limit,
+                    ..Default::default()
+                };
+                let response = client.get_bettor_leaderboard(request).await?;
+                println!("{:#?}", response);
+            }
+        }
         Commands::GetPrediction { user, prediction } => {
             let request = PredictionRequest {
-                user: if let Some(user) = user {
-                    Some(UserPubKey::from_str(user.as_str()).unwrap())
-                } else {
-                    None
-                },
+                user: user.map(|user| UserPubKey::from_str(user.as_str()).unwrap()),
                 prediction,
             };
             let response = client.get_prediction_overview(request.clone()).await?;
             println!("{:#?}", response);
             let response = client.get_prediction_ratio(request.clone()).await?;
-            println!("True: {} sats | False {} sats", response.0, response.1);
-            let response = client.get_prediction_judges(request.clone()).await?;
+            for (outcome, amount) in response.iter().enumerate() {
+                println!("Outcome {}: {} sats", outcome, amount);
+            }
+            let response = client
+                .get_prediction_judges(PredictionJudgesRequest {
+                    prediction,
+                    reputation: true,
+                })
+                .await?;
             println!("Judges: {:#?}", response);
             let request = PredictionUserRequest {
                 prediction: Some(request.prediction),
                 user: request.user,
+                page: PageRequest::default(),
             };
-            let response = client.get_bets(request, get_access().await?).await?;
+            let response = client
+                .get_bets(request.clone(), get_access().await?)
+                .await?;
             println!("Bets: {:#?}", response);
+            let response = client.get_ledger(request, get_access().await?).await?;
+            println!("Ledger: {:#?}", response);
+        }
+        Commands::GetPredictionHistory {
+            prediction,
+            bucket_sec,
+        } => {
+            let request = PredictionHistoryRequest {
+                prediction,
+                bucket_sec,
+                from: None,
+                to: None,
+            };
+            let response = client.get_prediction_history(request).await?;
+            println!("{:#?}", response);
         }
         Commands::AddBet {
-            bet_true,
+            outcome,
             amount,
             prediction,
             user,
         } => {
             let request = AddBetRequest {
-                bet: bet_true,
+                outcome,
                 prediction: prediction.into(),
                 user,
                 amount,
@@ -195,9 +357,54 @@ async fn main() -> Result<()> {
             let access = get_access().await?;
             let payment = client.add_bet(request, access.clone()).await?;
         }
+        Commands::QuotePayout {
+            prediction,
+            outcome,
+            amount,
+            user,
+        } => {
+            let request = PayoutQuoteRequest {
+                prediction,
+                outcome,
+                amount,
+                user,
+            };
+            let response = client.quote_payout(request, get_access().await?).await?;
+            println!("{:#?}", response);
+        }
         Commands::CancelBet { id } => {
             client.cancel_bet(id, get_access().await?).await?;
         }
+        Commands::GetPortfolio { user } => {
+            let response = client.get_portfolio(user, get_access().await?).await?;
+            println!("{:#?}", response);
+        }
+        Commands::CancelPrediction { prediction } => {
+            client
+                .cancel_prediction(prediction, get_access().await?)
+                .await?;
+        }
+        Commands::HaltPrediction { prediction, reason } => {
+            let request = HaltPredictionRequest { prediction, reason };
+            client.halt_prediction(request, get_access().await?).await?;
+        }
+        Commands::ResumePrediction { prediction, extend } => {
+            let request = ResumePredictionRequest { prediction, extend };
+            client
+                .resume_prediction(request, get_access().await?)
+                .await?;
+        }
+        Commands::ForceRefund { prediction } => {
+            client.force_refund(prediction, get_access().await?).await?;
+        }
+        Commands::CheckBooks => {
+            let response = client.check_books(get_access().await?).await?;
+            println!("{:#?}", response);
+        }
+        Commands::Reconcile => {
+            let response = client.reconcile(get_access().await?).await?;
+            println!("{:#?}", response);
+        }
         Commands::GenerateKeys => {
             let keys = generate_keypair(&mut rand::thread_rng());
             println!("Pubkey: {}", keys.1);
@@ -211,7 +418,7 @@ async fn main() -> Result<()> {
         Commands::Login => {
             let secret_key = read_secret().await?;
             let user = UserPubKey::from_secret_key_global(&secret_key);
-            let challenge = client.create_login_challenge(user.clone()).await?;
+            let challenge = client.create_login_challenge(user).await?;
             let message = Message::from_hashed_data::<Hash>(challenge.as_bytes());
             let signature = secret_key.sign_ecdsa(message);
             let mut file = File::create("access_token").await?;
@@ -28,13 +28,79 @@ impl Client {
             .await?;
         bail_if_err(response, expexted_code).await
     }
-    async fn get(&self, path: &'static str, expexted_code: StatusCode) -> Result<Response> {
-        let response = self.client.get(self.url.clone() + path).send().await?;
+    async fn get(
+        &self,
+        path: &'static str,
+        query: impl Serialize,
+        expexted_code: StatusCode,
+    ) -> Result<Response> {
+        let response = self
+            .client
+            .get(self.url.clone() + path)
+            .query(&query)
+            .send()
+            .await?;
         bail_if_err(response, expexted_code).await
     }
-    pub async fn new_prediction(&self, request: NewPredictionRequest) -> Result<RowId> {
+    pub async fn new_prediction(
+        &self,
+        request: NewPredictionRequest,
+        access: AccessRequest,
+    ) -> Result<RowId> {
         let response = self
-            .post("/new_prediction", request, StatusCode::CREATED)
+            .post(
+                "/new_prediction",
+                PostRequest {
+                    data: request,
+                    access,
+                },
+                StatusCode::CREATED,
+            )
+            .await?;
+        Ok(response.json::<RowId>().await?)
+    }
+    pub async fn cancel_prediction(&self, prediction: RowId, access: AccessRequest) -> Result<()> {
+        self.post(
+            "/cancel_prediction",
+            PostRequest {
+                data: prediction,
+                access,
+            },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn amend_prediction(
+        &self,
+        request: AmendPredictionRequest,
+        access: AccessRequest,
+    ) -> Result<()> {
+        self.post(
+            "/amend_prediction",
+            PostRequest {
+                data: request,
+                access,
+            },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn new_amm_prediction(
+        &self,
+        request: NewAmmPredictionRequest,
+        access: AccessRequest,
+    ) -> Result<RowId> {
+        let response = self
+            .post(
+                "/new_amm_prediction",
+                PostRequest {
+                    data: request,
+                    access,
+                },
+                StatusCode::CREATED,
+            )
             .await?;
         Ok(response.json::<RowId>().await?)
     }
@@ -86,6 +152,82 @@ impl Client {
         .await?;
         Ok(())
     }
+    pub async fn make_scalar_decision(
+        &self,
+        request: MakeScalarDecisionRequest,
+        access: AccessRequest,
+    ) -> Result<()> {
+        self.post(
+            "/make_scalar_decision",
+            PostRequest {
+                data: request,
+                access,
+            },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn commit_decision(
+        &self,
+        request: CommitDecisionRequest,
+        access: AccessRequest,
+    ) -> Result<()> {
+        self.post(
+            "/commit_decision",
+            PostRequest {
+                data: request,
+                access,
+            },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn reveal_decision(
+        &self,
+        request: RevealDecisionRequest,
+        access: AccessRequest,
+    ) -> Result<()> {
+        self.post(
+            "/reveal_decision",
+            PostRequest {
+                data: request,
+                access,
+            },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn dispute(&self, request: DisputeRequest, access: AccessRequest) -> Result<()> {
+        self.post(
+            "/dispute",
+            PostRequest {
+                data: request,
+                access,
+            },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn make_appeal_decision(
+        &self,
+        request: MakeDecisionRequest,
+        access: AccessRequest,
+    ) -> Result<()> {
+        self.post(
+            "/make_appeal_decision",
+            PostRequest {
+                data: request,
+                access,
+            },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
+    }
     pub async fn add_bet(&self, request: AddBetRequest, access: AccessRequest) -> Result<Payment> {
         let response = self
             .post(
@@ -99,6 +241,184 @@ impl Client {
             .await?;
         Ok(response.text().await?)
     }
+    pub async fn quote_payout(
+        &self,
+        request: PayoutQuoteRequest,
+        access: AccessRequest,
+    ) -> Result<PayoutQuoteResponse> {
+        let response = self
+            .post(
+                "/quote_payout",
+                PostRequest {
+                    data: request,
+                    access,
+                },
+                StatusCode::OK,
+            )
+            .await?;
+        Ok(response.json::<PayoutQuoteResponse>().await?)
+    }
+    pub async fn buy_shares(
+        &self,
+        request: TradeSharesRequest,
+        access: AccessRequest,
+    ) -> Result<Sats> {
+        let response = self
+            .post(
+                "/buy_shares",
+                PostRequest {
+                    data: request,
+                    access,
+                },
+                StatusCode::OK,
+            )
+            .await?;
+        Ok(response.json::<Sats>().await?)
+    }
+    pub async fn sell_shares(
+        &self,
+        request: TradeSharesRequest,
+        access: AccessRequest,
+    ) -> Result<Sats> {
+        let response = self
+            .post(
+                "/sell_shares",
+                PostRequest {
+                    data: request,
+                    access,
+                },
+                StatusCode::OK,
+            )
+            .await?;
+        Ok(response.json::<Sats>().await?)
+    }
+    pub async fn get_share_prices(&self, request: PredictionRequest) -> Result<Vec<f64>> {
+        let response = self
+            .post("/get_share_prices", request, StatusCode::OK)
+            .await?;
+        Ok(response.json::<Vec<f64>>().await?)
+    }
+    pub async fn get_shares(
+        &self,
+        request: PredictionUserRequest,
+        access: AccessRequest,
+    ) -> Result<Vec<ShareHolding>> {
+        let response = self
+            .post(
+                "/get_shares",
+                PostRequest {
+                    data: request,
+                    access,
+                },
+                StatusCode::OK,
+            )
+            .await?;
+        Ok(response.json::<Vec<ShareHolding>>().await?)
+    }
+    pub async fn offer_bet(&self, request: OfferBetRequest, access: AccessRequest) -> Result<()> {
+        self.post(
+            "/offer_bet",
+            PostRequest {
+                data: request,
+                access,
+            },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn buy_bet(&self, request: BuyBetRequest, access: AccessRequest) -> Result<()> {
+        self.post(
+            "/buy_bet",
+            PostRequest {
+                data: request,
+                access,
+            },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn get_bet_transfers(
+        &self,
+        bet: RowId,
+        access: AccessRequest,
+    ) -> Result<Vec<BetTransfer>> {
+        let response = self
+            .post(
+                "/get_bet_transfers",
+                PostRequest { data: bet, access },
+                StatusCode::OK,
+            )
+            .await?;
+        Ok(response.json::<Vec<BetTransfer>>().await?)
+    }
+    pub async fn place_order(
+        &self,
+        request: PlaceOrderRequest,
+        access: AccessRequest,
+    ) -> Result<RowId> {
+        let response = self
+            .post(
+                "/place_order",
+                PostRequest {
+                    data: request,
+                    access,
+                },
+                StatusCode::CREATED,
+            )
+            .await?;
+        Ok(response.json::<RowId>().await?)
+    }
+    pub async fn cancel_order(&self, id: RowId, access: AccessRequest) -> Result<()> {
+        self.post(
+            "/cancel_order",
+            PostRequest { data: id, access },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn get_order_book(&self, request: PredictionRequest) -> Result<OrderBookResponse> {
+        let response = self
+            .post("/get_order_book", request, StatusCode::OK)
+            .await?;
+        Ok(response.json::<OrderBookResponse>().await?)
+    }
+    pub async fn get_orders(
+        &self,
+        request: PredictionUserRequest,
+        access: AccessRequest,
+    ) -> Result<Vec<Order>> {
+        let response = self
+            .post(
+                "/get_orders",
+                PostRequest {
+                    data: request,
+                    access,
+                },
+                StatusCode::OK,
+            )
+            .await?;
+        Ok(response.json::<Vec<Order>>().await?)
+    }
+    pub async fn get_positions(
+        &self,
+        request: PredictionUserRequest,
+        access: AccessRequest,
+    ) -> Result<Vec<Position>> {
+        let response = self
+            .post(
+                "/get_positions",
+                PostRequest {
+                    data: request,
+                    access,
+                },
+                StatusCode::OK,
+            )
+            .await?;
+        Ok(response.json::<Vec<Position>>().await?)
+    }
     pub async fn cancel_bet(&self, id: RowId, access: AccessRequest) -> Result<()> {
         self.post(
             "/cancel_bet",
@@ -124,22 +444,107 @@ impl Client {
         .await?;
         Ok(())
     }
-    pub async fn get_predictions(&self) -> Result<Vec<PredictionOverviewResponse>> {
-        let response = self.get("/get_predictions", StatusCode::OK).await?;
-        Ok(response.json::<Vec<PredictionOverviewResponse>>().await?)
+    pub async fn halt_prediction(
+        &self,
+        request: HaltPredictionRequest,
+        access: AccessRequest,
+    ) -> Result<()> {
+        self.post(
+            "/halt_prediction",
+            PostRequest {
+                data: request,
+                access,
+            },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn resume_prediction(
+        &self,
+        request: ResumePredictionRequest,
+        access: AccessRequest,
+    ) -> Result<()> {
+        self.post(
+            "/resume_prediction",
+            PostRequest {
+                data: request,
+                access,
+            },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
+    }
+    pub async fn force_refund(&self, prediction: RowId, access: AccessRequest) -> Result<()> {
+        self.post(
+            "/force_refund",
+            PostRequest {
+                data: prediction,
+                access,
+            },
+            StatusCode::OK,
+        )
+        .await?;
+        Ok(())
     }
-    pub async fn get_prediction_ratio(&self, request: PredictionRequest) -> Result<(Sats, Sats)> {
+    pub async fn get_predictions(
+        &self,
+        request: PredictionListRequest,
+    ) -> Result<PredictionListResponse> {
+        let response = self
+            .get("/get_predictions", request, StatusCode::OK)
+            .await?;
+        Ok(response.json::<PredictionListResponse>().await?)
+    }
+    pub async fn get_bettor_leaderboard(
+        &self,
+        request: BettorLeaderboardRequest,
+    ) -> Result<Vec<BettorRank>> {
+        let response = self
+            .get("/get_bettor_leaderboard", request, StatusCode::OK)
+            .await?;
+        Ok(response.json::<Vec<BettorRank>>().await?)
+    }
+    pub async fn get_judge_leaderboard(
+        &self,
+        request: JudgeLeaderboardRequest,
+    ) -> Result<Vec<JudgeRank>> {
+        let response = self
+            .get("/get_judge_leaderboard", request, StatusCode::OK)
+            .await?;
+        Ok(response.json::<Vec<JudgeRank>>().await?)
+    }
+    pub async fn get_prediction_ratio(&self, request: PredictionRequest) -> Result<Vec<Sats>> {
         let response = self
             .post("/get_prediction_ratio", request, StatusCode::OK)
             .await?;
-        Ok(response.json::<(Sats, Sats)>().await?)
+        Ok(response.json::<Vec<Sats>>().await?)
     }
-    pub async fn get_prediction_judges(&self, request: PredictionRequest) -> Result<Vec<Judge>> {
+    pub async fn get_prediction_history(
+        &self,
+        request: PredictionHistoryRequest,
+    ) -> Result<Vec<HistoryBucket>> {
+        let response = self
+            .post("/get_prediction_history", request, StatusCode::OK)
+            .await?;
+        Ok(response.json::<Vec<HistoryBucket>>().await?)
+    }
+    pub async fn get_prediction_judges(
+        &self,
+        request: PredictionJudgesRequest,
+    ) -> Result<Vec<Judge>> {
         let response = self
             .post("/get_prediction_judges", request, StatusCode::OK)
             .await?;
         Ok(response.json::<Vec<Judge>>().await?)
     }
+    pub async fn get_judge_reputation(&self, user: UserPubKey) -> Result<JudgeReputation> {
+        let response = self
+            .post("/get_judge_reputation", user, StatusCode::OK)
+            .await?;
+        Ok(response.json::<JudgeReputation>().await?)
+    }
     pub async fn get_prediction_overview(
         &self,
         request: PredictionRequest,
@@ -159,6 +564,14 @@ impl Client {
         let _response = self.post("/try_login", request, StatusCode::OK).await?;
         Ok(())
     }
+    pub async fn check_books(&self, access: AccessRequest) -> Result<BooksResponse> {
+        let response = self.post("/check_books", access, StatusCode::OK).await?;
+        Ok(response.json::<BooksResponse>().await?)
+    }
+    pub async fn reconcile(&self, access: AccessRequest) -> Result<ReconciliationResponse> {
+        let response = self.post("/reconcile", access, StatusCode::OK).await?;
+        Ok(response.json::<ReconciliationResponse>().await?)
+    }
     pub async fn check_login(&self, access: AccessRequest) -> Result<()> {
         self.post("/check_login", access, StatusCode::OK).await?;
         Ok(())
@@ -227,6 +640,23 @@ impl Client {
             .await?;
         Ok(response.json::<Vec<Bet>>().await?)
     }
+    pub async fn get_ledger(
+        &self,
+        request: PredictionUserRequest,
+        access: AccessRequest,
+    ) -> Result<Vec<LedgerEntry>> {
+        let response = self
+            .post(
+                "/get_ledger",
+                PostRequest {
+                    data: request,
+                    access,
+                },
+                StatusCode::OK,
+            )
+            .await?;
+        Ok(response.json::<Vec<LedgerEntry>>().await?)
+    }
     pub async fn get_balance(&self, user: UserPubKey, access: AccessRequest) -> Result<Sats> {
         let response = self
             .post(
@@ -237,6 +667,20 @@ impl Client {
             .await?;
         Ok(response.json::<Sats>().await?)
     }
+    pub async fn get_portfolio(
+        &self,
+        user: UserPubKey,
+        access: AccessRequest,
+    ) -> Result<PortfolioResponse> {
+        let response = self
+            .post(
+                "/get_portfolio",
+                PostRequest { data: user, access },
+                StatusCode::OK,
+            )
+            .await?;
+        Ok(response.json::<PortfolioResponse>().await?)
+    }
     pub async fn get_available_balance(
         &self,
         user: UserPubKey,
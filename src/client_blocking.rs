@@ -143,9 +143,9 @@ impl Client {
         let response = self.get("/get_predictions", StatusCode::OK)?;
         Ok(response.json::<Vec<PredictionOverviewResponse>>()?)
     }
-    pub fn get_prediction_ratio(&self, request: PredictionRequest) -> Result<(Sats, Sats)> {
+    pub fn get_prediction_ratio(&self, request: PredictionRequest) -> Result<Vec<Sats>> {
         let response = self.post("/get_prediction_ratio", request, StatusCode::OK)?;
-        Ok(response.json::<(Sats, Sats)>()?)
+        Ok(response.json::<Vec<Sats>>()?)
     }
     pub fn get_prediction_judges(&self, request: PredictionRequest) -> Result<Vec<Judge>> {
         let response = self.post("/get_prediction_judges", request, StatusCode::OK)?;
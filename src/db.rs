@@ -3,14 +3,51 @@ use crate::mercado::Prediction;
 use anyhow::{bail, Context, Ok, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, Duration, TimeZone, Utc};
+use log::error;
 use secp256k1::ecdsa::Signature;
 use serde_json::json;
-use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteRow};
 use sqlx::types::Json;
-use sqlx::{query, Executor, Pool, Row, SqlitePool};
+use sqlx::{query, Executor, Pool, QueryBuilder, Row, Sqlite, SqlitePool, Transaction};
 use std::collections::HashMap;
 use std::str::FromStr;
 
+/// Columns that tables of databases from before categorical predictions
+/// are missing
+const ADDED_COLUMNS: [(&str, &str); 31] = [
+    ("predictions", "description"),
+    ("predictions", "resolution_source"),
+    ("predictions", "resolution_criteria"),
+    ("predictions", "category"),
+    ("predictions", "tags"),
+    ("predictions", "image_url"),
+    ("predictions", "outcomes"),
+    ("predictions", "scalar_min"),
+    ("predictions", "scalar_max"),
+    ("predictions", "platform_fee_ppm DEFAULT 0"),
+    ("predictions", "creator_fee_ppm DEFAULT 0"),
+    ("predictions", "judge_bond DEFAULT 0"),
+    ("predictions", "dispute_period DEFAULT 0"),
+    ("predictions", "dispute_bond DEFAULT 0"),
+    ("predictions", "reveal_period DEFAULT 0"),
+    ("predictions", "judge_replacement DEFAULT 0"),
+    ("predictions", "resolution_rules"),
+    ("predictions", "decision_extended DEFAULT 0"),
+    ("predictions", "appeal_judges"),
+    ("predictions", "resolution_time"),
+    ("predictions", "terms_changed"),
+    ("predictions", "halted_state"),
+    ("predictions", "halt_reason"),
+    ("predictions", "halted_at"),
+    ("predictions", "creator"),
+    ("predictions", "amm_subsidy"),
+    ("bets", "sale_price"),
+    ("bets", "status"),
+    ("judges", "commitment"),
+    ("judges", "bond DEFAULT 0"),
+    ("judges", "standby_order"),
+];
+
 pub struct DB {
     connection: SqlitePool,
 }
@@ -26,11 +63,37 @@ impl DB {
                 "CREATE TABLE IF NOT EXISTS predictions (\
                 id PRIMARY KEY,\
                 prediction,\
+                description,\
+                resolution_source,\
+                resolution_criteria,\
+                category,\
+                tags,\
+                image_url,\
+                outcomes NOT NULL,\
+                scalar_min,\
+                scalar_max,\
                 judge_share_ppm,\
+                platform_fee_ppm DEFAULT 0,\
+                creator_fee_ppm DEFAULT 0,\
                 state,\
                 trading_end,\
                 decision_period,\
-                judge_count\
+                judge_count,\
+                judge_bond DEFAULT 0,\
+                dispute_period DEFAULT 0,\
+                dispute_bond DEFAULT 0,\
+                reveal_period DEFAULT 0,\
+                judge_replacement DEFAULT 0,\
+                resolution_rules,\
+                decision_extended DEFAULT 0,\
+                appeal_judges,\
+                resolution_time,\
+                terms_changed,\
+                halted_state,\
+                halt_reason,\
+                halted_at,\
+                creator,\
+                amm_subsidy\
                 )",
             )
             .await
@@ -40,8 +103,108 @@ impl DB {
                 "CREATE TABLE IF NOT EXISTS bets (\
                 user NOT NULL,\
                 prediction NOT NULL,\
+                outcome NOT NULL,\
+                amount,\
+                sale_price,\
+                status\
+                )",
+            )
+            .await
+            .unwrap();
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS ledger (\
+                user NOT NULL,\
+                prediction NOT NULL,\
+                bet,\
+                kind NOT NULL,\
+                amount NOT NULL,\
+                time NOT NULL\
+                )",
+            )
+            .await
+            .unwrap();
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS pool_snapshots (\
+                prediction NOT NULL,\
+                time NOT NULL,\
+                outcome NOT NULL,\
+                amount NOT NULL,\
+                pools NOT NULL\
+                )",
+            )
+            .await
+            .unwrap();
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS share_trades (\
+                user NOT NULL,\
+                prediction NOT NULL,\
+                outcome NOT NULL,\
+                shares NOT NULL,\
+                cost NOT NULL\
+                )",
+            )
+            .await
+            .unwrap();
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS disputes (\
+                prediction PRIMARY KEY,\
+                user NOT NULL,\
+                bond NOT NULL,\
+                time NOT NULL\
+                )",
+            )
+            .await
+            .unwrap();
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS appeal_votes (\
+                prediction,\
+                user,\
+                decision NOT NULL,\
+                PRIMARY KEY (prediction,user)\
+                )",
+            )
+            .await
+            .unwrap();
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS bet_transfers (\
                 bet NOT NULL,\
-                amount\
+                seller NOT NULL,\
+                buyer NOT NULL,\
+                price NOT NULL,\
+                time NOT NULL\
+                )",
+            )
+            .await
+            .unwrap();
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS orders (\
+                user NOT NULL,\
+                prediction NOT NULL,\
+                outcome NOT NULL,\
+                side NOT NULL,\
+                price NOT NULL,\
+                quantity NOT NULL,\
+                filled DEFAULT 0,\
+                cancelled DEFAULT FALSE\
+                )",
+            )
+            .await
+            .unwrap();
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS order_fills (\
+                user NOT NULL,\
+                prediction NOT NULL,\
+                outcome NOT NULL,\
+                contracts NOT NULL,\
+                cost NOT NULL\
                 )",
             )
             .await
@@ -53,6 +216,9 @@ impl DB {
                 prediction,\
                 state NOT NULL,\
                 decision,\
+                commitment,\
+                bond DEFAULT 0,\
+                standby_order,\
                 PRIMARY KEY (user,prediction)\
                 )",
             )
@@ -95,7 +261,316 @@ impl DB {
             )
             .await
             .unwrap();
-        Self { connection }
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS journal (\
+                description NOT NULL,\
+                prediction,\
+                time NOT NULL\
+                )",
+            )
+            .await
+            .unwrap();
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS postings (\
+                txn NOT NULL,\
+                account NOT NULL,\
+                amount NOT NULL\
+                )",
+            )
+            .await
+            .unwrap();
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS migrations (\
+                name PRIMARY KEY,\
+                time NOT NULL\
+                )",
+            )
+            .await
+            .unwrap();
+        let db = Self { connection };
+        db.migrate().await.unwrap();
+        db.report_balance_mismatches().await.unwrap();
+        db
+    }
+    /// Brings databases of earlier versions up to the current schema. Every
+    /// migration runs once and gets remembered in the migrations table.
+    async fn migrate(&self) -> Result<()> {
+        if !self.migration_done("boolean_markets").await? {
+            self.migrate_boolean_markets().await?;
+            self.finish_migration("boolean_markets").await?;
+        }
+        if !self.migration_done("opening_balances").await? {
+            self.open_books().await?;
+            self.finish_migration("opening_balances").await?;
+        }
+        if !self.migration_done("escrowed_stakes").await? {
+            self.escrow_open_stakes().await?;
+            self.finish_migration("escrowed_stakes").await?;
+        }
+        if !self.migration_done("closed_books").await? {
+            self.close_books().await?;
+            self.finish_migration("closed_books").await?;
+        }
+        Ok(())
+    }
+    async fn migration_done(&self, name: &str) -> Result<bool> {
+        Ok(self
+            .connection
+            .fetch_optional(query("SELECT name FROM migrations WHERE name = ?").bind(name))
+            .await?
+            .is_some())
+    }
+    async fn finish_migration(&self, name: &str) -> Result<()> {
+        self.connection
+            .execute(
+                query("INSERT INTO migrations (name, time) VALUES (?,?)")
+                    .bind(name)
+                    .bind(Utc::now().timestamp()),
+            )
+            .await?;
+        Ok(())
+    }
+    /// Moves databases from before categorical predictions to outcomes. Bets
+    /// and decisions of `true` become `Yes` and `false` becomes `No`.
+    async fn migrate_boolean_markets(&self) -> Result<()> {
+        if self.has_column("bets", "bet").await? {
+            self.connection
+                .execute("ALTER TABLE bets RENAME COLUMN bet TO outcome")
+                .await?;
+            self.connection
+                .execute("UPDATE bets SET outcome = CASE WHEN outcome THEN 0 ELSE 1 END")
+                .await?;
+        }
+        for (table, column) in ADDED_COLUMNS {
+            let name = column.split_whitespace().next().unwrap();
+            if !self.has_column(table, name).await? {
+                self.connection
+                    .execute(format!("ALTER TABLE {} ADD COLUMN {}", table, column).as_str())
+                    .await?;
+            }
+        }
+        self.connection
+            .execute(
+                query("UPDATE predictions SET outcomes = ? WHERE outcomes IS NULL")
+                    .bind(json!(DEFAULT_OUTCOMES)),
+            )
+            .await?;
+        self.connection
+            .execute(
+                "UPDATE predictions SET state = CASE state \
+                WHEN 'Resolved(true)' THEN 'Resolved(0)' ELSE 'Resolved(1)' END \
+                WHERE state IN ('Resolved(true)', 'Resolved(false)')",
+            )
+            .await?;
+        self.connection
+            .execute(
+                "UPDATE judges SET decision = CASE WHEN decision THEN 0 ELSE 1 END, \
+                state = CASE state WHEN 'Resolved(true)' THEN 'Resolved(0)' ELSE 'Resolved(1)' END \
+                WHERE state IN ('Resolved(true)', 'Resolved(false)')",
+            )
+            .await?;
+        Ok(())
+    }
+    async fn has_column(&self, table: &str, column: &str) -> Result<bool> {
+        Ok(self
+            .connection
+            .fetch_optional(
+                query("SELECT name FROM pragma_table_info(?) WHERE name = ?")
+                    .bind(table)
+                    .bind(column),
+            )
+            .await?
+            .is_some())
+    }
+    /// Books the balances users got before the journal existed as
+    /// adjustments so the journal matches the stored balances. Differences
+    /// that show up later are reported by [`DB::check_books`] instead.
+    async fn open_books(&self) -> Result<()> {
+        let rows = self
+            .connection
+            .fetch_all(query(
+                "SELECT pubkey, balance - \
+                (SELECT COALESCE(SUM(amount), 0) FROM postings \
+                    WHERE account = 'User(' || users.pubkey || ')') AS missing \
+                FROM users WHERE missing != 0",
+            ))
+            .await?;
+        for row in rows {
+            let user = UserPubKey::from_str(row.get("pubkey"))?;
+            let missing: Sats = row.get("missing");
+            let mut tx = self.connection.begin().await?;
+            let txn = tx
+                .execute(
+                    query("INSERT INTO journal (description, time) VALUES (?,?)")
+                        .bind("Opening balance")
+                        .bind(Utc::now().timestamp()),
+                )
+                .await?
+                .last_insert_rowid();
+            for (account, amount) in [
+                (Account::User(user), missing),
+                (Account::Adjustments, -missing),
+            ] {
+                tx.execute(
+                    query("INSERT INTO postings (txn, account, amount) VALUES (?,?,?)")
+                        .bind(txn)
+                        .bind(account.to_string())
+                        .bind(amount),
+                )
+                .await?;
+            }
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+    /// Moves the stakes of bets and order book positions that were placed
+    /// while stakes were only reserved into the escrow of their predictions
+    async fn escrow_open_stakes(&self) -> Result<()> {
+        let rows = self
+            .connection
+            .fetch_all(query(
+                "SELECT bets.user, bets.prediction, bets.amount AS stake, predictions.state \
+                FROM bets JOIN predictions ON predictions.rowid = bets.prediction \
+                UNION ALL \
+                SELECT order_fills.user, order_fills.prediction, order_fills.cost AS stake, \
+                predictions.state \
+                FROM order_fills JOIN predictions ON predictions.rowid = order_fills.prediction",
+            ))
+            .await?;
+        let mut tx = self.connection.begin().await?;
+        for row in rows {
+            if MarketState::from_str(row.get("state"))?.is_settled() {
+                continue;
+            }
+            let user = UserPubKey::from_str(row.get("user"))?;
+            let prediction: RowId = row.get("prediction");
+            let stake: Sats = row.get("stake");
+            post(
+                &mut tx,
+                "Open stake",
+                Some(prediction),
+                &[
+                    (Account::User(user), -stake),
+                    (Account::Escrow(prediction), stake),
+                ],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+    /// Closes orders that stayed open after their prediction stopped trading
+    /// before [`DB::set_prediction_state`] took care of it
+    async fn close_books(&self) -> Result<()> {
+        self.connection
+            .execute(
+                "UPDATE orders SET cancelled = TRUE \
+                WHERE NOT cancelled AND filled < quantity AND prediction IN \
+                (SELECT rowid FROM predictions WHERE state NOT IN ('Trading', 'Stopped'))",
+            )
+            .await?;
+        Ok(())
+    }
+    /// Logs users whose stored balance doesn't match their postings
+    async fn report_balance_mismatches(&self) -> Result<()> {
+        for mismatch in self.check_books().await?.balance_mismatches {
+            error!(
+                "Balance of user {} is {} sats but the journal says {} sats",
+                mismatch.user, mismatch.cached, mismatch.journal
+            );
+        }
+        Ok(())
+    }
+    /// Records a transaction in the journal and updates the stored balances of
+    /// the users in it. Fails if the postings don't add up to zero.
+    pub async fn post_transaction(
+        &self,
+        description: &str,
+        prediction: Option<RowId>,
+        postings: &[(Account, Sats)],
+    ) -> Result<RowId> {
+        let mut tx = self.connection.begin().await?;
+        let txn = post(&mut tx, description, prediction, postings).await?;
+        tx.commit().await?;
+        Ok(txn)
+    }
+    pub async fn get_account_balance(&self, account: Account) -> Result<Sats> {
+        let row = self
+            .connection
+            .fetch_one(
+                query("SELECT COALESCE(SUM(amount), 0) AS balance FROM postings WHERE account = ?")
+                    .bind(account.to_string()),
+            )
+            .await?;
+        Ok(row.get("balance"))
+    }
+    /// Sum of the stored user balances and the sum of all other accounts
+    /// that hold funds on behalf of users
+    pub async fn get_liabilities(&self) -> Result<(Sats, Sats)> {
+        let row = self
+            .connection
+            .fetch_one(
+                query(
+                    "SELECT \
+                (SELECT COALESCE(SUM(balance), 0) FROM users) AS user_balances, \
+                (SELECT COALESCE(SUM(amount), 0) FROM postings \
+                    WHERE account NOT LIKE 'User(%' AND account NOT IN (?, ?)) AS escrow",
+                )
+                .bind(Account::Funding.to_string())
+                .bind(Account::Adjustments.to_string()),
+            )
+            .await?;
+        Ok((row.get("user_balances"), row.get("escrow")))
+    }
+    /// Verifies that every transaction is balanced and that the stored
+    /// balances of the users match their postings
+    pub async fn check_books(&self) -> Result<BooksResponse> {
+        let unbalanced_transactions = self
+            .connection
+            .fetch_all(query(
+                "SELECT txn FROM postings GROUP BY txn HAVING SUM(amount) != 0 ORDER BY txn",
+            ))
+            .await?
+            .into_iter()
+            .map(|row| row.get("txn"))
+            .collect();
+        let mut balance_mismatches = vec![];
+        for row in self
+            .connection
+            .fetch_all(query(
+                "SELECT pubkey, balance, \
+                (SELECT COALESCE(SUM(amount), 0) FROM postings \
+                    WHERE account = 'User(' || users.pubkey || ')') AS journal \
+                FROM users WHERE balance != journal",
+            ))
+            .await?
+        {
+            balance_mismatches.push(BalanceMismatch {
+                user: UserPubKey::from_str(row.get("pubkey"))?,
+                cached: row.get("balance"),
+                journal: row.get("journal"),
+            });
+        }
+        let mut accounts = vec![];
+        for row in self
+            .connection
+            .fetch_all(query(
+                "SELECT account, SUM(amount) AS balance FROM postings \
+                WHERE account NOT LIKE 'User(%' \
+                GROUP BY account HAVING balance != 0 ORDER BY account",
+            ))
+            .await?
+        {
+            accounts.push((Account::from_str(row.get("account"))?, row.get("balance")));
+        }
+        Ok(BooksResponse {
+            unbalanced_transactions,
+            balance_mismatches,
+            accounts,
+        })
     }
     pub async fn add_prediction(&self, prediction: Prediction) -> Result<RowId> {
         let id = self
@@ -104,35 +579,136 @@ impl DB {
                 query(
                     "INSERT INTO predictions (\
                     prediction,\
+                    description,\
+                    resolution_source,\
+                    resolution_criteria,\
+                    category,\
+                    tags,\
+                    image_url,\
+                    outcomes,\
+                    scalar_min,\
+                    scalar_max,\
                     judge_share_ppm,\
+                    platform_fee_ppm,\
+                    creator_fee_ppm,\
                     state,\
                     trading_end,\
                     decision_period,\
-                    judge_count)\
-                    VALUES (?,?,'WaitingForJudges',?,?,?)",
+                    judge_count,\
+                    judge_bond,\
+                    dispute_period,\
+                    dispute_bond,\
+                    reveal_period,\
+                    judge_replacement,\
+                    resolution_rules,\
+                    appeal_judges,\
+                    creator,\
+                    amm_subsidy)\
+                    VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,'WaitingForJudges',?,?,?,?,?,?,?,?,?,?,?,?)",
                 )
                 .bind(prediction.prediction.clone())
+                .bind(prediction.metadata.description.clone())
+                .bind(prediction.metadata.resolution_source.clone())
+                .bind(prediction.metadata.resolution_criteria.clone())
+                .bind(prediction.metadata.category.clone())
+                .bind(json!(prediction.metadata.tags))
+                .bind(prediction.metadata.image_url.clone())
+                .bind(json!(prediction.outcomes))
+                .bind(prediction.scalar.map(|range| range.min))
+                .bind(prediction.scalar.map(|range| range.max))
                 .bind(prediction.judge_share_ppm)
+                .bind(prediction.platform_fee_ppm)
+                .bind(prediction.creator_fee_ppm)
                 .bind(prediction.trading_end.timestamp())
                 .bind(prediction.decision_period.num_seconds())
-                .bind(prediction.judge_count),
+                .bind(prediction.judge_count)
+                .bind(prediction.judge_bond)
+                .bind(prediction.dispute_period.num_seconds())
+                .bind(prediction.dispute_bond)
+                .bind(prediction.reveal_period.num_seconds())
+                .bind(prediction.judge_replacement.num_seconds())
+                .bind(json!(prediction.resolution_rules))
+                .bind(json!(prediction
+                    .appeal_judges
+                    .iter()
+                    .map(|judge| judge.to_string())
+                    .collect::<Vec<_>>()))
+                .bind(prediction.creator.to_string())
+                .bind(prediction.amm_subsidy),
             )
             .await?
             .last_insert_rowid();
         for judge in prediction.judges {
-            let stmt = query(
-                "INSERT INTO judges (\
-                user,\
-                prediction,\
-                state)\
-                VALUES (?,?,'Nominated')",
-            );
-            self.connection
-                .execute(stmt.bind(judge.to_string()).bind(id))
-                .await?;
+            self.add_judge(id, judge).await?;
         }
         Ok(id)
     }
+    pub async fn add_judge(&self, prediction: RowId, judge: UserPubKey) -> Result<()> {
+        let stmt = query(
+            "INSERT INTO judges (\
+            user,\
+            prediction,\
+            state)\
+            VALUES (?,?,'Nominated')",
+        );
+        self.connection
+            .execute(stmt.bind(judge.to_string()).bind(prediction))
+            .await?;
+        Ok(())
+    }
+    pub async fn set_prediction_metadata(
+        &self,
+        prediction: RowId,
+        metadata: &PredictionMetadata,
+    ) -> Result<()> {
+        let stmt = query(
+            "UPDATE predictions SET description = ?, resolution_source = ?, \
+            resolution_criteria = ?, category = ?, tags = ?, image_url = ? WHERE rowid = ?",
+        );
+        self.connection
+            .execute(
+                stmt.bind(metadata.description.clone())
+                    .bind(metadata.resolution_source.clone())
+                    .bind(metadata.resolution_criteria.clone())
+                    .bind(metadata.category.clone())
+                    .bind(json!(metadata.tags))
+                    .bind(metadata.image_url.clone())
+                    .bind(prediction),
+            )
+            .await?;
+        Ok(())
+    }
+    pub async fn set_prediction_text(&self, prediction: RowId, text: &str) -> Result<()> {
+        self.connection
+            .execute(
+                query("UPDATE predictions SET prediction = ? WHERE rowid = ?")
+                    .bind(text)
+                    .bind(prediction),
+            )
+            .await?;
+        Ok(())
+    }
+    /// Records a change of the terms and resets all accepted nominations and
+    /// their bonds
+    pub async fn reset_nominations(&self, prediction: RowId, time: DateTime<Utc>) -> Result<()> {
+        let mut tx = self.connection.begin().await?;
+        tx.execute(
+            query("UPDATE predictions SET terms_changed = ? WHERE rowid = ?")
+                .bind(time.timestamp())
+                .bind(prediction),
+        )
+        .await?;
+        tx.execute(
+            query(
+                "UPDATE judges SET state = 'Nominated', bond = 0 \
+                WHERE prediction = ? AND state = 'Accepted'",
+            )
+            .bind(prediction),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
     pub async fn get_prediction_state(&self, prediction: RowId) -> Result<MarketState> {
         let state = MarketState::from_str(
             self.connection
@@ -143,27 +719,60 @@ impl DB {
         )?;
         Ok(state)
     }
+    /// Open orders get closed once the prediction leaves trading for good.
+    /// Only a halt keeps the order book around for a later resume.
     pub async fn set_prediction_state(&self, prediction: RowId, state: MarketState) -> Result<()> {
-        self.connection
-            .execute(
-                query(
-                    "UPDATE predictions \
+        let mut tx = self.connection.begin().await?;
+        tx.execute(
+            query(
+                "UPDATE predictions \
                 SET state = ? \
                 WHERE rowid = ?",
+            )
+            .bind(state.to_string())
+            .bind(prediction),
+        )
+        .await?;
+        if !matches!(state, MarketState::Trading | MarketState::Stopped) {
+            tx.execute(
+                query(
+                    "UPDATE orders SET cancelled = TRUE \
+                    WHERE prediction = ? AND NOT cancelled AND filled < quantity",
                 )
-                .bind(state.to_string())
                 .bind(prediction),
             )
             .await?;
+        }
+        tx.commit().await?;
         Ok(())
     }
-    pub async fn get_judge_state(&self, prediction: RowId, user: UserPubKey) -> Result<JudgeState> {
-        let state = JudgeState::from_str(
-            self.connection
-                .fetch_one(
-                    query(
-                        "SELECT state FROM judges WHERE \
-                user = ? AND \
+    pub async fn get_predictions_in_state(&self, state: MarketState) -> Result<Vec<RowId>> {
+        let rows = self
+            .connection
+            .fetch_all(
+                query("SELECT rowid FROM predictions WHERE state = ?").bind(state.to_string()),
+            )
+            .await?;
+        Ok(rows.into_iter().map(|row| row.get("rowid")).collect())
+    }
+    /// Predictions in a state that carries a value, like all
+    /// [`MarketState::Disputed`] predictions for the prefix `Disputed(`
+    pub async fn get_predictions_with_state_prefix(&self, prefix: &str) -> Result<Vec<RowId>> {
+        let rows = self
+            .connection
+            .fetch_all(
+                query("SELECT rowid FROM predictions WHERE state LIKE ? || '%'").bind(prefix),
+            )
+            .await?;
+        Ok(rows.into_iter().map(|row| row.get("rowid")).collect())
+    }
+    pub async fn get_judge_state(&self, prediction: RowId, user: UserPubKey) -> Result<JudgeState> {
+        let state = JudgeState::from_str(
+            self.connection
+                .fetch_one(
+                    query(
+                        "SELECT state FROM judges WHERE \
+                user = ? AND \
                 prediction = ?",
                     )
                     .bind(user.to_string())
@@ -207,7 +816,25 @@ impl DB {
                 .bind(prediction),
             )
             .await?;
-        if let JudgeState::Resolved(decision) = state {
+        if state == JudgeState::Standby {
+            self.connection
+                .execute(
+                    query(
+                        "UPDATE judges SET standby_order = \
+                    (SELECT COALESCE(MAX(standby_order), 0) + 1 FROM judges WHERE prediction = ?1) \
+                    WHERE user = ?2 AND prediction = ?1",
+                    )
+                    .bind(prediction)
+                    .bind(user.to_string()),
+                )
+                .await?;
+        }
+        let decision = match state {
+            JudgeState::Resolved(decision) => Some(i64::from(decision)),
+            JudgeState::ResolvedScalar(value) => Some(value),
+            _ => None,
+        };
+        if let Some(decision) = decision {
             self.connection
                 .execute(
                     query(
@@ -229,7 +856,253 @@ impl DB {
             .fetch_one(query("SELECT trading_end FROM predictions WHERE rowid=?").bind(prediction))
             .await?
             .get(0);
-        Ok(Utc.timestamp_opt(trading_end, 0).unwrap().into())
+        Ok(Utc.timestamp_opt(trading_end, 0).unwrap())
+    }
+    pub async fn set_trading_end(
+        &self,
+        prediction: RowId,
+        trading_end: DateTime<Utc>,
+    ) -> Result<()> {
+        self.connection
+            .execute(
+                query("UPDATE predictions SET trading_end = ? WHERE rowid = ?")
+                    .bind(trading_end.timestamp())
+                    .bind(prediction),
+            )
+            .await?;
+        Ok(())
+    }
+    pub async fn set_decision_period(&self, prediction: RowId, period: Duration) -> Result<()> {
+        self.connection
+            .execute(
+                query("UPDATE predictions SET decision_period = ? WHERE rowid = ?")
+                    .bind(period.num_seconds())
+                    .bind(prediction),
+            )
+            .await?;
+        Ok(())
+    }
+    /// Stops the prediction and remembers the state it was in
+    pub async fn halt_prediction(
+        &self,
+        prediction: RowId,
+        reason: &str,
+        time: DateTime<Utc>,
+    ) -> Result<()> {
+        self.connection
+            .execute(
+                query(
+                    "UPDATE predictions SET halted_state = state, state = ?, halt_reason = ?, \
+                    halted_at = ? WHERE rowid = ?",
+                )
+                .bind(MarketState::Stopped.to_string())
+                .bind(reason)
+                .bind(time.timestamp())
+                .bind(prediction),
+            )
+            .await?;
+        Ok(())
+    }
+    /// State before the prediction was stopped, the reason and when it was stopped
+    pub async fn get_halt(
+        &self,
+        prediction: RowId,
+    ) -> Result<Option<(MarketState, String, DateTime<Utc>)>> {
+        let row = self
+            .connection
+            .fetch_one(
+                query(
+                    "SELECT halted_state, halt_reason, halted_at FROM predictions WHERE rowid = ?",
+                )
+                .bind(prediction),
+            )
+            .await?;
+        let state: Option<String> = row.get("halted_state");
+        let Some(state) = state else {
+            return Ok(None);
+        };
+        Ok(Some((
+            MarketState::from_str(state.as_str())?,
+            row.get("halt_reason"),
+            Utc.timestamp_opt(row.get("halted_at"), 0).unwrap(),
+        )))
+    }
+    /// Puts a stopped prediction back into the state it was in before
+    pub async fn resume_prediction(&self, prediction: RowId) -> Result<()> {
+        self.connection
+            .execute(
+                query(
+                    "UPDATE predictions SET state = halted_state, halted_state = NULL, \
+                    halt_reason = NULL, halted_at = NULL WHERE rowid = ? AND halted_state IS NOT NULL",
+                )
+                .bind(prediction),
+            )
+            .await?;
+        Ok(())
+    }
+    pub async fn get_dispute_period(&self, prediction: RowId) -> Result<Duration> {
+        let dispute_period = self
+            .connection
+            .fetch_one(
+                query("SELECT dispute_period FROM predictions WHERE rowid=?").bind(prediction),
+            )
+            .await?
+            .get(0);
+        Ok(Duration::seconds(dispute_period))
+    }
+    pub async fn set_dispute_period(&self, prediction: RowId, period: Duration) -> Result<()> {
+        self.connection
+            .execute(
+                query("UPDATE predictions SET dispute_period = ? WHERE rowid = ?")
+                    .bind(period.num_seconds())
+                    .bind(prediction),
+            )
+            .await?;
+        Ok(())
+    }
+    pub async fn get_dispute_bond(&self, prediction: RowId) -> Result<Sats> {
+        let dispute_bond = self
+            .connection
+            .fetch_one(query("SELECT dispute_bond FROM predictions WHERE rowid=?").bind(prediction))
+            .await?
+            .get(0);
+        Ok(dispute_bond)
+    }
+    pub async fn get_reveal_period(&self, prediction: RowId) -> Result<Duration> {
+        let reveal_period = self
+            .connection
+            .fetch_one(
+                query("SELECT reveal_period FROM predictions WHERE rowid=?").bind(prediction),
+            )
+            .await?
+            .get(0);
+        Ok(Duration::seconds(reveal_period))
+    }
+    pub async fn get_judge_replacement(&self, prediction: RowId) -> Result<Duration> {
+        let judge_replacement = self
+            .connection
+            .fetch_one(
+                query("SELECT judge_replacement FROM predictions WHERE rowid=?").bind(prediction),
+            )
+            .await?
+            .get(0);
+        Ok(Duration::seconds(judge_replacement))
+    }
+    pub async fn get_appeal_judges(&self, prediction: RowId) -> Result<Vec<UserPubKey>> {
+        let judges: Option<Json<Vec<String>>> = self
+            .connection
+            .fetch_one(
+                query("SELECT appeal_judges FROM predictions WHERE rowid=?").bind(prediction),
+            )
+            .await?
+            .get(0);
+        judges
+            .map(|judges| judges.0)
+            .unwrap_or_default()
+            .iter()
+            .map(|judge| Ok(UserPubKey::from_str(judge)?))
+            .collect()
+    }
+    pub async fn set_resolution_time(
+        &self,
+        prediction: RowId,
+        resolution_time: DateTime<Utc>,
+    ) -> Result<()> {
+        self.connection
+            .execute(
+                query("UPDATE predictions SET resolution_time = ? WHERE rowid = ?")
+                    .bind(resolution_time.timestamp())
+                    .bind(prediction),
+            )
+            .await?;
+        Ok(())
+    }
+    pub async fn get_resolution_time(&self, prediction: RowId) -> Result<DateTime<Utc>> {
+        let resolution_time = self
+            .connection
+            .fetch_one(
+                query("SELECT resolution_time FROM predictions WHERE rowid=?").bind(prediction),
+            )
+            .await?
+            .get(0);
+        Ok(Utc.timestamp_opt(resolution_time, 0).unwrap())
+    }
+    pub async fn create_dispute(
+        &self,
+        prediction: RowId,
+        user: UserPubKey,
+        bond: Sats,
+    ) -> Result<()> {
+        self.connection
+            .execute(
+                query("INSERT INTO disputes (prediction, user, bond, time) VALUES (?,?,?,?)")
+                    .bind(prediction)
+                    .bind(user.to_string())
+                    .bind(bond)
+                    .bind(Utc::now().timestamp()),
+            )
+            .await?;
+        Ok(())
+    }
+    /// The user that disputed the decision, the bond they locked and when
+    pub async fn get_dispute(
+        &self,
+        prediction: RowId,
+    ) -> Result<Option<(UserPubKey, Sats, DateTime<Utc>)>> {
+        let row = self
+            .connection
+            .fetch_optional(
+                query("SELECT user, bond, time FROM disputes WHERE prediction = ?")
+                    .bind(prediction),
+            )
+            .await?;
+        Ok(row.map(|row| {
+            (
+                UserPubKey::from_str(row.get("user")).unwrap(),
+                row.get("bond"),
+                Utc.timestamp_opt(row.get("time"), 0).unwrap(),
+            )
+        }))
+    }
+    pub async fn set_appeal_vote(
+        &self,
+        prediction: RowId,
+        user: UserPubKey,
+        decision: Outcome,
+    ) -> Result<()> {
+        self.connection
+            .execute(
+                query(
+                    "INSERT OR REPLACE INTO appeal_votes (prediction, user, decision) \
+                    VALUES (?,?,?)",
+                )
+                .bind(prediction)
+                .bind(user.to_string())
+                .bind(decision),
+            )
+            .await?;
+        Ok(())
+    }
+    pub async fn get_appeal_votes(
+        &self,
+        prediction: RowId,
+    ) -> Result<HashMap<UserPubKey, Outcome>> {
+        let rows = self
+            .connection
+            .fetch_all(
+                query("SELECT user, decision FROM appeal_votes WHERE prediction = ?")
+                    .bind(prediction),
+            )
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    UserPubKey::from_str(row.get("user")).unwrap(),
+                    row.get("decision"),
+                )
+            })
+            .collect())
     }
     pub async fn get_decision_period(&self, prediction: RowId) -> Result<Duration> {
         let decision_period = self
@@ -241,6 +1114,38 @@ impl DB {
             .get(0);
         Ok(Duration::seconds(decision_period))
     }
+    pub async fn get_resolution_rules(&self, prediction: RowId) -> Result<ResolutionRules> {
+        let row = self
+            .connection
+            .fetch_one(
+                query("SELECT resolution_rules FROM predictions WHERE rowid=?").bind(prediction),
+            )
+            .await?;
+        Ok(resolution_rules_from_row(&row))
+    }
+    pub async fn get_decision_extended(&self, prediction: RowId) -> Result<bool> {
+        Ok(self
+            .connection
+            .fetch_one(
+                query("SELECT decision_extended FROM predictions WHERE rowid=?").bind(prediction),
+            )
+            .await?
+            .get(0))
+    }
+    /// Sets the new decision period and remembers that it was extended
+    pub async fn extend_decision_period(&self, prediction: RowId, period: Duration) -> Result<()> {
+        self.connection
+            .execute(
+                query(
+                    "UPDATE predictions SET decision_period = ?, decision_extended = 1 \
+                    WHERE rowid = ?",
+                )
+                .bind(period.num_seconds())
+                .bind(prediction),
+            )
+            .await?;
+        Ok(())
+    }
     pub async fn get_prediction_judges_mapped(
         &self,
         prediction: RowId,
@@ -267,6 +1172,42 @@ impl DB {
         }
         Ok(judges)
     }
+    /// Standby judges of a prediction in the order they accepted
+    pub async fn get_standby_judges(&self, prediction: RowId) -> Result<Vec<UserPubKey>> {
+        let rows = self
+            .connection
+            .fetch_all(
+                query(
+                    "SELECT user FROM judges \
+                    WHERE prediction = ? AND state = 'Standby' \
+                    ORDER BY standby_order",
+                )
+                .bind(prediction),
+            )
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| UserPubKey::from_str(row.get("user")).unwrap())
+            .collect())
+    }
+    /// Accepted judges that haven't voted yet and weren't standbys before
+    pub async fn get_replaceable_judges(&self, prediction: RowId) -> Result<Vec<UserPubKey>> {
+        let rows = self
+            .connection
+            .fetch_all(
+                query(
+                    "SELECT user FROM judges \
+                    WHERE prediction = ? AND state = 'Accepted' AND standby_order IS NULL \
+                    ORDER BY rowid",
+                )
+                .bind(prediction),
+            )
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| UserPubKey::from_str(row.get("user")).unwrap())
+            .collect())
+    }
     pub async fn get_judge_states(&self, prediction: RowId) -> Result<Vec<JudgeState>> {
         Ok(self
             .get_prediction_judges_mapped(prediction)
@@ -275,6 +1216,17 @@ impl DB {
             .cloned()
             .collect())
     }
+    /// Platform and creator fee of a prediction in ppm
+    pub async fn get_fee_ppm(&self, prediction: RowId) -> Result<(u32, u32)> {
+        let row = self
+            .connection
+            .fetch_one(
+                query("SELECT platform_fee_ppm, creator_fee_ppm FROM predictions WHERE rowid=?")
+                    .bind(prediction),
+            )
+            .await?;
+        Ok((row.get("platform_fee_ppm"), row.get("creator_fee_ppm")))
+    }
     pub async fn get_judge_share_ppm(&self, prediction: RowId) -> Result<u32> {
         let judge_share_ppm = self
             .connection
@@ -285,6 +1237,26 @@ impl DB {
             .get(0);
         Ok(judge_share_ppm)
     }
+    pub async fn get_outcomes(&self, prediction: RowId) -> Result<Vec<String>> {
+        let outcomes: Json<Vec<String>> = self
+            .connection
+            .fetch_one(query("SELECT outcomes FROM predictions WHERE rowid=?").bind(prediction))
+            .await
+            .with_context(|| format!("couldn't get outcomes for prediction {}", prediction))?
+            .get(0);
+        Ok(outcomes.0)
+    }
+    pub async fn get_scalar_range(&self, prediction: RowId) -> Result<Option<ScalarRange>> {
+        let row = self
+            .connection
+            .fetch_one(
+                query("SELECT scalar_min, scalar_max FROM predictions WHERE rowid=?")
+                    .bind(prediction),
+            )
+            .await
+            .with_context(|| format!("couldn't get scalar range for prediction {}", prediction))?;
+        Ok(scalar_range_from_row(&row))
+    }
     pub async fn get_judge_count(&self, prediction: RowId) -> Result<u32> {
         let judge_count = self
             .connection
@@ -293,78 +1265,1027 @@ impl DB {
             .get(0);
         Ok(judge_count)
     }
-    pub async fn get_bet(&self, bet: RowId) -> Result<Bet> {
-        let stmt = query(
-            "SELECT user, prediction, bet, amount \
-                FROM bets WHERE rowid = ?",
-        );
-        let row = self.connection.fetch_one(stmt.bind(bet)).await?;
-        let user = UserPubKey::from_str(row.get("user")).unwrap();
-        let prediction = row.get("prediction");
-        let id = bet;
-        let bet = row.get("bet");
-        let amount = row.get("amount");
-        Ok(Bet {
-            id,
-            user,
-            prediction,
-            bet,
-            amount,
-        })
+    pub async fn get_commitment(&self, prediction: RowId, user: UserPubKey) -> Result<String> {
+        let commitment: Option<String> = self
+            .connection
+            .fetch_one(
+                query("SELECT commitment FROM judges WHERE prediction = ? AND user = ?")
+                    .bind(prediction)
+                    .bind(user.to_string()),
+            )
+            .await?
+            .get(0);
+        commitment.ok_or(anyhow::anyhow!("Judge {} has no commitment", user))
+    }
+    pub async fn set_commitment(
+        &self,
+        prediction: RowId,
+        user: UserPubKey,
+        commitment: &str,
+    ) -> Result<()> {
+        let stmt = query("UPDATE judges SET commitment = ? WHERE prediction = ? AND user = ?");
+        self.connection
+            .execute(
+                stmt.bind(commitment)
+                    .bind(prediction)
+                    .bind(user.to_string()),
+            )
+            .await?;
+        Ok(())
+    }
+    pub async fn get_judge_bond(&self, prediction: RowId) -> Result<Sats> {
+        let judge_bond = self
+            .connection
+            .fetch_one(query("SELECT judge_bond FROM predictions WHERE rowid=?").bind(prediction))
+            .await?
+            .get(0);
+        Ok(judge_bond)
+    }
+    pub async fn set_judge_bond(
+        &self,
+        prediction: RowId,
+        user: UserPubKey,
+        bond: Sats,
+    ) -> Result<()> {
+        let stmt = query("UPDATE judges SET bond = ? WHERE prediction = ? AND user = ?");
+        self.connection
+            .execute(stmt.bind(bond).bind(prediction).bind(user.to_string()))
+            .await?;
+        Ok(())
+    }
+    pub async fn get_bet(&self, bet: RowId) -> Result<Bet> {
+        let stmt = query(
+            "SELECT user, prediction, outcome, amount, sale_price, status \
+                FROM bets WHERE rowid = ?",
+        );
+        let row = self.connection.fetch_one(stmt.bind(bet)).await?;
+        let user = UserPubKey::from_str(row.get("user")).unwrap();
+        let prediction = row.get("prediction");
+        let id = bet;
+        let outcome = row.get("outcome");
+        let amount = row.get("amount");
+        let sale_price = row.get("sale_price");
+        Ok(Bet {
+            id,
+            user,
+            prediction,
+            outcome,
+            amount,
+            sale_price,
+            status: bet_status_from_row(&row),
+        })
+    }
+    pub async fn create_bet(
+        &self,
+        prediction: RowId,
+        user: UserPubKey,
+        outcome: Outcome,
+        amount: Sats,
+    ) -> Result<()> {
+        let mut tx = self.connection.begin().await?;
+        let user_reserved = self.get_user_reserved(user).await?;
+        let user_balance = self.get_user_balance(user).await?;
+        if user_reserved + amount > user_balance {
+            tx.rollback().await?;
+            bail!("Not enough funds",)
+        }
+        tx.execute(
+            query(
+                "INSERT INTO bets ( \
+                user, \
+                prediction, \
+                outcome,\
+                amount) \
+                VALUES (?,?,?,?)",
+            )
+            .bind(user.to_string())
+            .bind(prediction)
+            .bind(outcome)
+            .bind(amount),
+        )
+        .await?;
+        post(
+            &mut tx,
+            "Bet",
+            Some(prediction),
+            &[
+                (Account::User(user), -amount),
+                (Account::Escrow(prediction), amount),
+            ],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+    /// Deletes the bet and returns its stake from the escrow
+    pub async fn remove_bet(&self, bet: RowId) -> Result<()> {
+        let mut tx = self.connection.begin().await?;
+        let row = tx
+            .fetch_one(query("SELECT user, prediction, amount FROM bets WHERE rowid = ?").bind(bet))
+            .await?;
+        let user = UserPubKey::from_str(row.get("user"))?;
+        let prediction: RowId = row.get("prediction");
+        let amount: Sats = row.get("amount");
+        tx.execute(query("DELETE FROM bets WHERE rowid = ?").bind(bet))
+            .await?;
+        post(
+            &mut tx,
+            "Bet cancelled",
+            Some(prediction),
+            &[
+                (Account::User(user), amount),
+                (Account::Escrow(prediction), -amount),
+            ],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+    pub async fn set_bets_status(&self, prediction: RowId, status: BetStatus) -> Result<()> {
+        let stmt = query("UPDATE bets SET status = ? WHERE prediction = ?");
+        self.connection
+            .execute(stmt.bind(json!(status)).bind(prediction))
+            .await?;
+        Ok(())
+    }
+    pub async fn add_ledger_entry(
+        &self,
+        user: UserPubKey,
+        prediction: RowId,
+        bet: Option<RowId>,
+        kind: LedgerKind,
+        amount: Sats,
+        time: DateTime<Utc>,
+    ) -> Result<()> {
+        self.connection
+            .execute(
+                query(
+                    "INSERT INTO ledger (user, prediction, bet, kind, amount, time) \
+                    VALUES (?,?,?,?,?,?)",
+                )
+                .bind(user.to_string())
+                .bind(prediction)
+                .bind(bet)
+                .bind(json!(kind))
+                .bind(amount)
+                .bind(time.timestamp()),
+            )
+            .await?;
+        Ok(())
+    }
+    pub async fn get_ledger(
+        &self,
+        prediction: Option<RowId>,
+        user: Option<UserPubKey>,
+        page: PageRequest,
+    ) -> Result<Vec<LedgerEntry>> {
+        let mut stmt = QueryBuilder::<Sqlite>::new(
+            "SELECT rowid, user, prediction, bet, kind, amount, time FROM ledger WHERE TRUE",
+        );
+        if let Some(prediction) = prediction {
+            stmt.push(" AND prediction = ").push_bind(prediction);
+        }
+        if let Some(user) = user {
+            stmt.push(" AND user = ").push_bind(user.to_string());
+        }
+        push_page(&mut stmt, "rowid", page);
+        let rows = stmt.build().fetch_all(&self.connection).await?;
+        let mut entries = Vec::new();
+        for row in rows {
+            let kind: Json<LedgerKind> = row.get("kind");
+            entries.push(LedgerEntry {
+                id: row.get("rowid"),
+                user: UserPubKey::from_str(row.get("user"))?,
+                prediction: row.get("prediction"),
+                bet: row.get("bet"),
+                kind: kind.0,
+                amount: row.get("amount"),
+                time: Utc.timestamp_opt(row.get("time"), 0).unwrap(),
+            });
+        }
+        Ok(entries)
+    }
+    pub async fn set_bet_sale_price(&self, bet: RowId, price: Option<Sats>) -> Result<()> {
+        let stmt = query("UPDATE bets SET sale_price = ? WHERE rowid = ?");
+        self.connection.execute(stmt.bind(price).bind(bet)).await?;
+        Ok(())
+    }
+    /// Moves the bet to the buyer and records the transfer. The stake stays
+    /// in the escrow, so the buyer only pays the price to the seller.
+    pub async fn transfer_bet(&self, bet: RowId, buyer: UserPubKey, price: Sats) -> Result<()> {
+        let mut tx = self.connection.begin().await?;
+        let row = tx
+            .fetch_one(
+                query("SELECT user, prediction, amount, sale_price FROM bets WHERE rowid = ?")
+                    .bind(bet),
+            )
+            .await?;
+        let seller = UserPubKey::from_str(row.get("user"))?;
+        let sale_price: Option<Sats> = row.get("sale_price");
+        if sale_price != Some(price) {
+            tx.rollback().await?;
+            bail!("Bet is not offered for {} sats", price)
+        }
+        let buyer_reserved = self.get_user_reserved(buyer).await?;
+        let buyer_balance = self.get_user_balance(buyer).await?;
+        if buyer_reserved + price > buyer_balance {
+            tx.rollback().await?;
+            bail!("Not enough funds",)
+        }
+        tx.execute(
+            query("UPDATE bets SET user = ?, sale_price = NULL WHERE rowid = ?")
+                .bind(buyer.to_string())
+                .bind(bet),
+        )
+        .await?;
+        let prediction: RowId = row.get("prediction");
+        post(
+            &mut tx,
+            "Bet transfer",
+            Some(prediction),
+            &[
+                (Account::User(buyer), -price),
+                (Account::User(seller), price),
+            ],
+        )
+        .await?;
+        tx.execute(
+            query(
+                "INSERT INTO bet_transfers ( \
+                bet, \
+                seller, \
+                buyer, \
+                price, \
+                time) \
+                VALUES (?,?,?,?,?)",
+            )
+            .bind(bet)
+            .bind(seller.to_string())
+            .bind(buyer.to_string())
+            .bind(price)
+            .bind(Utc::now().timestamp()),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+    pub async fn get_bet_transfers(&self, bet: RowId) -> Result<Vec<BetTransfer>> {
+        let stmt = query(
+            "SELECT bet, seller, buyer, price, time \
+            FROM bet_transfers WHERE bet = ? ORDER BY rowid",
+        );
+        let rows = self.connection.fetch_all(stmt.bind(bet)).await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| BetTransfer {
+                bet: row.get("bet"),
+                seller: UserPubKey::from_str(row.get("seller")).unwrap(),
+                buyer: UserPubKey::from_str(row.get("buyer")).unwrap(),
+                price: row.get("price"),
+                time: Utc.timestamp_opt(row.get("time"), 0).unwrap(),
+            })
+            .collect())
+    }
+    pub async fn get_prediction_bets_aggregated(
+        &self,
+        prediction: RowId,
+        outcome: Outcome,
+    ) -> Result<HashMap<UserPubKey, Sats>> {
+        let mut aggregated_bets = HashMap::new();
+        let bets: Vec<Bet> = self
+            .get_bets(Some(prediction), None, false, PageRequest::default())
+            .await?
+            .into_iter()
+            .filter(|p| p.outcome == outcome)
+            .collect();
+        for bet in bets {
+            if let Some(amount) = aggregated_bets.get_mut(&bet.user) {
+                *amount += bet.amount;
+            } else {
+                aggregated_bets.insert(bet.user, bet.amount);
+            }
+        }
+        Ok(aggregated_bets)
+    }
+    /// Sats of the users balance that are locked in open orders, judge bonds
+    /// and dispute bonds. Stakes of bets and matched positions already left
+    /// the balance for the escrow. Orders stay open while their prediction is
+    /// stopped, so they stay reserved until they get cancelled or closed.
+    pub async fn get_user_reserved(&self, user: UserPubKey) -> Result<Sats> {
+        let stmt = query(
+            "SELECT (quantity - filled) * \
+                CASE side WHEN 'Bid' THEN price ELSE ? - price END AS reserved \
+            FROM orders WHERE user = ? AND NOT cancelled",
+        );
+        let orders: Sats = self
+            .connection
+            .fetch_all(stmt.bind(CONTRACT_SATS).bind(user.to_string()))
+            .await?
+            .into_iter()
+            .map(|row| row.get::<Sats, _>("reserved"))
+            .sum();
+        let stmt = query(
+            "SELECT judges.bond, predictions.state \
+            FROM judges LEFT JOIN predictions ON predictions.rowid = judges.prediction \
+            WHERE judges.user = ?1 AND judges.bond > 0 \
+            UNION ALL \
+            SELECT disputes.bond, predictions.state \
+            FROM disputes LEFT JOIN predictions ON predictions.rowid = disputes.prediction \
+            WHERE disputes.user = ?1",
+        );
+        let mut bonds = 0;
+        for row in self
+            .connection
+            .fetch_all(stmt.bind(user.to_string()))
+            .await?
+        {
+            if !MarketState::from_str(row.get("state"))?.is_settled() {
+                bonds += row.get::<Sats, _>("bond");
+            }
+        }
+        Ok(orders + bonds)
+    }
+    pub async fn get_bets(
+        &self,
+        prediction: Option<RowId>,
+        user: Option<UserPubKey>,
+        exclude_settled: bool,
+        page: PageRequest,
+    ) -> Result<Vec<Bet>> {
+        let mut stmt = QueryBuilder::<Sqlite>::new(
+            "SELECT bets.user, bets.prediction, bets.outcome, bets.amount, bets.sale_price, \
+                bets.status, predictions.state, bets.rowid \
+                FROM bets LEFT JOIN predictions ON predictions.rowid = bets.prediction \
+                WHERE TRUE",
+        );
+        if let Some(prediction) = prediction {
+            stmt.push(" AND bets.prediction = ").push_bind(prediction);
+        }
+        if let Some(user) = user {
+            stmt.push(" AND bets.user = ").push_bind(user.to_string());
+        }
+        push_page(&mut stmt, "bets.rowid", page);
+        let rows = stmt.build().fetch_all(&self.connection).await?;
+        let mut bets = Vec::new();
+        for row in rows {
+            let id = row.get("rowid");
+            let user = UserPubKey::from_str(row.get("user")).unwrap();
+            let prediction = row.get("prediction");
+            let outcome = row.get("outcome");
+            let amount = row.get("amount");
+            let sale_price = row.get("sale_price");
+            let state = MarketState::from_str(row.get("state")).unwrap();
+            if exclude_settled && state.is_settled() {
+                continue;
+            }
+            bets.push(Bet {
+                id,
+                user,
+                prediction,
+                outcome,
+                amount,
+                sale_price,
+                status: bet_status_from_row(&row),
+            });
+        }
+        Ok(bets)
+    }
+    /// One page of the predictions matching `request`. The cursor consists of
+    /// the sort key and the id of the last prediction of the page.
+    pub async fn get_predictions(
+        &self,
+        request: &PredictionListRequest,
+        limit: u32,
+    ) -> Result<PredictionListResponse> {
+        let mut stmt = QueryBuilder::<Sqlite>::new(
+            "SELECT prediction_id, trading_end, volume FROM (\
+            SELECT rowid AS prediction_id, prediction, description, category, creator, state, \
+            trading_end, \
+            (SELECT COALESCE(SUM(amount), 0) FROM bets WHERE prediction = predictions.rowid) + \
+            (SELECT COALESCE(SUM(ABS(cost)), 0) FROM share_trades \
+                WHERE prediction = predictions.rowid) + \
+            (SELECT COALESCE(SUM(cost), 0) FROM order_fills \
+                WHERE prediction = predictions.rowid) AS volume \
+            FROM predictions) WHERE TRUE",
+        );
+        if let Some(state) = request.state.as_ref() {
+            stmt.push(" AND (state = ")
+                .push_bind(state.clone())
+                .push(" OR state LIKE ")
+                .push_bind(format!("{}(%", state))
+                .push(")");
+        }
+        if let Some(category) = request.category.as_ref() {
+            stmt.push(" AND category = ").push_bind(category.clone());
+        }
+        if let Some(judge) = request.judge {
+            stmt.push(" AND prediction_id IN (SELECT prediction FROM judges WHERE user = ")
+                .push_bind(judge.to_string())
+                .push(")");
+        }
+        if let Some(creator) = request.creator {
+            stmt.push(" AND creator = ").push_bind(creator.to_string());
+        }
+        if let Some(after) = request.trading_end_after {
+            stmt.push(" AND trading_end >= ")
+                .push_bind(after.timestamp());
+        }
+        if let Some(before) = request.trading_end_before {
+            stmt.push(" AND trading_end <= ")
+                .push_bind(before.timestamp());
+        }
+        if let Some(search) = request.search.as_ref() {
+            for word in search.split_whitespace() {
+                stmt.push(
+                    " AND instr(lower(prediction || ' ' || COALESCE(description, '')), lower(",
+                )
+                .push_bind(word.to_string())
+                .push(")) > 0");
+            }
+        }
+        let cursor = request
+            .cursor
+            .as_ref()
+            .map(|cursor| -> Result<(i64, RowId)> {
+                let (key, id) = cursor.split_once(':').context("Invalid cursor")?;
+                Ok((
+                    key.parse().context("Invalid cursor")?,
+                    id.parse().context("Invalid cursor")?,
+                ))
+            })
+            .transpose()?;
+        let key = match request.sort {
+            PredictionSort::Newest => "prediction_id",
+            PredictionSort::Volume => "volume",
+            PredictionSort::ClosingSoon => "trading_end",
+        };
+        let (cmp, order) = match request.sort {
+            PredictionSort::Newest | PredictionSort::Volume => ("<", "DESC"),
+            PredictionSort::ClosingSoon => (">", "ASC"),
+        };
+        if let Some((value, id)) = cursor {
+            stmt.push(format!(" AND ({key} {cmp} "))
+                .push_bind(value)
+                .push(format!(" OR ({key} = "))
+                .push_bind(value)
+                .push(format!(" AND prediction_id {cmp} "))
+                .push_bind(id)
+                .push("))");
+        }
+        stmt.push(format!(
+            " ORDER BY {key} {order}, prediction_id {order} LIMIT "
+        ))
+        .push_bind(limit + 1);
+        let rows = stmt.build().fetch_all(&self.connection).await?;
+
+        let mut predictions = Vec::new();
+        let mut next_cursor = None;
+        for (i, row) in rows.iter().enumerate() {
+            let id: RowId = row.get("prediction_id");
+            if i == limit as usize {
+                let last = &rows[i - 1];
+                let value: i64 = match request.sort {
+                    PredictionSort::Newest => last.get("prediction_id"),
+                    PredictionSort::Volume => last.get("volume"),
+                    PredictionSort::ClosingSoon => last.get("trading_end"),
+                };
+                next_cursor = Some(format!(
+                    "{}:{}",
+                    value,
+                    last.get::<RowId, _>("prediction_id")
+                ));
+                break;
+            }
+            predictions.push(self.get_prediction_overview(id).await?);
+        }
+        Ok(PredictionListResponse {
+            predictions,
+            next_cursor,
+        })
+    }
+    pub async fn get_prediction_overview(
+        &self,
+        prediction: RowId,
+    ) -> Result<PredictionOverviewResponse> {
+        let stmt = query(
+            "SELECT rowid, prediction, description, resolution_source, resolution_criteria, \
+            category, tags, image_url, outcomes, scalar_min, scalar_max, judge_share_ppm, \
+            platform_fee_ppm, creator_fee_ppm, judge_count, judge_bond, dispute_period, \
+            dispute_bond, reveal_period, judge_replacement, trading_end, decision_period, state, \
+            resolution_rules, decision_extended, amm_subsidy, creator, halt_reason \
+            FROM predictions WHERE rowid = ?",
+        );
+        let row = self.connection.fetch_one(stmt.bind(prediction)).await?;
+        let ratio = self.get_prediction_ratio(prediction).await?;
+        let outcomes: Json<Vec<String>> = row.get("outcomes");
+        let overview = PredictionOverviewResponse {
+            id: row.get("rowid"),
+            name: row.get("prediction"),
+            creator: creator_from_row(&row),
+            metadata: metadata_from_row(&row),
+            outcomes: outcomes.0,
+            scalar: scalar_range_from_row(&row),
+            judge_share_ppm: row.get("judge_share_ppm"),
+            platform_fee_ppm: row.get("platform_fee_ppm"),
+            creator_fee_ppm: row.get("creator_fee_ppm"),
+            judge_count: row.get("judge_count"),
+            judge_bond: row.get("judge_bond"),
+            dispute_period_sec: row.get("dispute_period"),
+            dispute_bond: row.get("dispute_bond"),
+            reveal_period_sec: row.get("reveal_period"),
+            judge_replacement_sec: row.get("judge_replacement"),
+            trading_end: Utc.timestamp_opt(row.get("trading_end"), 0).unwrap(),
+            decision_period_sec: row.get("decision_period"),
+            resolution_rules: resolution_rules_from_row(&row),
+            decision_extended: row.get("decision_extended"),
+            state: MarketState::from_str(row.get("state")).unwrap(),
+            halt_reason: row.get("halt_reason"),
+            ratio,
+            amm_subsidy: row.get("amm_subsidy"),
+        };
+        Ok(overview)
+    }
+    pub async fn get_prediction_judges(&self, prediction: RowId) -> Result<Vec<Judge>> {
+        let stmt = query(
+            "SELECT user, prediction, state, decision \
+            FROM judges \
+            WHERE prediction = ?",
+        );
+        let rows = self.connection.fetch_all(stmt.bind(prediction)).await?;
+        let judges = rows
+            .into_iter()
+            .map(|row| Judge {
+                user: UserPubKey::from_str(row.get("user")).unwrap(),
+                prediction: row.get("prediction"),
+                state: JudgeState::from_str(row.get("state")).unwrap(),
+                reputation: None,
+            })
+            .collect();
+        Ok(judges)
+    }
+    /// Every nomination of the judge with the state of the prediction
+    pub async fn get_judge_history(
+        &self,
+        user: UserPubKey,
+    ) -> Result<Vec<(RowId, JudgeState, MarketState)>> {
+        let stmt = query(
+            "SELECT judges.prediction, judges.state, predictions.state AS prediction_state \
+            FROM judges LEFT JOIN predictions ON predictions.rowid = judges.prediction \
+            WHERE judges.user = ?",
+        );
+        let rows = self
+            .connection
+            .fetch_all(stmt.bind(user.to_string()))
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                Ok((
+                    row.get("prediction"),
+                    JudgeState::from_str(row.get("state"))?,
+                    MarketState::from_str(row.get("prediction_state"))?,
+                ))
+            })
+            .collect()
+    }
+    /// Sats that went into a prediction through bets, order book matches and
+    /// the market maker
+    pub async fn get_prediction_volume(&self, prediction: RowId) -> Result<Sats> {
+        let stmt = query(
+            "SELECT \
+            (SELECT IFNULL(SUM(amount), 0) FROM bets WHERE prediction = ?1) + \
+            (SELECT IFNULL(SUM(cost), 0) FROM order_fills WHERE prediction = ?1) + \
+            (SELECT IFNULL(SUM(cost), 0) FROM share_trades WHERE prediction = ?1) + \
+            (SELECT IFNULL(amm_subsidy, 0) FROM predictions WHERE rowid = ?1)",
+        );
+        let volume = self
+            .connection
+            .fetch_one(stmt.bind(prediction))
+            .await?
+            .get(0);
+        Ok(volume)
+    }
+    pub async fn get_prediction_ratio(&self, prediction: RowId) -> Result<Vec<Sats>> {
+        let mut ratio = vec![0; self.get_outcomes(prediction).await?.len()];
+        let stmt = query(
+            "SELECT outcome, SUM(amount) AS amount \
+            FROM bets \
+            WHERE prediction = ? \
+            GROUP BY outcome",
+        );
+        let rows = self.connection.fetch_all(stmt.bind(prediction)).await?;
+        for row in rows {
+            let outcome: Outcome = row.get("outcome");
+            if let Some(amount) = ratio.get_mut(outcome as usize) {
+                *amount = row.get("amount");
+            }
+        }
+        for (amount, shares) in ratio
+            .iter_mut()
+            .zip(self.get_share_quantities(prediction).await?)
+        {
+            *amount += shares;
+        }
+        Ok(ratio)
+    }
+    /// Records the pools of the prediction after `amount` sats were bet on
+    /// `outcome`. Cancellations have a negative amount.
+    pub async fn add_pool_snapshot(
+        &self,
+        prediction: RowId,
+        outcome: Outcome,
+        amount: Sats,
+    ) -> Result<()> {
+        let pools = self.get_prediction_ratio(prediction).await?;
+        let stmt = query(
+            "INSERT INTO pool_snapshots (prediction, time, outcome, amount, pools) \
+            VALUES (?,?,?,?,?)",
+        )
+        .bind(prediction)
+        .bind(Utc::now().timestamp())
+        .bind(outcome)
+        .bind(amount)
+        .bind(json!(pools));
+        self.connection.execute(stmt).await?;
+        Ok(())
+    }
+    /// Snapshots of the pools of the prediction ordered by time
+    pub async fn get_pool_snapshots(
+        &self,
+        prediction: RowId,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<(DateTime<Utc>, Outcome, Sats, Vec<Sats>)>> {
+        let mut stmt = QueryBuilder::<Sqlite>::new(
+            "SELECT time, outcome, amount, pools FROM pool_snapshots WHERE prediction = ",
+        );
+        stmt.push_bind(prediction);
+        if let Some(from) = from {
+            stmt.push(" AND time >= ").push_bind(from.timestamp());
+        }
+        if let Some(to) = to {
+            stmt.push(" AND time < ").push_bind(to.timestamp());
+        }
+        stmt.push(" ORDER BY rowid");
+        let rows = stmt.build().fetch_all(&self.connection).await?;
+        let snapshots = rows
+            .into_iter()
+            .map(|row| {
+                let pools: Json<Vec<Sats>> = row.get("pools");
+                (
+                    Utc.timestamp_opt(row.get("time"), 0).unwrap(),
+                    row.get("outcome"),
+                    row.get("amount"),
+                    pools.0,
+                )
+            })
+            .collect();
+        Ok(snapshots)
+    }
+    /// Bettors with a username ranked by their stakes and payouts in
+    /// resolved predictions. Market maker subsidies count as stakes of the
+    /// creator and what is left of them as payout. A prediction counts as
+    /// correct if the user held bets, contracts or shares of the outcome it
+    /// resolved to, which never is the case for scalar predictions.
+    pub async fn get_bettor_leaderboard(
+        &self,
+        request: &BettorLeaderboardRequest,
+        limit: u32,
+    ) -> Result<Vec<BettorRank>> {
+        let mut stmt = QueryBuilder::<Sqlite>::new(
+            "SELECT ledger.user, users.username, \
+            SUM(ledger.amount) AS profit, \
+            -SUM(CASE WHEN ledger.kind = ",
+        );
+        stmt.push_bind(json!(LedgerKind::Stake))
+            .push(
+                " THEN ledger.amount ELSE 0 END) AS volume, \
+                COUNT(DISTINCT CASE WHEN EXISTS (SELECT 1 FROM predictions AS resolved \
+                    WHERE resolved.rowid = ledger.prediction AND resolved.state IN ( \
+                    SELECT 'Resolved(' || outcome || ')' FROM bets \
+                    WHERE user = ledger.user AND prediction = ledger.prediction \
+                    UNION ALL \
+                    SELECT 'Resolved(' || outcome || ')' FROM order_fills \
+                    WHERE user = ledger.user AND prediction = ledger.prediction \
+                    GROUP BY outcome HAVING SUM(contracts) > 0 \
+                    UNION ALL \
+                    SELECT 'Resolved(' || outcome || ')' FROM share_trades \
+                    WHERE user = ledger.user AND prediction = ledger.prediction \
+                    GROUP BY outcome HAVING SUM(shares) > 0)) \
+                THEN ledger.prediction END) AS correct, \
+                COUNT(DISTINCT ledger.prediction) AS predictions \
+                FROM ledger JOIN users ON users.pubkey = ledger.user \
+                WHERE users.username IS NOT NULL AND ledger.kind IN (",
+            )
+            .push_bind(json!(LedgerKind::Stake))
+            .push(", ")
+            .push_bind(json!(LedgerKind::Payout))
+            .push(", ")
+            .push_bind(json!(LedgerKind::Subsidy))
+            .push(") AND ledger.prediction IN (");
+        push_resolved_predictions(&mut stmt, request.from, request.to, &request.category);
+        stmt.push(") GROUP BY ledger.user ORDER BY ");
+        stmt.push(match request.rank_by {
+            BettorRanking::Profit => "profit DESC",
+            BettorRanking::Roi => "CAST(profit AS REAL) / MAX(volume, 1) DESC",
+            BettorRanking::Volume => "volume DESC",
+            BettorRanking::Correct => "correct DESC",
+        });
+        stmt.push(", ledger.user LIMIT ").push_bind(limit);
+        let rows = stmt.build().fetch_all(&self.connection).await?;
+        let mut ranks = vec![];
+        for row in rows {
+            let profit: Sats = row.get("profit");
+            let volume: Sats = row.get("volume");
+            ranks.push(BettorRank {
+                user: UserPubKey::from_str(row.get("user"))?,
+                username: row.get("username"),
+                profit,
+                volume,
+                roi: if volume > 0 {
+                    profit as f64 / volume as f64
+                } else {
+                    0.0
+                },
+                correct: row.get("correct"),
+                predictions: row.get("predictions"),
+            });
+        }
+        Ok(ranks)
+    }
+    /// Judges with a username ranked by their votes and fees in resolved
+    /// predictions
+    pub async fn get_judge_leaderboard(
+        &self,
+        request: &JudgeLeaderboardRequest,
+        limit: u32,
+    ) -> Result<Vec<JudgeRank>> {
+        let mut stmt = QueryBuilder::<Sqlite>::new(
+            "SELECT judges.user, users.username, \
+            (SELECT COALESCE(SUM(amount), 0) FROM ledger \
+                WHERE ledger.user = judges.user AND ledger.kind = ",
+        );
+        stmt.push_bind(json!(LedgerKind::JudgeFee))
+            .push(" AND ledger.prediction IN (");
+        push_resolved_predictions(&mut stmt, request.from, request.to, &request.category);
+        stmt.push(
+            ")) AS fees, \
+            SUM(predictions.state LIKE 'Resolved(%') AS votes, \
+            SUM(predictions.state = 'Resolved(' || judges.decision || ')') AS majority_votes \
+            FROM judges \
+            JOIN users ON users.pubkey = judges.user \
+            JOIN predictions ON predictions.rowid = judges.prediction \
+            WHERE users.username IS NOT NULL \
+            AND (judges.state LIKE 'Resolved(%' OR judges.state LIKE 'ResolvedScalar(%') \
+            AND judges.prediction IN (",
+        );
+        push_resolved_predictions(&mut stmt, request.from, request.to, &request.category);
+        stmt.push(") GROUP BY judges.user ORDER BY ");
+        stmt.push(match request.rank_by {
+            JudgeRanking::Fees => "fees DESC",
+            JudgeRanking::Agreement => {
+                "votes = 0, CAST(majority_votes AS REAL) / MAX(votes, 1) DESC, votes DESC"
+            }
+        });
+        stmt.push(", judges.user LIMIT ").push_bind(limit);
+        let rows = stmt.build().fetch_all(&self.connection).await?;
+        let mut ranks = vec![];
+        for row in rows {
+            let votes: u32 = row.get("votes");
+            let majority_votes: u32 = row.get("majority_votes");
+            ranks.push(JudgeRank {
+                user: UserPubKey::from_str(row.get("user"))?,
+                username: row.get("username"),
+                fees: row.get("fees"),
+                votes,
+                majority_votes,
+                agreement: (votes > 0).then(|| majority_votes as f64 / votes as f64),
+            });
+        }
+        Ok(ranks)
+    }
+    pub async fn get_amm_subsidy(&self, prediction: RowId) -> Result<Option<Sats>> {
+        let subsidy = self
+            .connection
+            .fetch_one(query("SELECT amm_subsidy FROM predictions WHERE rowid=?").bind(prediction))
+            .await?
+            .get(0);
+        Ok(subsidy)
+    }
+    pub async fn get_creator(&self, prediction: RowId) -> Result<Option<UserPubKey>> {
+        let creator: Option<String> = self
+            .connection
+            .fetch_one(query("SELECT creator FROM predictions WHERE rowid=?").bind(prediction))
+            .await?
+            .get(0);
+        Ok(creator.map(|creator| UserPubKey::from_str(creator.as_str()).unwrap()))
+    }
+    /// Records a trade with the market maker and moves `cost` from the users
+    /// balance. Sells have negative `shares` and `cost`.
+    pub async fn create_share_trade(
+        &self,
+        prediction: RowId,
+        user: UserPubKey,
+        outcome: Outcome,
+        shares: Shares,
+        cost: Sats,
+    ) -> Result<()> {
+        let mut tx = self.connection.begin().await?;
+        let user_reserved = self.get_user_reserved(user).await?;
+        let user_balance = self.get_user_balance(user).await?;
+        if cost > 0 && user_reserved + cost > user_balance {
+            tx.rollback().await?;
+            bail!("Not enough funds",)
+        }
+        tx.execute(
+            query(
+                "INSERT INTO share_trades ( \
+                user, \
+                prediction, \
+                outcome,\
+                shares,\
+                cost) \
+                VALUES (?,?,?,?,?)",
+            )
+            .bind(user.to_string())
+            .bind(prediction)
+            .bind(outcome)
+            .bind(shares)
+            .bind(cost),
+        )
+        .await?;
+        post(
+            &mut tx,
+            "Market maker trade",
+            Some(prediction),
+            &[
+                (Account::User(user), -cost),
+                (Account::Escrow(prediction), cost),
+            ],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+    /// Outstanding shares per outcome of a market maker prediction
+    pub async fn get_share_quantities(&self, prediction: RowId) -> Result<Vec<Shares>> {
+        let mut quantities = vec![0; self.get_outcomes(prediction).await?.len()];
+        let stmt = query(
+            "SELECT outcome, SUM(shares) AS shares \
+            FROM share_trades \
+            WHERE prediction = ? \
+            GROUP BY outcome",
+        );
+        let rows = self.connection.fetch_all(stmt.bind(prediction)).await?;
+        for row in rows {
+            let outcome: Outcome = row.get("outcome");
+            if let Some(shares) = quantities.get_mut(outcome as usize) {
+                *shares = row.get("shares");
+            }
+        }
+        Ok(quantities)
+    }
+    pub async fn get_shares(
+        &self,
+        prediction: Option<RowId>,
+        user: Option<UserPubKey>,
+    ) -> Result<Vec<ShareHolding>> {
+        let mut stmt = String::from(
+            "SELECT user, prediction, outcome, SUM(shares) AS shares \
+                FROM share_trades ",
+        );
+        match (prediction, user) {
+            (None, None) => {}
+            (Some(_), None) => stmt += "WHERE prediction = ? ",
+            (None, Some(_)) => stmt += "WHERE user = ? ",
+            (Some(_), Some(_)) => stmt += "WHERE prediction = ? AND user = ? ",
+        }
+        stmt += "GROUP BY user, prediction, outcome HAVING SUM(shares) != 0";
+        let stmt = match (prediction, user) {
+            (None, None) => query(stmt.as_str()),
+            (Some(prediction), None) => query(stmt.as_str()).bind(prediction),
+            (None, Some(user)) => query(stmt.as_str()).bind(user.to_string()),
+            (Some(prediction), Some(user)) => {
+                query(stmt.as_str()).bind(prediction).bind(user.to_string())
+            }
+        };
+        let rows = self.connection.fetch_all(stmt).await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| ShareHolding {
+                user: UserPubKey::from_str(row.get("user")).unwrap(),
+                prediction: row.get("prediction"),
+                outcome: row.get("outcome"),
+                shares: row.get("shares"),
+            })
+            .collect())
+    }
+    /// Net sats every user paid to the market maker of a prediction
+    pub async fn get_prediction_trade_costs(
+        &self,
+        prediction: RowId,
+    ) -> Result<HashMap<UserPubKey, Sats>> {
+        let stmt = query(
+            "SELECT user, SUM(cost) AS cost \
+            FROM share_trades \
+            WHERE prediction = ? \
+            GROUP BY user",
+        );
+        let rows = self.connection.fetch_all(stmt.bind(prediction)).await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    UserPubKey::from_str(row.get("user")).unwrap(),
+                    row.get("cost"),
+                )
+            })
+            .collect())
+    }
+    /// Net shares and sats the user traded with market makers per
+    /// prediction and outcome
+    pub async fn get_user_share_trades(
+        &self,
+        user: UserPubKey,
+    ) -> Result<Vec<(RowId, Outcome, Shares, Sats)>> {
+        let stmt = query(
+            "SELECT prediction, outcome, SUM(shares) AS shares, SUM(cost) AS cost \
+            FROM share_trades \
+            WHERE user = ? \
+            GROUP BY prediction, outcome",
+        );
+        let rows = self
+            .connection
+            .fetch_all(stmt.bind(user.to_string()))
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get("prediction"),
+                    row.get("outcome"),
+                    row.get("shares"),
+                    row.get("cost"),
+                )
+            })
+            .collect())
     }
-    pub async fn create_bet(
-        &self,
-        prediction: RowId,
-        user: UserPubKey,
-        bet: bool,
-        amount: Sats,
-    ) -> Result<()> {
-        let mut tx = self.connection.begin().await?;
-        let user_bets: Sats = self.get_user_bets_aggregated(user).await?.values().sum();
-        let user_balance = self.get_user_balance(user).await?;
-        if user_bets + amount > user_balance {
-            tx.rollback().await?;
-            bail!("Not enough funds",)
-        }
-        tx.execute(
-            query(
-                "INSERT INTO bets ( \
-                user, \
-                prediction, \
-                bet,\
-                amount) \
-                VALUES (?,?,?,?)",
+    pub async fn create_order(&self, request: &PlaceOrderRequest) -> Result<RowId> {
+        let id = self
+            .connection
+            .execute(
+                query(
+                    "INSERT INTO orders ( \
+                    user, \
+                    prediction, \
+                    outcome,\
+                    side,\
+                    price,\
+                    quantity) \
+                    VALUES (?,?,?,?,?,?)",
+                )
+                .bind(request.user.to_string())
+                .bind(request.prediction)
+                .bind(request.outcome)
+                .bind(request.side.to_string())
+                .bind(request.price)
+                .bind(request.quantity),
             )
-            .bind(user.to_string())
-            .bind(prediction)
-            .bind(bet)
-            .bind(amount),
-        )
-        .await?;
-        tx.commit().await?;
-        Ok(())
+            .await?
+            .last_insert_rowid();
+        Ok(id)
     }
-    pub async fn remove_bet(&self, bet: RowId) -> Result<()> {
-        let stmt = query("DELETE FROM bets WHERE rowid = ?");
-        self.connection.execute(stmt.bind(bet)).await?;
-        Ok(())
+    pub async fn get_order(&self, order: RowId) -> Result<Order> {
+        let stmt = query(
+            "SELECT rowid, user, prediction, outcome, side, price, quantity, filled, cancelled \
+            FROM orders WHERE rowid = ?",
+        );
+        let row = self
+            .connection
+            .fetch_one(stmt.bind(order))
+            .await
+            .with_context(|| format!("couldn't get order {}", order))?;
+        order_from_row(&row)
     }
-    pub async fn remove_bets(
+    pub async fn get_orders(
         &self,
         prediction: Option<RowId>,
         user: Option<UserPubKey>,
-    ) -> Result<()> {
-        let stmt = String::from("DELETE FROM bets ");
-        let stmt = match (prediction, user) {
-            (None, None) => stmt,
-            _ => stmt + "WHERE ",
-        };
-        let stmt = match (prediction, user) {
-            (None, None) => stmt,
-            (Some(_), None) => stmt + "bets.prediction = ?",
-            (None, Some(_)) => stmt + "bets.user = ?",
-            (Some(_), Some(_)) => stmt + "bets.prediction = ? AND bets.user = ?",
-        };
+        open_only: bool,
+    ) -> Result<Vec<Order>> {
+        let mut stmt = String::from(
+            "SELECT rowid, user, prediction, outcome, side, price, quantity, filled, cancelled \
+                FROM orders WHERE 1 ",
+        );
+        if prediction.is_some() {
+            stmt += "AND prediction = ? ";
+        }
+        if user.is_some() {
+            stmt += "AND user = ? ";
+        }
+        if open_only {
+            stmt += "AND NOT cancelled AND filled < quantity ";
+        }
+        stmt += "ORDER BY rowid";
         let stmt = match (prediction, user) {
             (None, None) => query(stmt.as_str()),
             (Some(prediction), None) => query(stmt.as_str()).bind(prediction),
@@ -373,204 +2294,108 @@ impl DB {
                 query(stmt.as_str()).bind(prediction).bind(user.to_string())
             }
         };
-        let rows = self.connection.execute(stmt).await?;
+        let rows = self.connection.fetch_all(stmt).await?;
+        rows.iter().map(order_from_row).collect()
+    }
+    pub async fn fill_order(&self, order: RowId, quantity: i64) -> Result<()> {
+        let stmt = query("UPDATE orders SET filled = filled + ? WHERE rowid = ?");
+        self.connection
+            .execute(stmt.bind(quantity).bind(order))
+            .await?;
         Ok(())
     }
-    pub async fn get_prediction_bets_aggregated(
+    pub async fn cancel_order(&self, order: RowId) -> Result<()> {
+        let stmt = query("UPDATE orders SET cancelled = TRUE WHERE rowid = ?");
+        self.connection.execute(stmt.bind(order)).await?;
+        Ok(())
+    }
+    pub async fn create_order_fill(
         &self,
         prediction: RowId,
-        outcome: bool,
-    ) -> Result<HashMap<UserPubKey, Sats>> {
-        let mut aggregated_bets = HashMap::new();
-        let bets: Vec<Bet> = self
-            .get_bets(Some(prediction), None, vec![])
-            .await?
-            .into_iter()
-            .filter(|p| p.bet == outcome)
-            .collect();
-        for bet in bets {
-            if let Some(amount) = aggregated_bets.get_mut(&bet.user) {
-                *amount += bet.amount;
-            } else {
-                aggregated_bets.insert(bet.user, bet.amount);
-            }
-        }
-        Ok(aggregated_bets)
-    }
-    pub async fn get_user_bets_aggregated(&self, user: UserPubKey) -> Result<HashMap<RowId, Sats>> {
-        let mut aggregated_bets = HashMap::new();
-        let exluded_market_states = vec![
-            MarketState::Resolved(true),
-            MarketState::Resolved(false),
-            MarketState::Refunded(RefundReason::Insolvency),
-            MarketState::Refunded(RefundReason::Tie),
-            MarketState::Refunded(RefundReason::TimeForDecisionRanOut),
-        ];
-        let bets: Vec<Bet> = self
-            .get_bets(None, Some(user), exluded_market_states)
-            .await?;
-        for bet in bets {
-            if let Some(amount) = aggregated_bets.get_mut(&bet.prediction) {
-                *amount += bet.amount;
-            } else {
-                aggregated_bets.insert(bet.prediction, bet.amount);
-            }
-        }
-        Ok(aggregated_bets)
+        user: UserPubKey,
+        outcome: Outcome,
+        contracts: i64,
+        cost: Sats,
+    ) -> Result<()> {
+        let mut tx = self.connection.begin().await?;
+        tx.execute(
+            query(
+                "INSERT INTO order_fills ( \
+                    user, \
+                    prediction, \
+                    outcome,\
+                    contracts,\
+                    cost) \
+                    VALUES (?,?,?,?,?)",
+            )
+            .bind(user.to_string())
+            .bind(prediction)
+            .bind(outcome)
+            .bind(contracts)
+            .bind(cost),
+        )
+        .await?;
+        post(
+            &mut tx,
+            "Order fill",
+            Some(prediction),
+            &[
+                (Account::User(user), -cost),
+                (Account::Escrow(prediction), cost),
+            ],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
     }
-    pub async fn get_bets(
+    pub async fn get_positions(
         &self,
         prediction: Option<RowId>,
         user: Option<UserPubKey>,
-        exclude_states: Vec<MarketState>,
-    ) -> Result<Vec<Bet>> {
+    ) -> Result<Vec<Position>> {
         let mut stmt = String::from(
-            "SELECT bets.user, bets.prediction, bets.bet, bets.amount, predictions.state, bets.rowid \
-                FROM bets LEFT JOIN predictions ON predictions.rowid = bets.prediction ",
+            "SELECT user, prediction, outcome, SUM(contracts) AS contracts, SUM(cost) AS cost \
+                FROM order_fills ",
         );
         match (prediction, user) {
             (None, None) => {}
-            (Some(prediction), None) => stmt = stmt + "WHERE bets.prediction = ?",
-            (None, Some(user)) => stmt = stmt + "WHERE bets.user = ?",
-            (Some(prediction), Some(user)) => {
-                stmt = stmt + "WHERE bets.prediction = ? AND bets.user = ?"
-            }
+            (Some(_), None) => stmt += "WHERE prediction = ? ",
+            (None, Some(_)) => stmt += "WHERE user = ? ",
+            (Some(_), Some(_)) => stmt += "WHERE prediction = ? AND user = ? ",
         }
-        let rows = match (prediction, user) {
-            (None, None) => self.connection.fetch_all(query(stmt.as_str())).await?,
-            (Some(prediction), None) => {
-                self.connection
-                    .fetch_all(query(stmt.as_str()).bind(prediction))
-                    .await?
-            }
-            (None, Some(user)) => {
-                self.connection
-                    .fetch_all(query(stmt.as_str()).bind(user.to_string()))
-                    .await?
-            }
+        stmt += "GROUP BY user, prediction, outcome";
+        let stmt = match (prediction, user) {
+            (None, None) => query(stmt.as_str()),
+            (Some(prediction), None) => query(stmt.as_str()).bind(prediction),
+            (None, Some(user)) => query(stmt.as_str()).bind(user.to_string()),
             (Some(prediction), Some(user)) => {
-                self.connection
-                    .fetch_all(query(stmt.as_str()).bind(prediction).bind(user.to_string()))
-                    .await?
+                query(stmt.as_str()).bind(prediction).bind(user.to_string())
             }
         };
-        let mut bets = Vec::new();
-        for row in rows {
-            let id = row.get("rowid");
-            let user = UserPubKey::from_str(row.get("user")).unwrap();
-            let prediction = row.get("prediction");
-            let bet = row.get("bet");
-            let amount = row.get("amount");
-            let state = MarketState::from_str(row.get("state")).unwrap();
-            if exclude_states.contains(&state) {
-                continue;
-            }
-            bets.push(Bet {
-                id,
-                user,
-                prediction,
-                bet,
-                amount,
-            });
-        }
-        Ok(bets)
-    }
-    pub async fn get_predictions(&self) -> Result<HashMap<RowId, PredictionOverviewResponse>> {
-        let stmt = query(
-            "SELECT predictions.rowid, predictions.prediction, judge_share_ppm, judge_count, trading_end, \
-            decision_period, predictions.state, bet, sum(amount) AS amount \
-            FROM predictions \
-            LEFT JOIN bets ON predictions.rowid = bets.prediction \
-            GROUP BY bet, predictions.rowid",
-        );
         let rows = self.connection.fetch_all(stmt).await?;
-
-        let mut predictions: HashMap<RowId, PredictionOverviewResponse> = HashMap::new();
-        for row in rows {
-            let id = row.get("rowid");
-            let name = row.get("prediction");
-            let judge_share_ppm = row.get("judge_share_ppm");
-            let judge_count = row.get("judge_count");
-            let decision_period_sec = row.get("decision_period");
-            let trading_end = Utc.timestamp_opt(row.get("trading_end"), 0).unwrap();
-            let state = MarketState::from_str(row.get("state")).unwrap();
-
-            predictions.insert(
-                id,
-                PredictionOverviewResponse {
-                    id,
-                    name,
-                    judge_share_ppm,
-                    judge_count,
-                    trading_end,
-                    decision_period_sec,
-                    state,
-                    ratio: self.get_prediction_ratio(id).await?,
-                },
-            );
-        }
-        Ok(predictions)
-    }
-    pub async fn get_prediction_overview(
-        &self,
-        prediction: RowId,
-    ) -> Result<PredictionOverviewResponse> {
-        let stmt = query(
-            "SELECT rowid, prediction, judge_share_ppm, judge_count, trading_end, \
-            decision_period, state \
-            FROM predictions WHERE rowid = ?",
-        );
-        let row = self.connection.fetch_one(stmt.bind(prediction)).await?;
-        let ratio = self.get_prediction_ratio(prediction).await?;
-        let overview = PredictionOverviewResponse {
-            id: row.get("rowid"),
-            name: row.get("prediction"),
-            judge_share_ppm: row.get("judge_share_ppm"),
-            judge_count: row.get("judge_count"),
-            trading_end: Utc.timestamp_opt(row.get("trading_end"), 0).unwrap(),
-            decision_period_sec: row.get("decision_period"),
-            state: MarketState::from_str(row.get("state")).unwrap(),
-            ratio,
-        };
-        Ok(overview)
-    }
-    pub async fn get_prediction_judges(&self, prediction: RowId) -> Result<Vec<Judge>> {
-        let stmt = query(
-            "SELECT user, prediction, state, decision \
-            FROM judges \
-            WHERE prediction = ?",
-        );
-        let rows = self.connection.fetch_all(stmt.bind(prediction)).await?;
-        let judges = rows
+        Ok(rows
             .into_iter()
-            .map(|row| Judge {
+            .map(|row| Position {
                 user: UserPubKey::from_str(row.get("user")).unwrap(),
                 prediction: row.get("prediction"),
-                state: JudgeState::from_str(row.get("state")).unwrap(),
+                outcome: row.get("outcome"),
+                contracts: row.get("contracts"),
+                cost: row.get("cost"),
             })
-            .collect();
-        Ok(judges)
+            .collect())
     }
-    pub async fn get_prediction_ratio(&self, prediction: RowId) -> Result<(Sats, Sats)> {
-        let stmt_true = query(
-            "SELECT SUM(amount) AS amount \
-            FROM bets \
-            WHERE prediction = ? AND bet = true",
-        );
-        let stmt_false = query(
-            "SELECT SUM(amount) AS amount \
-            FROM bets \
-            WHERE prediction = ? AND bet = false",
-        );
-        let row_true = self
-            .connection
-            .fetch_one(stmt_true.bind(prediction))
-            .await?;
-        let row_false = self
-            .connection
-            .fetch_one(stmt_false.bind(prediction))
-            .await?;
-        Ok((row_true.get("amount"), row_false.get("amount")))
+    /// Removes all orders and matched positions of a prediction
+    pub async fn remove_orders(&self, prediction: RowId) -> Result<()> {
+        let stmt = query("DELETE FROM orders WHERE prediction = ?");
+        self.connection.execute(stmt.bind(prediction)).await?;
+        let stmt = query("DELETE FROM order_fills WHERE prediction = ?");
+        self.connection.execute(stmt.bind(prediction)).await?;
+        Ok(())
+    }
+    pub async fn remove_share_trades(&self, prediction: RowId) -> Result<()> {
+        let stmt = query("DELETE FROM share_trades WHERE prediction = ?");
+        self.connection.execute(stmt.bind(prediction)).await?;
+        Ok(())
     }
     pub async fn update_user_role(&self, user: UserPubKey, role: UserRole) -> Result<()> {
         self.create_user(user).await?;
@@ -602,17 +2427,6 @@ impl DB {
         let balance: Sats = row.get("balance");
         Ok(balance)
     }
-    pub async fn adjust_user_balance(&self, user: UserPubKey, amount: Sats) -> Result<Sats> {
-        self.create_user(user).await?;
-        let stmt =
-            query("UPDATE users SET balance = balance + ? WHERE pubkey = ? RETURNING balance");
-        let row = self
-            .connection
-            .fetch_one(stmt.bind(amount).bind(user.to_string()))
-            .await?;
-        let balance = row.get("balance");
-        Ok(balance)
-    }
     pub async fn create_session(&self, user: UserPubKey, challenge: String) -> Result<()> {
         self.create_user(user).await?;
         let stmt = query(
@@ -731,12 +2545,17 @@ impl DB {
         prediction: Option<RowId>,
         user: Option<UserPubKey>,
     ) -> Result<Vec<JudgePublic>> {
-        let mut stmt = String::from("SELECT user, prediction FROM judges ");
+        let mut stmt = String::from(
+            "SELECT judges.user, judges.prediction, predictions.terms_changed \
+            FROM judges LEFT JOIN predictions ON predictions.rowid = judges.prediction ",
+        );
         match (prediction, user) {
             (None, None) => {}
-            (Some(prediction), None) => stmt = stmt + "WHERE prediction = ?",
-            (None, Some(user)) => stmt = stmt + "WHERE user = ?",
-            (Some(prediction), Some(user)) => stmt = stmt + "WHERE prediction = ? AND user = ?",
+            (Some(prediction), None) => stmt += "WHERE judges.prediction = ?",
+            (None, Some(user)) => stmt += "WHERE judges.user = ?",
+            (Some(prediction), Some(user)) => {
+                stmt += "WHERE judges.prediction = ? AND judges.user = ?"
+            }
         }
         let rows = match (prediction, user) {
             (None, None) => self.connection.fetch_all(query(stmt.as_str())).await?,
@@ -761,6 +2580,9 @@ impl DB {
             .map(|row| JudgePublic {
                 user: UserPubKey::from_str(row.get("user")).unwrap(),
                 prediction: row.get("prediction"),
+                terms_changed: row
+                    .get::<Option<i64>, _>("terms_changed")
+                    .map(|time| Utc.timestamp_opt(time, 0).unwrap()),
             })
             .collect();
         Ok(judges)
@@ -834,40 +2656,154 @@ impl DB {
         &self,
         user: Option<UserPubKey>,
         direction: Option<TxDirection>,
+        page: PageRequest,
     ) -> Result<Vec<RowId>> {
-        let mut stmt = String::from(
-            "SELECT rowid \
-                FROM payments ",
-        );
-        match (user, direction.clone()) {
-            (None, None) => {}
-            (Some(user), None) => stmt = stmt + "WHERE user = ?",
-            (None, Some(direction)) => stmt = stmt + "WHERE direction = ?",
-            (Some(user), Some(direction)) => stmt = stmt + "WHERE user = ? AND direction = ?",
+        let mut stmt = QueryBuilder::<Sqlite>::new("SELECT rowid FROM payments WHERE TRUE");
+        if let Some(user) = user {
+            stmt.push(" AND user = ").push_bind(json!(user));
         }
-        let rows = match (user, direction) {
-            (None, None) => self.connection.fetch_all(query(stmt.as_str())).await?,
-            (Some(user), None) => {
-                self.connection
-                    .fetch_all(query(stmt.as_str()).bind(json!(user)))
-                    .await?
-            }
-            (None, Some(direction)) => {
-                self.connection
-                    .fetch_all(query(stmt.as_str()).bind(json!(direction)))
-                    .await?
-            }
-            (Some(user), Some(direction)) => {
-                self.connection
-                    .fetch_all(
-                        query(stmt.as_str())
-                            .bind(json!(user))
-                            .bind(json!(direction)),
-                    )
-                    .await?
-            }
-        };
+        if let Some(direction) = direction {
+            stmt.push(" AND direction = ").push_bind(json!(direction));
+        }
+        push_page(&mut stmt, "rowid", page);
+        let rows = stmt.build().fetch_all(&self.connection).await?;
         let txs = rows.into_iter().map(|row| row.get("rowid")).collect();
         Ok(txs)
     }
 }
+/// Inserts a transaction with its postings and updates the stored balances
+/// of the users in it
+async fn post(
+    tx: &mut Transaction<'_, Sqlite>,
+    description: &str,
+    prediction: Option<RowId>,
+    postings: &[(Account, Sats)],
+) -> Result<RowId> {
+    let sum: Sats = postings.iter().map(|(_, amount)| amount).sum();
+    if sum != 0 {
+        bail!("Transaction \"{}\" is off by {} sats", description, sum);
+    }
+    let txn = tx
+        .execute(
+            query("INSERT INTO journal (description, prediction, time) VALUES (?,?,?)")
+                .bind(description)
+                .bind(prediction)
+                .bind(Utc::now().timestamp()),
+        )
+        .await?
+        .last_insert_rowid();
+    for (account, amount) in postings {
+        if let Account::User(user) = account {
+            tx.execute(
+                query("INSERT OR IGNORE INTO users (pubkey) VALUES (?)").bind(user.to_string()),
+            )
+            .await?;
+        }
+        if *amount == 0 {
+            continue;
+        }
+        tx.execute(
+            query("INSERT INTO postings (txn, account, amount) VALUES (?,?,?)")
+                .bind(txn)
+                .bind(account.to_string())
+                .bind(amount),
+        )
+        .await?;
+        if let Account::User(user) = account {
+            tx.execute(
+                query("UPDATE users SET balance = balance + ? WHERE pubkey = ?")
+                    .bind(amount)
+                    .bind(user.to_string()),
+            )
+            .await?;
+        }
+    }
+    Ok(txn)
+}
+/// Selects the ids of resolved predictions with the category that were
+/// settled inside the time window. The settlement is the last thing that
+/// gets booked on a prediction, trades and stakes come before it.
+fn push_resolved_predictions(
+    stmt: &mut QueryBuilder<Sqlite>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    category: &Option<String>,
+) {
+    stmt.push(
+        "SELECT predictions.rowid FROM predictions \
+        JOIN (SELECT prediction, MAX(time) AS time FROM ledger GROUP BY prediction) AS settled \
+        ON settled.prediction = predictions.rowid \
+        WHERE predictions.state LIKE 'Resolved%'",
+    );
+    if let Some(from) = from {
+        stmt.push(" AND settled.time >= ")
+            .push_bind(from.timestamp());
+    }
+    if let Some(to) = to {
+        stmt.push(" AND settled.time < ").push_bind(to.timestamp());
+    }
+    if let Some(category) = category {
+        stmt.push(" AND predictions.category = ")
+            .push_bind(category.clone());
+    }
+}
+/// Restricts the query to the page and orders it by `id_column`
+fn push_page(stmt: &mut QueryBuilder<Sqlite>, id_column: &str, page: PageRequest) {
+    if let Some(cursor) = page.cursor {
+        stmt.push(format!(" AND {id_column} > ")).push_bind(cursor);
+    }
+    stmt.push(format!(" ORDER BY {id_column}"));
+    if let Some(limit) = page.limit {
+        stmt.push(" LIMIT ").push_bind(limit);
+    }
+}
+fn order_from_row(row: &SqliteRow) -> Result<Order> {
+    Ok(Order {
+        id: row.get("rowid"),
+        user: UserPubKey::from_str(row.get("user"))?,
+        prediction: row.get("prediction"),
+        outcome: row.get("outcome"),
+        side: OrderSide::from_str(row.get("side"))?,
+        price: row.get("price"),
+        quantity: row.get("quantity"),
+        filled: row.get("filled"),
+        cancelled: row.get("cancelled"),
+    })
+}
+fn metadata_from_row(row: &SqliteRow) -> PredictionMetadata {
+    let tags: Option<Json<Vec<String>>> = row.get("tags");
+    PredictionMetadata {
+        description: row
+            .get::<Option<String>, _>("description")
+            .unwrap_or_default(),
+        resolution_source: row
+            .get::<Option<String>, _>("resolution_source")
+            .unwrap_or_default(),
+        resolution_criteria: row
+            .get::<Option<String>, _>("resolution_criteria")
+            .unwrap_or_default(),
+        category: row.get("category"),
+        tags: tags.map(|tags| tags.0).unwrap_or_default(),
+        image_url: row.get("image_url"),
+    }
+}
+fn resolution_rules_from_row(row: &SqliteRow) -> ResolutionRules {
+    let rules: Option<Json<ResolutionRules>> = row.get("resolution_rules");
+    rules.map(|rules| rules.0).unwrap_or_default()
+}
+fn bet_status_from_row(row: &SqliteRow) -> BetStatus {
+    let status: Option<Json<BetStatus>> = row.get("status");
+    status.map(|status| status.0).unwrap_or_default()
+}
+fn creator_from_row(row: &SqliteRow) -> Option<UserPubKey> {
+    let creator: Option<String> = row.get("creator");
+    creator.map(|creator| UserPubKey::from_str(creator.as_str()).unwrap())
+}
+fn scalar_range_from_row(row: &SqliteRow) -> Option<ScalarRange> {
+    let min: Option<i64> = row.get("scalar_min");
+    let max: Option<i64> = row.get("scalar_max");
+    Some(ScalarRange {
+        min: min?,
+        max: max?,
+    })
+}
@@ -13,10 +13,13 @@ pub trait FundingSource {
     async fn pay_bolt11(&self, invoice: Invoice, amount: Sats) -> Result<PaymentHash>;
     async fn check_bolt11(&self, hash: PaymentHash) -> Result<TxStateBolt11>;
     async fn decode_bolt11(&self, invoice: Invoice) -> Result<Sats>;
+    /// Amount currently held by the wallet
+    async fn get_balance(&self) -> Result<Sats>;
 }
 #[derive(Debug, Default)]
 pub struct TestFundingSource {
     bolt11: Arc<Mutex<HashMap<PaymentHash, TxStateBolt11>>>,
+    balance: Arc<Mutex<Sats>>,
 }
 #[async_trait]
 impl FundingSource for TestFundingSource {
@@ -29,6 +32,7 @@ impl FundingSource for TestFundingSource {
             .lock()
             .unwrap()
             .insert(hash.clone(), TxStateBolt11::Settled(amount));
+        *self.balance.lock().unwrap() += amount;
         Ok((hash, invoice))
     }
     async fn pay_bolt11(&self, _invoice: Invoice, amount: Sats) -> Result<PaymentHash> {
@@ -38,6 +42,7 @@ impl FundingSource for TestFundingSource {
             .lock()
             .unwrap()
             .insert(hash.clone(), TxStateBolt11::Settled(amount));
+        *self.balance.lock().unwrap() -= amount;
         Ok(hash)
     }
     async fn check_bolt11(&self, hash: PaymentHash) -> Result<TxStateBolt11> {
@@ -51,4 +56,7 @@ impl FundingSource for TestFundingSource {
     async fn decode_bolt11(&self, _invoice: Invoice) -> Result<Sats> {
         Ok(100)
     }
+    async fn get_balance(&self) -> Result<Sats> {
+        Ok(*self.balance.lock().unwrap())
+    }
 }
@@ -78,16 +78,10 @@ impl LnBitsWallet {
             Self::extract_api_key(&text).context("couldn't extract api key from response text")?;
         Ok(api_key)
     }
-    fn extract_api_key(response_text: &String) -> Result<String> {
+    fn extract_api_key(response_text: &str) -> Result<String> {
         let api_key_lines = response_text
             .lines()
-            .filter_map(|line| {
-                if line.contains("Admin key:") {
-                    return Some(line);
-                } else {
-                    None
-                }
-            })
+            .filter(|line| line.contains("Admin key:"))
             .collect::<Vec<&str>>();
         let key_line = api_key_lines
             .first()
@@ -101,16 +95,10 @@ impl LnBitsWallet {
         }
         Ok(api_key)
     }
-    fn extract_wallet_id(response_text: &String) -> Result<String> {
+    fn extract_wallet_id(response_text: &str) -> Result<String> {
         let wallet_id_lines = response_text
             .lines()
-            .filter_map(|line| {
-                if line.contains("Wallet ID:") {
-                    return Some(line);
-                } else {
-                    None
-                }
-            })
+            .filter(|line| line.contains("Wallet ID:"))
             .collect::<Vec<&str>>();
         let id_line = wallet_id_lines
             .first()
@@ -226,6 +214,13 @@ impl LnBitsWallet {
         let json = response.json::<DecodeBolt11Response>().await?;
         Ok(json.amount_msat / 1000)
     }
+    pub async fn get_balance(&self) -> Result<Sats> {
+        let response = self
+            .get("/api/v1/wallet".to_string(), StatusCode::OK)
+            .await?;
+        let json = response.json::<WalletDetailsResponse>().await?;
+        Ok(json.balance / 1000)
+    }
     pub async fn is_reachable(&self) -> Result<()> {
         let response = self
             .get(
@@ -294,6 +289,12 @@ pub struct DecodeBolt11Response {
     description: String,
     payee: String,
 }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletDetailsResponse {
+    id: String,
+    name: String,
+    balance: Sats,
+}
 
 #[cfg(test)]
 mod test {
@@ -339,6 +340,6 @@ mod test {
         let (payment_hash, invoice) = receiver_wallet.create_invoice(100).await.unwrap();
         sender_wallet.pay_invoice(invoice).await.unwrap();
         let paid = receiver_wallet.is_payed(payment_hash).await.unwrap();
-        assert_eq!(paid, true);
+        assert!(paid);
     }
 }
@@ -47,4 +47,7 @@ impl FundingSource for LnbitsFundingSource {
     async fn decode_bolt11(&self, invoice: Invoice) -> Result<Sats> {
         self.wallet.decode_bolt11(invoice).await
     }
+    async fn get_balance(&self) -> Result<Sats> {
+        self.wallet.get_balance().await
+    }
 }
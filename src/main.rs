@@ -4,10 +4,11 @@ use crate::db::DB;
 use crate::funding_source::FundingSource;
 use crate::funding_source::TestFundingSource;
 use crate::lnbits::funding_source::LnbitsFundingSource;
-use crate::mercado::Mercado;
+use crate::mercado::{Mercado, PlatformFee};
 use anyhow::bail;
 use anyhow::Result;
 use axum::extract::Json;
+use axum::extract::Query;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::routing::{get, post};
@@ -38,19 +39,44 @@ mod mercado;
 #[debug_handler]
 async fn new_prediction(
     State(state): State<Arc<RwLock<Mercado>>>,
-    Json(request): Json<NewPredictionRequest>,
+    Json(request): Json<PostRequest<NewPredictionRequest>>,
 ) -> Result<(StatusCode, Json<RowId>), (StatusCode, String)> {
-    let mut backend = state.write().await;
-    let prediction = request;
+    let backend = state.write().await;
     let id = backend
-        .new_prediction(
-            prediction.prediction.clone(),
-            prediction.judges,
-            prediction.judge_count,
-            prediction.judge_share_ppm,
-            prediction.trading_end,
-            Duration::seconds(prediction.decision_period_sec.into()),
-        )
+        .new_prediction(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok((StatusCode::CREATED, id.into()))
+}
+async fn cancel_prediction(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<RowId>>,
+) -> Result<(), (StatusCode, String)> {
+    let backend = state.write().await;
+    backend
+        .cancel_prediction(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
+async fn amend_prediction(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<AmendPredictionRequest>>,
+) -> Result<(), (StatusCode, String)> {
+    let backend = state.write().await;
+    backend
+        .amend_prediction(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
+async fn new_amm_prediction(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<NewAmmPredictionRequest>>,
+) -> Result<(StatusCode, Json<RowId>), (StatusCode, String)> {
+    let backend = state.write().await;
+    let id = backend
+        .new_amm_prediction(request.data, request.access)
         .await
         .map_err(map_any_err_and_code)?;
     Ok((StatusCode::CREATED, id.into()))
@@ -92,17 +118,74 @@ async fn make_decision(
         .map_err(map_any_err_and_code)?;
     Ok(())
 }
+async fn make_scalar_decision(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<MakeScalarDecisionRequest>>,
+) -> Result<(), (StatusCode, String)> {
+    let mut backend = state.write().await;
+    let (request, access) = (request.data, request.access);
+    backend
+        .make_scalar_decision(request.prediction, request.judge, request.value, access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
+async fn commit_decision(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<CommitDecisionRequest>>,
+) -> Result<(), (StatusCode, String)> {
+    let mut backend = state.write().await;
+    backend
+        .commit_decision(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
+async fn reveal_decision(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<RevealDecisionRequest>>,
+) -> Result<(), (StatusCode, String)> {
+    let mut backend = state.write().await;
+    backend
+        .reveal_decision(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
+async fn dispute(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<DisputeRequest>>,
+) -> Result<(), (StatusCode, String)> {
+    let mut backend = state.write().await;
+    backend
+        .dispute(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
+async fn make_appeal_decision(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<MakeDecisionRequest>>,
+) -> Result<(), (StatusCode, String)> {
+    let mut backend = state.write().await;
+    let (request, access) = (request.data, request.access);
+    backend
+        .make_appeal_decision(request.prediction, request.judge, request.decision, access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
 async fn add_bet(
     State(state): State<Arc<RwLock<Mercado>>>,
     Json(request): Json<PostRequest<AddBetRequest>>,
 ) -> Result<StatusCode, (StatusCode, String)> {
     let mut backend = state.write().await;
     let (request, access) = (request.data, request.access);
-    let invoice = backend
+    backend
         .add_bet(
             request.prediction,
             request.user,
-            request.bet,
+            request.outcome,
             request.amount,
             access,
         )
@@ -110,13 +193,156 @@ async fn add_bet(
         .map_err(map_any_err_and_code)?;
     Ok(StatusCode::CREATED)
 }
+async fn quote_payout(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<PayoutQuoteRequest>>,
+) -> Result<Json<PayoutQuoteResponse>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let quote = backend
+        .quote_payout(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(quote))
+}
+async fn buy_shares(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<TradeSharesRequest>>,
+) -> Result<Json<Sats>, (StatusCode, String)> {
+    let mut backend = state.write().await;
+    let cost = backend
+        .buy_shares(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(cost))
+}
+async fn sell_shares(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<TradeSharesRequest>>,
+) -> Result<Json<Sats>, (StatusCode, String)> {
+    let mut backend = state.write().await;
+    let proceeds = backend
+        .sell_shares(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(proceeds))
+}
+async fn get_share_prices(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PredictionRequest>,
+) -> Result<Json<Vec<f64>>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let prices = backend
+        .get_share_prices(request.prediction)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(prices))
+}
+async fn get_shares(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<PredictionUserRequest>>,
+) -> Result<Json<Vec<ShareHolding>>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let shares = backend
+        .get_shares(request.data.prediction, request.data.user, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(shares))
+}
+async fn offer_bet(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<OfferBetRequest>>,
+) -> Result<(), (StatusCode, String)> {
+    let backend = state.write().await;
+    backend
+        .offer_bet(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
+async fn buy_bet(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<BuyBetRequest>>,
+) -> Result<(), (StatusCode, String)> {
+    let mut backend = state.write().await;
+    backend
+        .buy_bet(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
+async fn get_bet_transfers(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<RowId>>,
+) -> Result<Json<Vec<BetTransfer>>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let transfers = backend
+        .get_bet_transfers(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(transfers))
+}
+async fn place_order(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<PlaceOrderRequest>>,
+) -> Result<(StatusCode, Json<RowId>), (StatusCode, String)> {
+    let mut backend = state.write().await;
+    let id = backend
+        .place_order(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok((StatusCode::CREATED, id.into()))
+}
+async fn cancel_order(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<RowId>>,
+) -> Result<(), (StatusCode, String)> {
+    let mut backend = state.write().await;
+    backend
+        .cancel_order(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
+async fn get_order_book(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PredictionRequest>,
+) -> Result<Json<OrderBookResponse>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let order_book = backend
+        .get_order_book(request.prediction)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(order_book))
+}
+async fn get_orders(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<PredictionUserRequest>>,
+) -> Result<Json<Vec<Order>>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let orders = backend
+        .get_orders(request.data.prediction, request.data.user, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(orders))
+}
+async fn get_positions(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<PredictionUserRequest>>,
+) -> Result<Json<Vec<Position>>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let positions = backend
+        .get_positions(request.data.prediction, request.data.user, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(positions))
+}
 async fn cancel_bet(
     State(state): State<Arc<RwLock<Mercado>>>,
     Json(request): Json<PostRequest<RowId>>,
 ) -> Result<(), (StatusCode, String)> {
     let mut backend = state.write().await;
     let (request, access) = (request.data, request.access);
-    let invoice = backend
+    backend
         .cancel_bet(request, access)
         .await
         .map_err(map_any_err_and_code)?;
@@ -124,13 +350,36 @@ async fn cancel_bet(
 }
 async fn get_predictions(
     State(state): State<Arc<RwLock<Mercado>>>,
-) -> Result<Json<Vec<PredictionOverviewResponse>>, (StatusCode, String)> {
-    let mut backend = state.write().await;
+    Query(request): Query<PredictionListRequest>,
+) -> Result<Json<PredictionListResponse>, (StatusCode, String)> {
+    let backend = state.read().await;
     let predictions = backend
-        .get_predictions()
+        .get_predictions(request)
         .await
         .map_err(map_any_err_and_code)?;
-    Ok(Json(predictions.into_values().collect()))
+    Ok(Json(predictions))
+}
+async fn get_bettor_leaderboard(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Query(request): Query<BettorLeaderboardRequest>,
+) -> Result<Json<Vec<BettorRank>>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let leaderboard = backend
+        .get_bettor_leaderboard(request)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(leaderboard))
+}
+async fn get_judge_leaderboard(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Query(request): Query<JudgeLeaderboardRequest>,
+) -> Result<Json<Vec<JudgeRank>>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let leaderboard = backend
+        .get_judge_leaderboard(request)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(leaderboard))
 }
 async fn get_prediction_overview(
     State(state): State<Arc<RwLock<Mercado>>>,
@@ -146,7 +395,7 @@ async fn get_prediction_overview(
 async fn get_prediction_ratio(
     State(state): State<Arc<RwLock<Mercado>>>,
     Json(request): Json<PredictionRequest>,
-) -> Result<Json<(Sats, Sats)>, (StatusCode, String)> {
+) -> Result<Json<Vec<Sats>>, (StatusCode, String)> {
     let mut backend = state.write().await;
     let ratio = backend
         .get_prediction_ratio(request.prediction)
@@ -154,17 +403,39 @@ async fn get_prediction_ratio(
         .map_err(map_any_err_and_code)?;
     Ok(Json(ratio))
 }
+async fn get_prediction_history(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PredictionHistoryRequest>,
+) -> Result<Json<Vec<HistoryBucket>>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let history = backend
+        .get_prediction_history(request)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(history))
+}
 async fn get_prediction_judges(
     State(state): State<Arc<RwLock<Mercado>>>,
-    Json(request): Json<PredictionRequest>,
+    Json(request): Json<PredictionJudgesRequest>,
 ) -> Result<Json<Vec<Judge>>, (StatusCode, String)> {
     let mut backend = state.write().await;
     let judges = backend
-        .get_prediction_judges(request.prediction)
+        .get_prediction_judges(request.prediction, request.reputation)
         .await
         .map_err(map_any_err_and_code)?;
     Ok(Json(judges))
 }
+async fn get_judge_reputation(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<UserPubKey>,
+) -> Result<Json<JudgeReputation>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let reputation = backend
+        .get_judge_reputation(request)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(reputation))
+}
 async fn force_decision_period(
     State(state): State<Arc<RwLock<Mercado>>>,
     Json(request): Json<PostRequest<RowId>>,
@@ -176,6 +447,41 @@ async fn force_decision_period(
         .map_err(map_any_err_and_code)?;
     Ok(())
 }
+async fn halt_prediction(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<HaltPredictionRequest>>,
+) -> Result<(), (StatusCode, String)> {
+    let mut backend = state.write().await;
+    let (request, access) = (request.data, request.access);
+    backend
+        .halt_prediction(request.prediction, request.reason, access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
+async fn resume_prediction(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<ResumePredictionRequest>>,
+) -> Result<(), (StatusCode, String)> {
+    let mut backend = state.write().await;
+    let (request, access) = (request.data, request.access);
+    backend
+        .resume_prediction(request.prediction, request.extend, access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
+async fn force_refund(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<RowId>>,
+) -> Result<(), (StatusCode, String)> {
+    let mut backend = state.write().await;
+    backend
+        .force_refund(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(())
+}
 async fn get_login_challenge(
     State(state): State<Arc<RwLock<Mercado>>>,
     Json(user): Json<UserPubKey>,
@@ -211,6 +517,28 @@ async fn check_login(
         .map_err(|e| (StatusCode::UNAUTHORIZED, map_any_err(e)))?;
     Ok(())
 }
+async fn check_books(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<AccessRequest>,
+) -> Result<Json<BooksResponse>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let books = backend
+        .check_books(request)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(books))
+}
+async fn reconcile(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<AccessRequest>,
+) -> Result<Json<ReconciliationResponse>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let reconciliation = backend
+        .reconcile(request)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(reconciliation))
+}
 async fn update_user(
     State(state): State<Arc<RwLock<Mercado>>>,
     Json(request): Json<PostRequest<UpdateUserRequest>>,
@@ -279,11 +607,32 @@ async fn get_bets(
 ) -> Result<Json<Vec<Bet>>, (StatusCode, String)> {
     let backend = state.read().await;
     let bets = backend
-        .get_bets(request.data.prediction, request.data.user, request.access)
+        .get_bets(
+            request.data.prediction,
+            request.data.user,
+            request.data.page,
+            request.access,
+        )
         .await
         .map_err(map_any_err_and_code)?;
     Ok(Json(bets))
 }
+async fn get_ledger(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<PredictionUserRequest>>,
+) -> Result<Json<Vec<LedgerEntry>>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let entries = backend
+        .get_ledger(
+            request.data.prediction,
+            request.data.user,
+            request.data.page,
+            request.access,
+        )
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(entries))
+}
 async fn get_balance(
     State(state): State<Arc<RwLock<Mercado>>>,
     Json(request): Json<PostRequest<UserPubKey>>,
@@ -295,6 +644,17 @@ async fn get_balance(
         .map_err(map_any_err_and_code)?;
     Ok(Json(balance))
 }
+async fn get_portfolio(
+    State(state): State<Arc<RwLock<Mercado>>>,
+    Json(request): Json<PostRequest<UserPubKey>>,
+) -> Result<Json<PortfolioResponse>, (StatusCode, String)> {
+    let backend = state.read().await;
+    let portfolio = backend
+        .get_portfolio(request.data, request.access)
+        .await
+        .map_err(map_any_err_and_code)?;
+    Ok(Json(portfolio))
+}
 async fn get_available_balance(
     State(state): State<Arc<RwLock<Mercado>>>,
     Json(request): Json<PostRequest<UserPubKey>>,
@@ -361,7 +721,7 @@ async fn get_txs(
     let backend = state.read().await;
     let data = request.data;
     let txs = backend
-        .get_txs(data.user, data.direction, request.access)
+        .get_txs(data.user, data.direction, data.page, request.access)
         .await
         .map_err(map_any_err_and_code)?;
     Ok(Json(txs))
@@ -385,6 +745,9 @@ struct MercadoConfig {
     lnbits: Option<LnbitsConfig>,
     funding_source: String,
     disable_auth: bool,
+    scheduler_interval_sec: u64,
+    platform_fee_ppm: u32,
+    house_account: Option<String>,
 }
 #[derive(Debug, Clone, Deserialize)]
 struct LnbitsConfig {
@@ -401,9 +764,15 @@ impl MercadoConfig {
             .set_default("funding_source", "Lnbits".to_string())?
             .set_default("db", "data.db".to_string())?
             .set_default("disable_auth", false)?
+            .set_default("scheduler_interval_sec", 60)?
+            .set_default("platform_fee_ppm", 0)?
             .add_source(File::with_name(path.as_str()).required(false))
             .build()?;
-        Ok(config.try_deserialize()?)
+        let config: Self = config.try_deserialize()?;
+        if config.scheduler_interval_sec == 0 {
+            bail!("scheduler_interval_sec needs to be at least 1");
+        }
+        Ok(config)
     }
 }
 
@@ -443,38 +812,79 @@ async fn run_server(config: MercadoConfig) -> Result<(u16, JoinHandle<()>)> {
         }
         _ => bail!("Invalid Funding source specified"),
     };
+    let platform_fee = PlatformFee {
+        fee_ppm: config.platform_fee_ppm,
+        house_account: config
+            .house_account
+            .map(|house| UserPubKey::from_str(house.as_str()))
+            .transpose()?,
+    };
     let backend = Mercado::new(
         db,
         funding_source,
         config.admins.clone(),
+        platform_fee,
         config.disable_auth,
     )
     .await
     .unwrap();
     let state = Arc::new(RwLock::new(backend));
+    run_scheduler(state.clone(), config.scheduler_interval_sec);
     let app = Router::new()
         .route("/new_prediction", post(new_prediction))
+        .route("/new_amm_prediction", post(new_amm_prediction))
+        .route("/cancel_prediction", post(cancel_prediction))
+        .route("/amend_prediction", post(amend_prediction))
         .route("/accept_nomination", post(accept_nomination))
         .route("/refuse_nomination", post(refuse_nomination))
         .route("/add_bet", post(add_bet))
+        .route("/quote_payout", post(quote_payout))
+        .route("/buy_shares", post(buy_shares))
+        .route("/sell_shares", post(sell_shares))
+        .route("/get_share_prices", post(get_share_prices))
+        .route("/get_shares", post(get_shares))
+        .route("/offer_bet", post(offer_bet))
+        .route("/buy_bet", post(buy_bet))
+        .route("/get_bet_transfers", post(get_bet_transfers))
+        .route("/place_order", post(place_order))
+        .route("/cancel_order", post(cancel_order))
+        .route("/get_order_book", post(get_order_book))
+        .route("/get_orders", post(get_orders))
+        .route("/get_positions", post(get_positions))
         .route("/make_decision", post(make_decision))
+        .route("/make_scalar_decision", post(make_scalar_decision))
+        .route("/commit_decision", post(commit_decision))
+        .route("/reveal_decision", post(reveal_decision))
+        .route("/dispute", post(dispute))
+        .route("/make_appeal_decision", post(make_appeal_decision))
         .route("/get_predictions", get(get_predictions))
+        .route("/get_bettor_leaderboard", get(get_bettor_leaderboard))
+        .route("/get_judge_leaderboard", get(get_judge_leaderboard))
         .route("/get_prediction_overview", post(get_prediction_overview))
         .route("/get_prediction_ratio", post(get_prediction_ratio))
+        .route("/get_prediction_history", post(get_prediction_history))
         .route("/get_prediction_judges", post(get_prediction_judges))
+        .route("/get_judge_reputation", post(get_judge_reputation))
         .route("/try_login", post(try_login))
         .route("/check_login", post(check_login))
         .route("/get_login_challenge", post(get_login_challenge))
         .route("/update_user", post(update_user))
         .route("/cancel_bet", post(cancel_bet))
         .route("/force_decision_period", post(force_decision_period))
+        .route("/halt_prediction", post(halt_prediction))
+        .route("/resume_prediction", post(resume_prediction))
+        .route("/force_refund", post(force_refund))
+        .route("/check_books", post(check_books))
+        .route("/reconcile", post(reconcile))
         .route("/get_username", post(get_username))
         .route("/get_user", post(get_user))
         .route("/get_judges", post(get_judges))
         .route("/get_judge", post(get_judge))
         .route("/get_bets", post(get_bets))
+        .route("/get_ledger", post(get_ledger))
         .route("/get_balance", post(get_balance))
         .route("/get_available_balance", post(get_available_balance))
+        .route("/get_portfolio", post(get_portfolio))
         .route("/adjust_balance", post(adjust_balance))
         .route("/init_withdrawal_bolt11", post(init_withdrawal_bolt11))
         .route("/init_deposit_bolt11", post(init_deposit_bolt11))
@@ -492,6 +902,21 @@ async fn run_server(config: MercadoConfig) -> Result<(u16, JoinHandle<()>)> {
     Ok((port, handle))
 }
 
+/// Periodically advances the lifecycle of all markets so that time based
+/// state transitions don't depend on users interacting with a market.
+fn run_scheduler(state: Arc<RwLock<Mercado>>, interval_sec: u64) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_sec));
+        loop {
+            interval.tick().await;
+            let mut backend = state.write().await;
+            if let Err(e) = backend.advance_markets().await {
+                warn!("Failed to advance markets: {:#}", e);
+            }
+        }
+    })
+}
+
 #[cfg(test)]
 mod test {
     use secp256k1::{ecdsa::Signature, generate_keypair, rand};
@@ -517,13 +942,32 @@ mod test {
             lnbits: None,
             funding_source: "Test".to_string(),
             disable_auth: true,
+            scheduler_interval_sec: 60,
+            platform_fee_ppm: 0,
+            house_account: None,
         }
     }
 
+    #[test]
+    fn config_rejects_zero_scheduler_interval() {
+        let path = std::env::temp_dir().join(format!(
+            "mercado_zero_interval_{}_{}.json",
+            std::process::id(),
+            Utc::now().timestamp_nanos_opt().unwrap()
+        ));
+        std::fs::write(&path, r#"{"scheduler_interval_sec": 0}"#).unwrap();
+        assert!(MercadoConfig::new(path.to_str().unwrap().to_string()).is_err());
+        std::fs::write(&path, r#"{"scheduler_interval_sec": 5}"#).unwrap();
+        let config = MercadoConfig::new(path.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(config.scheduler_interval_sec, 5);
+        std::fs::remove_file(path).unwrap();
+    }
+
     #[tokio::test]
     async fn new_prediction() {
         let (port, _) = run_server(get_test_config()).await.unwrap();
         let client = Client::new("http://127.0.0.1:".to_string() + port.to_string().as_str());
+        let access = get_test_access();
 
         let (_, j1) = generate_keypair(&mut rand::thread_rng());
         let (_, j2) = generate_keypair(&mut rand::thread_rng());
@@ -541,14 +985,32 @@ mod test {
         // Create a new Prediction
         let prediction_http_request = NewPredictionRequest {
             prediction: "Test prediction".into(),
+            outcomes: vec![],
+            scalar: None,
             judges: vec![j1, j2, j3],
             judge_share_ppm: 100000,
             trading_end: Utc::now() + Duration::days(3),
             decision_period_sec: Duration::days(1).num_seconds().try_into().unwrap(),
             judge_count: 2,
+            judge_bond: 0,
+            dispute_period_sec: 0,
+            dispute_bond: 0,
+            appeal_judges: vec![],
+            reveal_period_sec: 0,
+            judge_replacement_sec: 0,
+            creator_fee_ppm: 0,
+            metadata: PredictionMetadata {
+                description: "Resolves *Yes* if the test passes".into(),
+                resolution_source: "cargo test".into(),
+                resolution_criteria: "All assertions hold".into(),
+                category: Some("Testing".into()),
+                tags: vec!["rust".into(), "ci".into()],
+                image_url: Some("https://example.com/test.png".into()),
+            },
+            resolution_rules: ResolutionRules::default(),
         };
         let prediction_id = client
-            .new_prediction(prediction_http_request.clone())
+            .new_prediction(prediction_http_request.clone(), access.clone())
             .await
             .unwrap();
         prediction_request.prediction = prediction_id;
@@ -562,24 +1024,110 @@ mod test {
             .await
             .unwrap();
         let judges = client
-            .get_prediction_judges(prediction_request)
+            .get_prediction_judges(PredictionJudgesRequest {
+                prediction: prediction_id,
+                reputation: true,
+            })
             .await
             .unwrap();
+        assert!(judges
+            .iter()
+            .all(|judge| judge.reputation.unwrap().nominations == 1));
         assert_eq!(
             prediction,
             PredictionOverviewResponse {
                 id: prediction_id,
                 name: "Test prediction".into(),
+                creator: Some(access.user),
+                metadata: prediction_http_request.metadata.clone(),
+                outcomes: vec!["Yes".into(), "No".into()],
+                scalar: None,
                 state: MarketState::WaitingForJudges,
+                halt_reason: None,
                 judge_share_ppm: 100000,
+                platform_fee_ppm: 0,
+                creator_fee_ppm: 0,
                 judge_count: 2,
+                judge_bond: 0,
+                dispute_period_sec: 0,
+                dispute_bond: 0,
+                reveal_period_sec: 0,
+                judge_replacement_sec: 0,
                 trading_end: Utc
                     .timestamp_opt(prediction_http_request.trading_end.timestamp(), 0)
                     .unwrap(),
                 decision_period_sec: 86400,
+                resolution_rules: ResolutionRules::default(),
+                decision_extended: false,
                 ratio,
+                amm_subsidy: None,
             }
-        )
+        );
+
+        // Changing the terms resets accepted nominations
+        client
+            .accept_nomination(
+                NominationRequest {
+                    prediction: prediction_id,
+                    user: j1,
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        let (_, j4) = generate_keypair(&mut rand::thread_rng());
+        client
+            .amend_prediction(
+                AmendPredictionRequest {
+                    prediction: prediction_id,
+                    text: Some("Amended prediction".into()),
+                    trading_end: None,
+                    new_judges: vec![j4],
+                    metadata: None,
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        let judge = client
+            .get_judge(
+                JudgeRequest {
+                    prediction: prediction_id,
+                    user: j1,
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(judge.state, JudgeState::Nominated);
+        let nominations = client
+            .get_judges(PredictionUserRequest {
+                prediction: None,
+                user: Some(j4),
+                page: PageRequest::default(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(nominations.len(), 1);
+        assert!(nominations[0].terms_changed.is_some());
+        let prediction = client
+            .get_prediction_overview(prediction_request.clone())
+            .await
+            .unwrap();
+        assert_eq!(prediction.name, "Amended prediction");
+
+        client
+            .cancel_prediction(prediction_id, access.clone())
+            .await
+            .unwrap();
+        let prediction = client
+            .get_prediction_overview(prediction_request)
+            .await
+            .unwrap();
+        assert_eq!(
+            prediction.state,
+            MarketState::Refunded(RefundReason::Cancelled)
+        );
     }
 
     #[tokio::test]
@@ -602,20 +1150,34 @@ mod test {
         // Create a new Prediction
         let prediction = NewPredictionRequest {
             prediction: "Test prediction".into(),
+            outcomes: vec![],
+            scalar: None,
             judges: vec![j1, j2, j3],
             judge_share_ppm: 100000,
             trading_end: Utc::now() + Duration::days(3),
             decision_period_sec: Duration::days(1).num_seconds().try_into().unwrap(),
             judge_count: 2,
+            judge_bond: 0,
+            dispute_period_sec: 0,
+            dispute_bond: 0,
+            appeal_judges: vec![],
+            reveal_period_sec: 0,
+            judge_replacement_sec: 0,
+            creator_fee_ppm: 0,
+            metadata: PredictionMetadata::default(),
+            resolution_rules: ResolutionRules::default(),
         };
-        let prediction_id = client.new_prediction(prediction).await.unwrap();
+        let prediction_id = client
+            .new_prediction(prediction, access.clone())
+            .await
+            .unwrap();
 
         // Refuse Nomination for 1 judge
         let request = NominationRequest {
             prediction: prediction_id,
             user: j3,
         };
-        let response = client
+        client
             .refuse_nomination(request, access.clone())
             .await
             .unwrap();
@@ -626,7 +1188,7 @@ mod test {
                 prediction: prediction_id,
                 user: judge,
             };
-            let response = client
+            client
                 .accept_nomination(request, access.clone())
                 .await
                 .unwrap();
@@ -646,14 +1208,14 @@ mod test {
             let request = AddBetRequest {
                 prediction: prediction_id,
                 user,
-                bet: true,
+                outcome: 0,
                 amount: 100,
             };
             let payment = client.add_bet(request, access.clone()).await.unwrap();
         }
 
         // Forcing the end of the decision period
-        let response = client
+        client
             .force_decision_period(prediction_id, access.clone())
             .await
             .unwrap();
@@ -663,15 +1225,15 @@ mod test {
             let request = MakeDecisionRequest {
                 prediction: prediction_id,
                 judge,
-                decision: true,
+                decision: 0,
             };
-            let response = client.make_decision(request, access.clone()).await.unwrap();
+            client.make_decision(request, access.clone()).await.unwrap();
         }
 
         // Cash out users
         for user in [u1, u2, u3] {
             let sats = client.get_balance(user, access.clone()).await.unwrap();
-            assert_eq!(sats, 89);
+            assert_eq!(sats, 90);
         }
 
         // Cash out judges
@@ -680,8 +1242,12 @@ mod test {
             assert_eq!(sats, 15);
         }
 
-        let predictions = client.get_predictions().await.unwrap();
-        let prediction = predictions.first().unwrap();
+        let predictions = client
+            .get_predictions(PredictionListRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(predictions.next_cursor, None);
+        let prediction = predictions.predictions.first().unwrap();
         let ratio = client
             .get_prediction_ratio(PredictionRequest {
                 user: None,
@@ -689,7 +1255,7 @@ mod test {
             })
             .await
             .unwrap();
-        assert_eq!(ratio.0, 300);
+        assert_eq!(ratio, vec![300, 0]);
         assert_eq!(prediction.name, "Test prediction".to_string());
     }
     #[tokio::test]
@@ -11,9 +11,9 @@ use secp256k1::ecdsa::Signature;
 use secp256k1::hashes::sha256::Hash;
 use secp256k1::rand::distributions::Alphanumeric;
 use secp256k1::rand::Rng;
-use secp256k1::{rand, Message};
+use secp256k1::{rand, Message, SecretKey};
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 use std::sync::Arc;
@@ -22,13 +22,27 @@ use thiserror::Error;
 #[derive(Debug)]
 pub struct Prediction {
     pub prediction: String,
+    pub metadata: PredictionMetadata,
+    pub outcomes: Vec<String>,
+    pub scalar: Option<ScalarRange>,
     pub judges: Vec<UserPubKey>,
     pub judge_share_ppm: u32,
+    pub platform_fee_ppm: u32,
+    pub creator_fee_ppm: u32,
     pub state: MarketState,
     pub trading_end: DateTime<Utc>,
     pub decision_period: Duration,
     pub judge_count: u32,
+    pub judge_bond: Sats,
+    pub dispute_period: Duration,
+    pub dispute_bond: Sats,
+    pub reveal_period: Duration,
+    pub judge_replacement: Duration,
+    pub resolution_rules: ResolutionRules,
+    pub appeal_judges: Vec<UserPubKey>,
     pub cash_out: Option<CashOut>,
+    pub creator: UserPubKey,
+    pub amm_subsidy: Option<Sats>,
 }
 impl FromStr for JudgeState {
     type Err = anyhow::Error;
@@ -36,9 +50,14 @@ impl FromStr for JudgeState {
         match s {
             "Nominated" => Ok(Self::Nominated),
             "Accepted" => Ok(Self::Accepted),
+            "Committed" => Ok(Self::Committed),
+            "Standby" => Ok(Self::Standby),
+            "Replaced" => Ok(Self::Replaced),
             "Refused" => Ok(Self::Refused),
-            "Resolved(true)" => Ok(Self::Resolved(true)),
-            "Resolved(false)" => Ok(Self::Resolved(false)),
+            e if e.starts_with("Resolved(") => Ok(Self::Resolved(parse_outcome_value(e)?)),
+            e if e.starts_with("ResolvedScalar(") => {
+                Ok(Self::ResolvedScalar(parse_state_value(e)?))
+            }
             e => {
                 error!(
                     "Error trying to serialize \"{}\" from db into JudgeState",
@@ -57,13 +76,23 @@ impl FromStr for MarketState {
             "Trading" => Ok(Self::Trading),
             "Stopped" => Ok(Self::Stopped),
             "WaitingForDecision" => Ok(Self::WaitingForDecision),
-            "Resolved(true)" => Ok(Self::Resolved(true)),
-            "Resolved(false)" => Ok(Self::Resolved(false)),
+            e if e.starts_with("WaitingForDisputes(") => {
+                Ok(Self::WaitingForDisputes(parse_state_value(e)?))
+            }
+            e if e.starts_with("Disputed(") => Ok(Self::Disputed(parse_state_value(e)?)),
+            e if e.starts_with("Resolved(") => Ok(Self::Resolved(parse_outcome_value(e)?)),
+            e if e.starts_with("ResolvedScalar(") => {
+                Ok(Self::ResolvedScalar(parse_state_value(e)?))
+            }
             "Refunded(TimeForDecisionRanOut)" => {
                 Ok(Self::Refunded(RefundReason::TimeForDecisionRanOut))
             }
             "Refunded(Insolvency)" => Ok(Self::Refunded(RefundReason::Insolvency)),
             "Refunded(Tie)" => Ok(Self::Refunded(RefundReason::Tie)),
+            "Refunded(NoConsensus)" => Ok(Self::Refunded(RefundReason::NoConsensus)),
+            "Refunded(NotEnoughJudges)" => Ok(Self::Refunded(RefundReason::NotEnoughJudges)),
+            "Refunded(Cancelled)" => Ok(Self::Refunded(RefundReason::Cancelled)),
+            "Refunded(Admin)" => Ok(Self::Refunded(RefundReason::Admin)),
             e => {
                 error!(
                     "Error trying to serialize \"{}\" from db into MarketState",
@@ -74,6 +103,128 @@ impl FromStr for MarketState {
         }
     }
 }
+/// Number of entries of a listing page
+fn page_limit(limit: Option<u32>) -> Result<u32> {
+    match limit {
+        None => Ok(DEFAULT_PAGE_LIMIT),
+        Some(0) => bail!("Limit has to be at least 1"),
+        Some(limit) => Ok(limit.min(MAX_PAGE_LIMIT)),
+    }
+}
+fn check_metadata(metadata: &PredictionMetadata) -> Result<()> {
+    if let Some(category) = metadata.category.as_ref() {
+        if category.trim().is_empty() {
+            bail!("Category can't be empty");
+        }
+    }
+    for (i, tag) in metadata.tags.iter().enumerate() {
+        if tag.trim().is_empty() {
+            bail!("Tags can't be empty");
+        }
+        if metadata.tags[..i].contains(tag) {
+            bail!("Tag \"{}\" exists more than once", tag);
+        }
+    }
+    if let Some(url) = metadata.image_url.as_ref() {
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            bail!("Image url {} needs to be a http(s) url", url);
+        }
+    }
+    Ok(())
+}
+/// Parses the value out of a serialized state like `Resolved(<value>)`
+fn parse_state_value<T>(state: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let value = state
+        .split_once('(')
+        .and_then(|(_, value)| value.strip_suffix(')'))
+        .ok_or(anyhow!("Invalid state: {}", state))?;
+    Ok(T::from_str(value)?)
+}
+impl FromStr for Account {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        match s {
+            "Fees" => Ok(Self::Fees),
+            "Funding" => Ok(Self::Funding),
+            "Adjustments" => Ok(Self::Adjustments),
+            "Dust" => Ok(Self::Dust),
+            e if e.starts_with("User(") => Ok(Self::User(parse_state_value(e)?)),
+            e if e.starts_with("Escrow(") => Ok(Self::Escrow(parse_state_value(e)?)),
+            e if e.starts_with("JudgePool(") => Ok(Self::JudgePool(parse_state_value(e)?)),
+            e => bail!("Invalid account: {}", e),
+        }
+    }
+}
+/// Parses the outcome out of a state like `Resolved(<outcome>)`. Yes/no
+/// predictions stored `true` and `false` before they had outcomes.
+fn parse_outcome_value(state: &str) -> Result<Outcome> {
+    match parse_state_value::<bool>(state) {
+        Ok(value) => Ok(outcome_from_bool(value)),
+        Err(_) => parse_state_value(state),
+    }
+}
+/// Sats one contract of the order costs
+fn order_price(side: OrderSide, price: Sats) -> Sats {
+    match side {
+        OrderSide::Bid => price,
+        OrderSide::Ask => CONTRACT_SATS - price,
+    }
+}
+/// Whether the order ends up holding contracts of outcome `0`
+fn buys_first_outcome(order: &Order) -> bool {
+    (order.side == OrderSide::Bid) == (order.outcome == 0)
+}
+/// Price of the order in terms of outcome `0`
+fn first_outcome_price(order: &Order) -> Sats {
+    if order.outcome == 0 {
+        order.price
+    } else {
+        CONTRACT_SATS - order.price
+    }
+}
+/// Logs why a market couldn't advance so the sweep can go on with the next
+fn log_advance_error<T>(prediction: RowId, result: Result<T>) {
+    if let Err(e) = result {
+        error!("Couldn't advance prediction {}: {:#}", prediction, e);
+    }
+}
+/// `numerator / denominator`, if there is anything to divide by
+fn ratio(numerator: Sats, denominator: Sats) -> Option<f64> {
+    (denominator != 0).then(|| numerator as f64 / denominator as f64)
+}
+/// Median of the values, rounded down if there is an even number of values
+fn median(values: &mut [i64]) -> Option<i64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    let upper = values[values.len() / 2];
+    if values.len() % 2 == 1 {
+        return Some(upper);
+    }
+    let lower = values[values.len() / 2 - 1];
+    Some(lower + (upper - lower) / 2)
+}
+/// Splits `amount` evenly between `users`. Returns the amount if there is
+/// nobody to split it between.
+fn split_evenly(
+    amount: Sats,
+    users: &[UserPubKey],
+    kind: LedgerKind,
+    cash_outs: &mut HashMap<UserPubKey, Settlement>,
+) -> Sats {
+    if users.is_empty() {
+        return amount;
+    }
+    for (user, share) in users.iter().zip(distribute(amount, &vec![1; users.len()])) {
+        cash_outs.entry(*user).or_default().add(kind, None, share);
+    }
+    0
+}
 impl FromStr for RefundReason {
     type Err = anyhow::Error;
 
@@ -82,6 +233,10 @@ impl FromStr for RefundReason {
             "Insolvency" => Ok(Self::Insolvency),
             "TimeForDecisionRanOut" => Ok(Self::TimeForDecisionRanOut),
             "Tie" => Ok(Self::Tie),
+            "NoConsensus" => Ok(Self::NoConsensus),
+            "NotEnoughJudges" => Ok(Self::NotEnoughJudges),
+            "Cancelled" => Ok(Self::Cancelled),
+            "Admin" => Ok(Self::Admin),
             e => {
                 error!(
                     "Error trying to serialize \"{}\" from db into RefundReason",
@@ -99,9 +254,36 @@ pub struct CashOut {
     amount: Sats,
     invoice: Option<String>,
 }
+/// Sats a user locked in a prediction and gets paid from it on settlement.
+/// Every entry ends up in the ledger, stakes and slashed bonds are negative.
+#[derive(Debug, Default, Clone)]
+struct Settlement {
+    entries: Vec<(LedgerKind, Option<RowId>, Sats)>,
+}
+impl Settlement {
+    fn add(&mut self, kind: LedgerKind, bet: Option<RowId>, amount: Sats) {
+        if amount != 0 {
+            self.entries.push((kind, bet, amount));
+        }
+    }
+    /// Change of the balance of the user
+    fn total(&self) -> Sats {
+        self.entries.iter().map(|(_, _, amount)| amount).sum()
+    }
+}
+/// Fee the operator takes from the pool of every prediction created while it
+/// is configured
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlatformFee {
+    pub fee_ppm: u32,
+    /// Receives the platform fees and the remainders of settlements that
+    /// nobody is entitled to
+    pub house_account: Option<UserPubKey>,
+}
 pub struct Mercado {
     db: Arc<DB>,
     funding: Arc<Box<dyn FundingSource + Send + Sync>>,
+    platform_fee: PlatformFee,
     disable_auth: bool,
 }
 
@@ -110,11 +292,16 @@ impl Mercado {
         db: Arc<DB>,
         funding: Box<dyn FundingSource + Send + Sync>,
         admins: Vec<String>,
+        platform_fee: PlatformFee,
         test: bool,
     ) -> Result<Self> {
+        if platform_fee.fee_ppm > 0 && platform_fee.house_account.is_none() {
+            bail!("Platform fees need a house account");
+        }
         let me = Self {
             db,
             funding: Arc::new(funding),
+            platform_fee,
             disable_auth: test,
         };
         for admin in admins {
@@ -126,13 +313,209 @@ impl Mercado {
     }
     pub async fn new_prediction(
         &self,
-        prediction: String,
-        judges: Vec<UserPubKey>,
-        judge_count: u32,
-        judge_share_ppm: u32,
-        trading_end: DateTime<Utc>,
-        decision_period: Duration,
+        request: NewPredictionRequest,
+        access: AccessRequest,
+    ) -> Result<RowId> {
+        self.check_access(access.clone()).await?;
+        self.create_prediction(request, access.user, None).await
+    }
+    /// Cancels a prediction that is still waiting for judges. Only its
+    /// creator can do that.
+    pub async fn cancel_prediction(&self, prediction: RowId, access: AccessRequest) -> Result<()> {
+        self.check_creator_amendment(prediction, access).await?;
+        self.db
+            .set_prediction_state(prediction, MarketState::Refunded(RefundReason::Cancelled))
+            .await?;
+        info!("Prediction {} was cancelled by its creator", prediction);
+        self.refund_prediction(prediction).await
+    }
+    /// Changes the terms of a prediction that is still waiting for judges.
+    /// Judges that already accepted have to accept again if the text, the
+    /// metadata or the trading end changed.
+    pub async fn amend_prediction(
+        &self,
+        request: AmendPredictionRequest,
+        access: AccessRequest,
+    ) -> Result<()> {
+        let AmendPredictionRequest {
+            prediction,
+            text,
+            trading_end,
+            new_judges,
+            metadata,
+        } = request;
+        self.check_creator_amendment(prediction, access).await?;
+        if let Some(metadata) = metadata.as_ref() {
+            check_metadata(metadata)?;
+        }
+        if let Some(trading_end) = trading_end {
+            let current = self.db.get_trading_end(prediction).await?;
+            if trading_end <= current {
+                bail!(
+                    "Trading end can only be extended but {} is not after {}",
+                    trading_end,
+                    current
+                );
+            }
+        }
+        let judges = self.db.get_prediction_judges_mapped(prediction).await?;
+        let appeal_judges = self.db.get_appeal_judges(prediction).await?;
+        for (i, judge) in new_judges.iter().enumerate() {
+            if judges.contains_key(judge)
+                || appeal_judges.contains(judge)
+                || new_judges[..i].contains(judge)
+            {
+                bail!("Judge {} is nominated more than once", judge);
+            }
+        }
+        if let Some(text) = text.as_ref() {
+            self.db.set_prediction_text(prediction, text).await?;
+        }
+        if let Some(trading_end) = trading_end {
+            self.db.set_trading_end(prediction, trading_end).await?;
+        }
+        if let Some(metadata) = metadata.as_ref() {
+            self.db
+                .set_prediction_metadata(prediction, metadata)
+                .await?;
+        }
+        if text.is_some() || trading_end.is_some() || metadata.is_some() {
+            self.db.reset_nominations(prediction, Utc::now()).await?;
+            debug!(
+                "Terms of prediction {} changed. Reset accepted nominations",
+                prediction
+            );
+        }
+        for judge in new_judges {
+            self.db.add_judge(prediction, judge).await?;
+            debug!("Nominated {} on prediction {}", judge, prediction);
+        }
+        Ok(())
+    }
+    async fn check_creator_amendment(
+        &self,
+        prediction: RowId,
+        access: AccessRequest,
+    ) -> Result<()> {
+        let creator = self
+            .db
+            .get_creator(prediction)
+            .await?
+            .ok_or(anyhow!("Prediction {} has no creator", prediction))?;
+        self.check_access_for_user(creator, access).await?;
+        match self.get_active_state(prediction).await? {
+            MarketState::WaitingForJudges => {
+                if self.close_if_judges_missing(prediction).await? {
+                    bail!("Trading end passed before enough judges accepted");
+                }
+            }
+            _ => bail!("Wrong market state"),
+        }
+        Ok(())
+    }
+    pub async fn new_amm_prediction(
+        &self,
+        request: NewAmmPredictionRequest,
+        access: AccessRequest,
+    ) -> Result<RowId> {
+        let NewAmmPredictionRequest {
+            creator,
+            subsidy,
+            prediction,
+        } = request;
+        self.check_access_for_user(creator, access.clone()).await?;
+        if prediction.scalar.is_some() {
+            bail!("Scalar predictions can't use a market maker");
+        }
+        if subsidy <= 0 {
+            bail!("Subsidy has to be positive");
+        }
+        if self.get_available_balance(creator, access).await? < subsidy {
+            bail!("Not enough funds");
+        }
+        let id = self
+            .create_prediction(prediction, creator, Some(subsidy))
+            .await?;
+        self.db
+            .post_transaction(
+                "Market maker subsidy",
+                Some(id),
+                &[
+                    (Account::User(creator), -subsidy),
+                    (Account::Escrow(id), subsidy),
+                ],
+            )
+            .await?;
+        self.db
+            .add_ledger_entry(creator, id, None, LedgerKind::Stake, -subsidy, Utc::now())
+            .await?;
+        debug!(
+            "{} subsidized market maker of prediction {} with {} sats",
+            creator, id, subsidy
+        );
+        Ok(id)
+    }
+    async fn create_prediction(
+        &self,
+        request: NewPredictionRequest,
+        creator: UserPubKey,
+        amm_subsidy: Option<Sats>,
     ) -> Result<RowId> {
+        let NewPredictionRequest {
+            prediction,
+            outcomes,
+            scalar,
+            judges,
+            judge_share_ppm,
+            trading_end,
+            decision_period_sec,
+            judge_count,
+            judge_bond,
+            dispute_period_sec,
+            dispute_bond,
+            appeal_judges,
+            reveal_period_sec,
+            judge_replacement_sec,
+            creator_fee_ppm,
+            metadata,
+            resolution_rules,
+        } = request;
+        let decision_period = Duration::seconds(decision_period_sec.into());
+        let dispute_period = Duration::seconds(dispute_period_sec.into());
+        let reveal_period = Duration::seconds(reveal_period_sec.into());
+        let judge_replacement = Duration::seconds(judge_replacement_sec.into());
+        let outcomes = if let Some(range) = scalar {
+            if !outcomes.is_empty() {
+                bail!("Scalar predictions can't have custom outcomes");
+            }
+            if range.min >= range.max {
+                bail!(
+                    "Scalar range minimum {} needs to be lower than maximum {}",
+                    range.min,
+                    range.max
+                );
+            }
+            SCALAR_OUTCOMES
+                .iter()
+                .map(|outcome| outcome.to_string())
+                .collect()
+        } else if outcomes.is_empty() {
+            DEFAULT_OUTCOMES
+                .iter()
+                .map(|outcome| outcome.to_string())
+                .collect()
+        } else {
+            outcomes
+        };
+        if outcomes.len() < 2 {
+            bail!("There needs to be more than one outcome");
+        }
+        check_metadata(&metadata)?;
+        for (i, outcome) in outcomes.iter().enumerate() {
+            if outcomes[..i].contains(outcome) {
+                bail!("Outcome \"{}\" exists more than once", outcome);
+            }
+        }
         if judge_count == 0 {
             bail!("There neeeds to be at least one judge");
         }
@@ -143,12 +526,50 @@ impl Mercado {
                 judge_count
             ));
         }
+        if judge_bond < 0 {
+            bail!("Judge bond can't be negative");
+        }
+        if dispute_bond < 0 {
+            bail!("Dispute bond can't be negative");
+        }
+        if dispute_period_sec > 0 {
+            if scalar.is_some() {
+                bail!("Decisions on scalar predictions can't be disputed");
+            }
+            if appeal_judges.is_empty() {
+                bail!("There need to be appeal judges to allow disputes");
+            }
+        } else if !appeal_judges.is_empty() {
+            bail!("Appeal judges need a dispute period");
+        }
+        for (i, judge) in appeal_judges.iter().enumerate() {
+            if judges.contains(judge) || appeal_judges[..i].contains(judge) {
+                bail!("Appeal judge {} is nominated more than once", judge);
+            }
+        }
         if judge_share_ppm > 1000000 {
             return Err(anyhow!(
                 "judge_share_ppm was {} but needs to be lower than 100.000",
                 judge_share_ppm
             ));
         }
+        let platform_fee_ppm = self.platform_fee.fee_ppm;
+        if u64::from(judge_share_ppm) + u64::from(platform_fee_ppm) + u64::from(creator_fee_ppm)
+            > 1000000
+        {
+            bail!(
+                "Judge share, platform fee of {} ppm and creator fee add up to more than the pool",
+                platform_fee_ppm
+            );
+        }
+        if amm_subsidy.is_some()
+            && u64::from(judge_share_ppm) + u64::from(platform_fee_ppm) >= 1000000
+        {
+            bail!(
+                "Judge share and platform fee of {} ppm leave no subsidy for the market maker",
+                platform_fee_ppm
+            );
+        }
         if trading_end < Utc::now() + Duration::days(2) {
             return Err(anyhow!(
                 "Trading end was at {} but needs to be after {}",
@@ -163,17 +584,50 @@ impl Mercado {
                 Duration::days(1).num_seconds()
             ));
         }
+        if judge_replacement >= decision_period {
+            bail!("Judges need to be replaced before the decision period ends");
+        }
+        if resolution_rules.supermajority_ppm > 1000000 {
+            bail!(
+                "supermajority_ppm was {} but can't be more than 1.000.000",
+                resolution_rules.supermajority_ppm
+            );
+        }
+        if scalar.is_some() && resolution_rules.supermajority_ppm > 0 {
+            bail!("Decisions on scalar predictions can't require a supermajority");
+        }
+        if resolution_rules.quorum > judge_count {
+            bail!(
+                "Quorum of {} votes is higher than the judge count of {}",
+                resolution_rules.quorum,
+                judge_count
+            );
+        }
         let id = self
             .db
             .add_prediction(Prediction {
                 prediction: prediction.clone(),
-                judges: judges.iter().map(|user| user.clone()).collect(),
+                metadata,
+                outcomes,
+                scalar,
+                judges: judges.to_vec(),
                 judge_count,
+                judge_bond,
+                dispute_period,
+                dispute_bond,
+                reveal_period,
+                judge_replacement,
+                resolution_rules,
+                appeal_judges,
                 judge_share_ppm,
+                platform_fee_ppm,
+                creator_fee_ppm,
                 trading_end,
                 decision_period,
                 state: MarketState::WaitingForJudges,
                 cash_out: None,
+                creator,
+                amm_subsidy,
             })
             .await?;
         debug!("Created Prediction {}: {}", id, prediction);
@@ -185,27 +639,41 @@ impl Mercado {
         user: UserPubKey,
         access: AccessRequest,
     ) -> Result<()> {
-        self.check_access_for_user(user.clone(), access).await?;
-        match self
-            .db
-            .get_prediction_state(prediction)
-            .await
-            .context("failed to get prediction state")?
-        {
-            MarketState::WaitingForJudges => {}
+        self.check_access_for_user(user, access.clone()).await?;
+        // Judges that accept after enough judges were found become standbys
+        let judge_state = match self.get_active_state(prediction).await? {
+            MarketState::WaitingForJudges => {
+                if self.close_if_judges_missing(prediction).await? {
+                    bail!("Trading end passed before enough judges accepted");
+                }
+                JudgeState::Accepted
+            }
+            MarketState::Trading | MarketState::WaitingForDecision => JudgeState::Standby,
             _ => bail!("Wrong market state"),
+        };
+        if self.db.get_judge_state(prediction, user).await? != JudgeState::Nominated {
+            bail!("Judge {} has no open nomination", user);
+        }
+        let bond = self.db.get_judge_bond(prediction).await?;
+        if bond > 0 {
+            if self.get_available_balance(user, access).await? < bond {
+                bail!("Not enough funds to lock the judge bond of {} sats", bond);
+            }
+            self.db.set_judge_bond(prediction, user, bond).await?;
         }
         debug!(
-            "Accepted nomination on prediction {} for user {}",
-            prediction, user
+            "Accepted nomination on prediction {} for user {} as {}",
+            prediction, user, judge_state
         );
         match self
             .db
-            .set_judge_state(prediction, user, JudgeState::Accepted)
+            .set_judge_state(prediction, user, judge_state)
             .await
             .context("failed to set judge state")
         {
-            Ok(_) => self.try_activate_trading(prediction).await,
+            Ok(_) if judge_state == JudgeState::Accepted => {
+                self.try_activate_trading(prediction).await
+            }
             e => e,
         }
     }
@@ -215,8 +683,8 @@ impl Mercado {
         user: UserPubKey,
         access: AccessRequest,
     ) -> Result<()> {
-        self.check_access_for_user(user.clone(), access).await?;
-        match self.db.get_prediction_state(prediction).await? {
+        self.check_access_for_user(user, access).await?;
+        match self.get_active_state(prediction).await? {
             MarketState::WaitingForJudges => {}
             _ => bail!("Wrong market state"),
         }
@@ -232,709 +700,6564 @@ impl Mercado {
         &mut self,
         prediction: RowId,
         judge: UserPubKey,
-        decision: bool,
+        decision: Outcome,
         access: AccessRequest,
     ) -> Result<()> {
-        self.check_access_for_user(judge.clone(), access).await?;
-        match self.db.get_prediction_state(prediction).await? {
+        self.check_access_for_user(judge, access).await?;
+        self.check_outcome(prediction, decision).await?;
+        if self.db.get_scalar_range(prediction).await?.is_some() {
+            bail!("Decisions on scalar predictions need to be a value");
+        }
+        self.check_plain_decision_allowed(prediction).await?;
+        self.check_decision_allowed(prediction, judge).await?;
+        debug!(
+            "Voted for {} on prediction {} for judge {}",
+            decision, prediction, judge
+        );
+        match self
+            .db
+            .set_judge_state(prediction, judge, JudgeState::Resolved(decision))
+            .await
+        {
+            Ok(_) => self.try_resolve(prediction).await,
+            e => e,
+        }
+    }
+    pub async fn make_scalar_decision(
+        &mut self,
+        prediction: RowId,
+        judge: UserPubKey,
+        value: i64,
+        access: AccessRequest,
+    ) -> Result<()> {
+        self.check_access_for_user(judge, access).await?;
+        let range = self
+            .db
+            .get_scalar_range(prediction)
+            .await?
+            .ok_or(anyhow!("Prediction {} is not a scalar market", prediction))?;
+        if value < range.min || value > range.max {
+            bail!(
+                "Value {} is outside of the range {} to {}",
+                value,
+                range.min,
+                range.max
+            );
+        }
+        self.check_plain_decision_allowed(prediction).await?;
+        self.check_decision_allowed(prediction, judge).await?;
+        debug!(
+            "Voted for value {} on prediction {} for judge {}",
+            value, prediction, judge
+        );
+        match self
+            .db
+            .set_judge_state(prediction, judge, JudgeState::ResolvedScalar(value))
+            .await
+        {
+            Ok(_) => self.try_resolve(prediction).await,
+            e => e,
+        }
+    }
+    async fn check_decision_allowed(&mut self, prediction: RowId, judge: UserPubKey) -> Result<()> {
+        match self.get_active_state(prediction).await? {
             MarketState::WaitingForDecision => {
-                if self.db.get_trading_end(prediction).await?
-                    + self.db.get_decision_period(prediction).await?
-                    < Utc::now()
-                {
-                    self.db
-                        .set_prediction_state(
-                            prediction,
-                            MarketState::Refunded(RefundReason::TimeForDecisionRanOut),
-                        )
-                        .await?;
-                    info!(
-                        "Time for decision for prediction {} ran out. Refunding bets",
-                        prediction
-                    );
-                    //Execute refund
-                    self.db.remove_bets(Some(prediction), None).await?;
+                if self.finish_decision_period_if_over(prediction).await? {
                     bail!("Wrong market state");
                 }
             }
             MarketState::Trading => {
-                if self.db.get_trading_end(prediction).await? < Utc::now() {
-                    self.db
-                        .set_prediction_state(prediction, MarketState::WaitingForDecision)
-                        .await?;
-                } else {
+                if !self.end_trading_if_due(prediction).await? {
                     bail!("Can't make decision while market is still trading")
                 }
             }
             _ => bail!("Wrong market state"),
         }
-        match self.db.get_judge_state(prediction.clone(), judge).await? {
+        self.replace_silent_judges(prediction).await?;
+        match self.db.get_judge_state(prediction, judge).await? {
             JudgeState::Nominated | JudgeState::Refused => {
                 bail!("Judge did not accept the nomination")
             }
-            JudgeState::Resolved(_) | JudgeState::Accepted => {}
+            JudgeState::Standby => bail!("Standby judges can't decide"),
+            JudgeState::Replaced => bail!("Judge was replaced for not deciding in time"),
+            JudgeState::Resolved(_)
+            | JudgeState::ResolvedScalar(_)
+            | JudgeState::Accepted
+            | JudgeState::Committed => {}
+        }
+        Ok(())
+    }
+    /// Replaces judges that haven't voted once the replacement point of the
+    /// decision period passed. Standby judges take over in the order they
+    /// accepted and the replaced judges get their bond back. Promoted standby
+    /// judges don't get replaced again.
+    async fn replace_silent_judges(&self, prediction: RowId) -> Result<()> {
+        let replacement = self.db.get_judge_replacement(prediction).await?;
+        // Judges voting again in an extended decision period aren't silent
+        if replacement == Duration::zero()
+            || self.db.get_trading_end(prediction).await? + replacement > Utc::now()
+            || self.db.get_decision_extended(prediction).await?
+        {
+            return Ok(());
+        }
+        let bond = self.db.get_judge_bond(prediction).await?;
+        let mut standbys = self.db.get_standby_judges(prediction).await?.into_iter();
+        for judge in self.db.get_replaceable_judges(prediction).await? {
+            let Some(standby) = standbys.next() else {
+                break;
+            };
+            self.db
+                .set_judge_state(prediction, judge, JudgeState::Replaced)
+                .await?;
+            if bond > 0 {
+                // Votes aren't final yet, so the slashed bond goes to the house
+                let mut cash_out: HashMap<UserPubKey, Settlement> = HashMap::new();
+                cash_out
+                    .entry(judge)
+                    .or_default()
+                    .add(LedgerKind::Bond, None, -bond);
+                let dust = self.credit_house(bond, &mut cash_out);
+                self.apply_cash_out(prediction, cash_out, dust).await?;
+                self.db.set_judge_bond(prediction, judge, 0).await?;
+            }
+            self.db
+                .set_judge_state(prediction, standby, JudgeState::Accepted)
+                .await?;
+            info!(
+                "Replaced judge {} with standby judge {} on prediction {}",
+                judge, standby, prediction
+            );
+        }
+        Ok(())
+    }
+    async fn check_plain_decision_allowed(&self, prediction: RowId) -> Result<()> {
+        if self.db.get_reveal_period(prediction).await? > Duration::zero() {
+            bail!(
+                "Decisions on prediction {} need to be committed and revealed",
+                prediction
+            );
+        }
+        Ok(())
+    }
+    /// Commits a judge to a decision on a prediction with commit-reveal
+    /// voting. Judges can change their commitment until the reveal window
+    /// starts.
+    pub async fn commit_decision(
+        &mut self,
+        request: CommitDecisionRequest,
+        access: AccessRequest,
+    ) -> Result<()> {
+        let CommitDecisionRequest {
+            prediction,
+            judge,
+            commitment,
+        } = request;
+        self.check_access_for_user(judge, access).await?;
+        if self.db.get_reveal_period(prediction).await? == Duration::zero() {
+            bail!("Prediction {} doesn't use commit-reveal voting", prediction);
+        }
+        self.check_decision_allowed(prediction, judge).await?;
+        if self.reveal_started(prediction).await? {
+            bail!("Can't commit to a decision after the reveal window started");
+        }
+        self.db
+            .set_commitment(prediction, judge, &commitment)
+            .await?;
+        self.db
+            .set_judge_state(prediction, judge, JudgeState::Committed)
+            .await?;
+        debug!("Judge {} committed on prediction {}", judge, prediction);
+        Ok(())
+    }
+    /// Reveals a committed decision. It only counts if `decision` and `salt`
+    /// match the commitment.
+    pub async fn reveal_decision(
+        &mut self,
+        request: RevealDecisionRequest,
+        access: AccessRequest,
+    ) -> Result<()> {
+        let RevealDecisionRequest {
+            prediction,
+            judge,
+            decision,
+            salt,
+        } = request;
+        self.check_access_for_user(judge, access).await?;
+        self.check_decision_allowed(prediction, judge).await?;
+        if self.db.get_judge_state(prediction, judge).await? != JudgeState::Committed {
+            bail!("Judge {} has no decision to reveal", judge);
+        }
+        if !self.reveal_started(prediction).await? {
+            bail!("Decisions can't be revealed before all judges committed or the decision period is over");
         }
+        let commitment = self.db.get_commitment(prediction, judge).await?;
+        if calculate_decision_commitment(prediction, judge, decision, &salt) != commitment {
+            bail!("Decision doesn't match the commitment");
+        }
+        let state = match self.db.get_scalar_range(prediction).await? {
+            Some(range) => {
+                if decision < range.min || decision > range.max {
+                    bail!(
+                        "Value {} is outside of the range {} to {}",
+                        decision,
+                        range.min,
+                        range.max
+                    );
+                }
+                JudgeState::ResolvedScalar(decision)
+            }
+            None => {
+                let outcome = Outcome::try_from(decision)
+                    .map_err(|_| anyhow!("Outcome {} doesn't exist", decision))?;
+                self.check_outcome(prediction, outcome).await?;
+                JudgeState::Resolved(outcome)
+            }
+        };
         debug!(
-            "Voted for {} on prediction {} for judge {}",
+            "Revealed {} on prediction {} for judge {}",
             decision, prediction, judge
         );
-        match self
-            .db
-            .set_judge_state(prediction, judge, JudgeState::Resolved(decision))
-            .await
+        self.db.set_judge_state(prediction, judge, state).await?;
+        self.try_resolve(prediction).await
+    }
+    /// Reveals start once every judge committed or the decision period is over
+    async fn reveal_started(&self, prediction: RowId) -> Result<bool> {
+        if self.db.get_trading_end(prediction).await?
+            + self.db.get_decision_period(prediction).await?
+            < Utc::now()
         {
-            Ok(_) => self.try_resolve(prediction).await,
-            e => e,
+            return Ok(true);
         }
+        Ok(!self
+            .db
+            .get_judge_states(prediction)
+            .await?
+            .contains(&JudgeState::Accepted))
+    }
+    /// End of the time judges have to decide including the reveal window
+    async fn get_decision_deadline(&self, prediction: RowId) -> Result<DateTime<Utc>> {
+        Ok(self.db.get_trading_end(prediction).await?
+            + self.db.get_decision_period(prediction).await?
+            + self.db.get_reveal_period(prediction).await?)
     }
     async fn try_resolve(&mut self, prediction: RowId) -> Result<()> {
-        let mut true_count = 0;
-        let mut false_count = 0;
+        if self.db.get_scalar_range(prediction).await?.is_some() {
+            return self.try_resolve_scalar(prediction).await;
+        }
+        // Judges that didn't reveal in time count as not voted
+        let deadline_passed = self.get_decision_deadline(prediction).await? < Utc::now();
+        let rules = self.db.get_resolution_rules(prediction).await?;
+        let mut votes: HashMap<Outcome, u32> = HashMap::new();
         for state in self.db.get_judge_states(prediction).await? {
             match state {
-                JudgeState::Accepted => {
+                JudgeState::Accepted | JudgeState::Committed if !deadline_passed => {
                     return Ok(());
                 }
                 JudgeState::Resolved(decision) => {
-                    if decision {
-                        true_count += 1;
-                    } else {
-                        false_count += 1;
-                    }
+                    *votes.entry(decision).or_default() += 1;
                 }
                 _ => {}
             }
         }
-        match true_count.cmp(&false_count) {
-            Ordering::Less => {
-                self.db
-                    .set_prediction_state(prediction, MarketState::Resolved(false))
-                    .await?
-            }
-            Ordering::Equal => {
-                self.db
-                    .set_prediction_state(prediction, MarketState::Refunded(RefundReason::Tie))
-                    .await?;
-                info!("Decision for {} was a tie. Refunding bets", prediction);
-                // Refund bets
-                self.db.remove_bets(Some(prediction), None).await?;
-                bail!("There was a decision tie between an even number of judges")
-            }
-            Ordering::Greater => {
-                self.db
-                    .set_prediction_state(prediction, MarketState::Resolved(true))
-                    .await?
-            }
-        }
-        let cash_out = self.calculate_cash_out(prediction).await?;
-        self.apply_cash_out(cash_out).await?;
-        Ok(())
-    }
-    async fn apply_cash_out(&self, cash_out: HashMap<UserPubKey, (Sats, Sats)>) -> Result<()> {
-        for (user, (placed_bets, cash_out)) in cash_out {
-            let new_balance = self
-                .db
-                .adjust_user_balance(user, cash_out - placed_bets)
-                .await?;
-            if new_balance.is_negative() {
-                error!(
-                    "User {} has new balance {} after cash_out",
-                    user, new_balance
-                )
+        let cast: u32 = votes.values().sum();
+        if cast == 0 || (deadline_passed && cast < rules.quorum) {
+            return Ok(());
+        }
+        let mut majority = None;
+        let mut majority_votes = 0;
+        for (outcome, count) in votes {
+            match count.cmp(&majority_votes) {
+                Ordering::Greater => {
+                    majority = Some(outcome);
+                    majority_votes = count;
+                }
+                Ordering::Equal => majority = None,
+                Ordering::Less => {}
             }
         }
-        Ok(())
-    }
-    async fn calculate_cash_out(
-        &self,
-        prediction: RowId,
-    ) -> Result<HashMap<UserPubKey, (Sats, Sats)>> {
-        if let MarketState::Resolved(outcome) = self.db.get_prediction_state(prediction).await? {
-            let outcome_bets = self
-                .db
-                .get_prediction_bets_aggregated(prediction, outcome)
-                .await?;
-            let non_outcome_bets = self
-                .db
-                .get_prediction_bets_aggregated(prediction, !outcome)
-                .await?;
-            let outcome_amount = self
-                .get_prediction_bets_aggregated(prediction, outcome)
-                .await?;
-            let non_outcome_amount = self
-                .get_prediction_bets_aggregated(prediction, !outcome)
-                .await?;
-
-            // Calculate outcome users
-            let mut user_cash_outs = HashMap::new();
-            let mut user_cash_out_amount = 0;
-            for (user, bet_amount) in outcome_bets {
-                let cash_out = calculate_user_cash_out(
-                    bet_amount,
-                    outcome_amount,
-                    non_outcome_amount,
-                    self.db.get_judge_share_ppm(prediction).await?,
-                );
-                if cash_out == 0 {
-                    continue;
+        let supermajority = u64::from(majority_votes) * 1000000
+            >= u64::from(rules.supermajority_ppm) * u64::from(cast);
+        match majority {
+            Some(outcome) if supermajority => {
+                if self.db.get_dispute_period(prediction).await? > Duration::zero() {
+                    self.db
+                        .set_prediction_state(prediction, MarketState::WaitingForDisputes(outcome))
+                        .await?;
+                    self.db.set_resolution_time(prediction, Utc::now()).await?;
+                    debug!(
+                        "Judges decided on {} for prediction {}. Waiting for disputes",
+                        outcome, prediction
+                    );
+                    return Ok(());
                 }
-                user_cash_out_amount += cash_out;
-                let non_outcome_bet = non_outcome_bets.get(&user).cloned();
-                let placed_bets = bet_amount + non_outcome_bet.unwrap_or_default();
-                user_cash_outs.insert(user.clone(), (placed_bets, cash_out));
+                self.db
+                    .set_prediction_state(prediction, MarketState::Resolved(outcome))
+                    .await?
             }
-
-            //Calculate non-outcome users
-            for (user, bet_amount) in non_outcome_bets {
-                if let None = user_cash_outs.get(&user) {
-                    user_cash_outs.insert(user, (bet_amount, 0));
+            _ => {
+                if self.extend_decision_period(prediction, true).await? {
+                    return Ok(());
                 }
-            }
-
-            // Calculate judges
-            let mut judge_cash_out_amount = 0;
-            let judge_outcome_count = self.get_outcome_judge_count(prediction).await?;
-            for (judge, state) in self.db.get_prediction_judges_mapped(prediction).await? {
-                if let JudgeState::Resolved(decision) = state {
-                    if decision == outcome {
-                        let cash_out = calculate_judge_cash_out(
-                            judge_outcome_count,
-                            outcome_amount,
-                            non_outcome_amount,
-                            self.db.get_judge_share_ppm(prediction).await?,
-                        );
-                        if cash_out == 0 {
-                            continue;
-                        }
-                        judge_cash_out_amount += cash_out;
-                        if let Some((placed_bets, user_cash_out)) = user_cash_outs.remove(&judge) {
-                            user_cash_outs.insert(judge, (placed_bets, user_cash_out + cash_out));
-                        } else {
-                            user_cash_outs.insert(judge, (0, cash_out));
-                        }
-                    }
+                if majority.is_some() {
+                    self.db
+                        .set_prediction_state(
+                            prediction,
+                            MarketState::Refunded(RefundReason::NoConsensus),
+                        )
+                        .await?;
+                    info!(
+                        "Judges didn't reach the supermajority on {}. Refunding bets",
+                        prediction
+                    );
+                    self.refund_prediction(prediction).await?;
+                    bail!("The judges didn't reach the required supermajority")
                 }
-            }
-
-            // Check solvency after calculation
-            if user_cash_out_amount + judge_cash_out_amount > outcome_amount + non_outcome_amount {
                 self.db
-                    .set_prediction_state(
-                        prediction,
-                        MarketState::Refunded(RefundReason::Insolvency),
-                    )
+                    .set_prediction_state(prediction, MarketState::Refunded(RefundReason::Tie))
                     .await?;
-                error!(
-                    "For some reason the cash out calculation made the prediction {} \
-                   insolvent. Bets are being refunded",
-                    prediction
-                );
-                error!("The following should be true but wasn't:");
-                error!("user_cash_out_amount + judge_cash_out_amount > outcome_amount + non_outcome_amount");
-                error!(
-                    "{} + {} > {} + {}",
-                    user_cash_out_amount, judge_cash_out_amount, outcome_amount, non_outcome_amount
-                );
-                //Execute refund
-                self.db.remove_bets(Some(prediction), None).await?;
-                bail!(
-                    "For some reason the cash out calculation made the prediction {} \
-                  insolvent. Bets are being refunded",
-                    prediction
-                )
+                info!("Decision for {} was a tie. Refunding bets", prediction);
+                self.refund_prediction(prediction).await?;
+                bail!("There was a decision tie between the judges")
             }
-            Ok(user_cash_outs)
-        } else {
-            bail!("Market not resolved")
         }
+        let (cash_out, dust) = self.calculate_cash_out(prediction).await?;
+        self.apply_cash_out(prediction, cash_out, dust).await?;
+        self.db
+            .set_bets_status(prediction, BetStatus::Settled)
+            .await?;
+        Ok(())
     }
-    async fn try_activate_trading(&mut self, prediction: RowId) -> Result<()> {
-        let mut accepted_count = 0;
-        for state in self
-            .db
-            .get_judge_states(prediction)
-            .await
-            .context("failed to get judge states")?
-        {
-            if state == JudgeState::Accepted {
-                accepted_count += 1;
-            }
+    /// Disputes the decision of the judges. The appeal judges then decide on
+    /// the outcome again.
+    pub async fn dispute(&mut self, request: DisputeRequest, access: AccessRequest) -> Result<()> {
+        let DisputeRequest { prediction, user } = request;
+        self.check_access_for_user(user, access.clone()).await?;
+        let outcome = match self.get_active_state(prediction).await? {
+            MarketState::WaitingForDisputes(outcome) => outcome,
+            _ => bail!("Wrong market state"),
+        };
+        if self.finish_dispute_period_if_over(prediction).await? {
+            bail!("Dispute period is over");
         }
-        if accepted_count
-            == self
+        let has_stake = !self
+            .db
+            .get_bets(Some(prediction), Some(user), false, PageRequest::default())
+            .await?
+            .is_empty()
+            || !self
                 .db
-                .get_judge_count(prediction)
-                .await
-                .context("failed to get judge count")?
-        {
-            self.db
-                .set_prediction_state(prediction, MarketState::Trading)
-                .await?;
+                .get_positions(Some(prediction), Some(user))
+                .await?
+                .is_empty()
+            || !self
+                .db
+                .get_shares(Some(prediction), Some(user))
+                .await?
+                .is_empty();
+        if !has_stake {
+            bail!("Only users that bet on the prediction can dispute it");
         }
+        let bond = self.db.get_dispute_bond(prediction).await?;
+        if bond > 0 && self.get_available_balance(user, access.clone()).await? < bond {
+            bail!("Not enough funds to lock the dispute bond of {} sats", bond);
+        }
+        self.db.create_dispute(prediction, user, bond).await?;
+        self.db
+            .set_prediction_state(prediction, MarketState::Disputed(outcome))
+            .await?;
+        info!(
+            "{} disputed decision {} on prediction {} by {}",
+            user, outcome, prediction, access.user
+        );
         Ok(())
     }
-    pub async fn add_bet(
+    pub async fn make_appeal_decision(
         &mut self,
         prediction: RowId,
-        user: UserPubKey,
-        bet: bool,
-        amount: Sats,
+        judge: UserPubKey,
+        decision: Outcome,
         access: AccessRequest,
     ) -> Result<()> {
-        self.check_access_for_user(user.clone(), access.clone())
-            .await?;
-        match self.db.get_prediction_state(prediction).await? {
-            MarketState::Trading => {
-                if self.db.get_trading_end(prediction).await? < Utc::now() {
-                    self.db
-                        .set_prediction_state(prediction, MarketState::WaitingForDecision)
-                        .await?;
-                    debug!("Triggered trading end because someone tried betting after trading end");
-                    bail!("Trading ended");
-                }
-            }
-            _ => bail!("Prediction has to be Trading to be able to bet on it"),
+        self.check_access_for_user(judge, access).await?;
+        self.check_outcome(prediction, decision).await?;
+        match self.get_active_state(prediction).await? {
+            MarketState::Disputed(_) => {}
+            _ => bail!("Wrong market state"),
         }
-        if amount <= 0 {
-            bail!("Amount has to be positive");
+        if self.finish_dispute_period_if_over(prediction).await? {
+            bail!("Time for the appeal ran out");
+        }
+        let appeal_judges = self.db.get_appeal_judges(prediction).await?;
+        if !appeal_judges.contains(&judge) {
+            bail!("User is not an appeal judge of this prediction");
         }
-        self.db.create_bet(prediction, user, bet, amount).await?;
+        self.db.set_appeal_vote(prediction, judge, decision).await?;
         debug!(
-            "Added {} sats bet on {} and prediction {} for user {} by {}",
-            amount, bet, prediction, user, access.user
+            "Voted for {} in the appeal on prediction {} for judge {}",
+            decision, prediction, judge
         );
+        let mut votes: HashMap<Outcome, usize> = HashMap::new();
+        for decision in self.db.get_appeal_votes(prediction).await?.into_values() {
+            *votes.entry(decision).or_default() += 1;
+        }
+        if let Some((verdict, _)) = votes
+            .into_iter()
+            .find(|(_, count)| count * 2 > appeal_judges.len())
+        {
+            self.finish_dispute(prediction, Some(verdict)).await?;
+        }
         Ok(())
     }
-    pub async fn cancel_bet(&mut self, id: RowId, access: AccessRequest) -> Result<()> {
-        let bet = self.db.get_bet(id).await?;
-        self.check_access_for_user(bet.user, access.clone()).await?;
-        let market_state = self.db.get_prediction_state(bet.prediction).await?;
-        match market_state {
-            MarketState::Trading => {
-                if self.db.get_trading_end(bet.prediction).await? < Utc::now() {
-                    self.db
-                        .set_prediction_state(bet.prediction, MarketState::WaitingForDecision)
-                        .await?;
-                    bail!("Wrong market state");
-                }
+    /// Makes the decision of a prediction final once nobody disputed it in
+    /// time or the appeal judges didn't reach a verdict in time. Returns
+    /// whether that happened.
+    async fn finish_dispute_period_if_over(&self, prediction: RowId) -> Result<bool> {
+        let start = match self.db.get_dispute(prediction).await? {
+            Some((_, _, time)) => time,
+            None => self.db.get_resolution_time(prediction).await?,
+        };
+        if start + self.db.get_dispute_period(prediction).await? < Utc::now() {
+            self.finish_dispute(prediction, None).await?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+    /// Resolves a prediction after the dispute period with the verdict of the
+    /// appeal judges or the decision of the judges without a verdict. If the
+    /// appeal confirms the decision the disputer loses the dispute bond to
+    /// the judges that made it.
+    async fn finish_dispute(&self, prediction: RowId, verdict: Option<Outcome>) -> Result<()> {
+        let decision = match self.db.get_prediction_state(prediction).await? {
+            MarketState::WaitingForDisputes(outcome) | MarketState::Disputed(outcome) => outcome,
+            _ => bail!("Wrong market state"),
+        };
+        let outcome = verdict.unwrap_or(decision);
+        self.db
+            .set_prediction_state(prediction, MarketState::Resolved(outcome))
+            .await?;
+        info!("Decision {} on prediction {} is final", outcome, prediction);
+        let (mut cash_out, mut dust) = self.calculate_cash_out(prediction).await?;
+        if let (Some((disputer, bond, _)), Some(verdict)) =
+            (self.db.get_dispute(prediction).await?, verdict)
+        {
+            if verdict == decision && bond > 0 {
+                let judges: Vec<UserPubKey> = self
+                    .db
+                    .get_prediction_judges_mapped(prediction)
+                    .await?
+                    .into_iter()
+                    .filter(|(_, state)| *state == JudgeState::Resolved(decision))
+                    .map(|(judge, _)| judge)
+                    .collect();
+                cash_out
+                    .entry(disputer)
+                    .or_default()
+                    .add(LedgerKind::Bond, None, -bond);
+                let rest = split_evenly(bond, &judges, LedgerKind::Slash, &mut cash_out);
+                dust += self.credit_house(rest, &mut cash_out);
             }
-            MarketState::Refunded(_) => {
-                //TODO what needs to happen here?
+        }
+        self.apply_cash_out(prediction, cash_out, dust).await?;
+        self.db
+            .set_bets_status(prediction, BetStatus::Settled)
+            .await
+    }
+    async fn try_resolve_scalar(&mut self, prediction: RowId) -> Result<()> {
+        let deadline_passed = self.get_decision_deadline(prediction).await? < Utc::now();
+        let mut values = vec![];
+        for state in self.db.get_judge_states(prediction).await? {
+            match state {
+                JudgeState::Accepted | JudgeState::Committed if !deadline_passed => {
+                    return Ok(());
+                }
+                JudgeState::ResolvedScalar(value) => values.push(value),
+                _ => {}
             }
-            _ => bail!("Wrong market state"),
         }
-        self.db.remove_bet(id).await?;
-        debug!("Cancelled bet {} by {}", id, access.user);
+        if deadline_passed
+            && values.len() < self.db.get_resolution_rules(prediction).await?.quorum as usize
+        {
+            return Ok(());
+        }
+        let Some(value) = median(&mut values) else {
+            return Ok(());
+        };
+        self.db
+            .set_prediction_state(prediction, MarketState::ResolvedScalar(value))
+            .await?;
+        let (cash_out, dust) = self.calculate_cash_out(prediction).await?;
+        self.apply_cash_out(prediction, cash_out, dust).await?;
+        self.db
+            .set_bets_status(prediction, BetStatus::Settled)
+            .await?;
         Ok(())
     }
-    async fn get_outcome_judge_count(&self, prediction: RowId) -> Result<u32> {
-        if let MarketState::Resolved(outcome) = self.db.get_prediction_state(prediction).await? {
-            let mut count = 0;
-            for state in self.db.get_judge_states(prediction).await? {
-                if let JudgeState::Resolved(decision) = state {
-                    if decision == outcome {
-                        count += 1;
+    /// Posts the settlement against the escrow of the prediction and records
+    /// every entry in the ledger. Stakes are already in the escrow, judge fees
+    /// go to the judge pool and platform and creator fees to the fee account,
+    /// from where they are paid out in separate transactions. `dust` goes to
+    /// the dust account.
+    async fn apply_cash_out(
+        &self,
+        prediction: RowId,
+        cash_out: HashMap<UserPubKey, Settlement>,
+        dust: Sats,
+    ) -> Result<()> {
+        let time = Utc::now();
+        let mut postings = vec![];
+        let mut judge_fees = vec![];
+        let mut fees = vec![];
+        for (user, settlement) in cash_out.iter() {
+            let mut credit = 0;
+            for (kind, _, amount) in settlement.entries.iter() {
+                match kind {
+                    LedgerKind::Stake => continue,
+                    LedgerKind::JudgeFee => {
+                        postings.push((Account::JudgePool(prediction), *amount));
+                        judge_fees.push((Account::User(*user), *amount));
                     }
+                    LedgerKind::PlatformFee | LedgerKind::CreatorFee => {
+                        postings.push((Account::Fees, *amount));
+                        fees.push((Account::User(*user), *amount));
+                    }
+                    _ => credit += amount,
                 }
+                postings.push((Account::Escrow(prediction), -amount));
             }
-            return Ok(count);
+            postings.push((Account::User(*user), credit));
         }
-        bail!("Wrong market state")
+        if dust != 0 {
+            postings.push((Account::Escrow(prediction), -dust));
+            postings.push((Account::Dust, dust));
+        }
+        self.db
+            .post_transaction("Settlement", Some(prediction), &postings)
+            .await?;
+        self.pay_out_pool(prediction, Account::JudgePool(prediction), judge_fees)
+            .await?;
+        self.pay_out_pool(prediction, Account::Fees, fees).await?;
+        for (user, settlement) in cash_out {
+            let new_balance = self.db.get_user_balance(user).await?;
+            if new_balance.is_negative() {
+                error!(
+                    "User {} has new balance {} after cash_out",
+                    user, new_balance
+                )
+            }
+            for (kind, bet, amount) in settlement.entries {
+                self.db
+                    .add_ledger_entry(user, prediction, bet, kind, amount, time)
+                    .await?;
+            }
+        }
+        Ok(())
     }
-    pub async fn get_predictions(&self) -> Result<HashMap<RowId, PredictionOverviewResponse>> {
-        self.db.get_predictions().await
+    /// Pays the fees a settlement put into `pool` out to their receivers
+    async fn pay_out_pool(
+        &self,
+        prediction: RowId,
+        pool: Account,
+        mut payouts: Vec<(Account, Sats)>,
+    ) -> Result<()> {
+        let total: Sats = payouts.iter().map(|(_, amount)| amount).sum();
+        if total == 0 {
+            return Ok(());
+        }
+        payouts.push((pool, -total));
+        let description = match pool {
+            Account::JudgePool(_) => "Judge fees",
+            _ => "Fees",
+        };
+        self.db
+            .post_transaction(description, Some(prediction), &payouts)
+            .await?;
+        Ok(())
     }
-    pub async fn get_prediction_overview(
+    /// Settlements of every user and the dust nobody is entitled to
+    async fn calculate_cash_out(
         &self,
         prediction: RowId,
-    ) -> Result<PredictionOverviewResponse> {
-        self.db.get_prediction_overview(prediction).await
+    ) -> Result<(HashMap<UserPubKey, Settlement>, Sats)> {
+        match self.db.get_prediction_state(prediction).await? {
+            MarketState::Resolved(outcome) => {
+                if self.db.get_amm_subsidy(prediction).await?.is_some() {
+                    return self.calculate_amm_cash_out(prediction, outcome).await;
+                }
+                self.calculate_outcome_cash_out(prediction, outcome).await
+            }
+            MarketState::ResolvedScalar(value) => {
+                self.calculate_scalar_cash_out(prediction, value).await
+            }
+            _ => bail!("Market not resolved"),
+        }
     }
-
-    pub async fn get_prediction_bets_aggregated(
+    /// The judges get their share of the pool and the fees get taken out of
+    /// it, the rest goes to the winning bets by their amount
+    async fn calculate_outcome_cash_out(
         &self,
         prediction: RowId,
-        bet: bool,
-    ) -> Result<Sats> {
+        outcome: Outcome,
+    ) -> Result<(HashMap<UserPubKey, Settlement>, Sats)> {
         let bets = self
             .db
-            .get_prediction_bets_aggregated(prediction, bet)
+            .get_bets(Some(prediction), None, false, PageRequest::default())
             .await?;
-        Ok(bets.values().sum())
-    }
-    pub async fn get_prediction_ratio(&self, prediction: RowId) -> Result<(Sats, Sats)> {
-        self.db.get_prediction_ratio(prediction).await
-    }
-    pub async fn get_prediction_judges(&self, prediction: RowId) -> Result<Vec<Judge>> {
-        self.db.get_prediction_judges(prediction).await
+        let positions = self.db.get_positions(Some(prediction), None).await?;
+        let judges = self.get_outcome_judges(prediction).await?;
+        let (mut user_cash_outs, dust, cash_out_amount, pool_amount) = self
+            .split_outcome_pool(prediction, outcome, &bets, &positions, &judges)
+            .await?;
+        self.slash_minority_judges(prediction, outcome, &mut user_cash_outs)
+            .await?;
+        self.check_solvency(prediction, cash_out_amount + dust, pool_amount)
+            .await?;
+        let dust = self.credit_house(dust, &mut user_cash_outs);
+        Ok((user_cash_outs, dust))
     }
-    pub async fn force_decision_period(
+    /// Splits the bets and order book positions of a prediction as if it
+    /// resolved to `outcome` with the judge share going to `judges`. Returns
+    /// the settlements, the dust, the sats paid out and the sats in the pool.
+    async fn split_outcome_pool(
         &self,
         prediction: RowId,
-        access: AccessRequest,
-    ) -> Result<()> {
-        if let UserRole::User = self.check_access(access.clone()).await? {
-            bail!("Access Denied: Admin only API");
+        outcome: Outcome,
+        bets: &[Bet],
+        positions: &[Position],
+        judges: &[UserPubKey],
+    ) -> Result<(HashMap<UserPubKey, Settlement>, Sats, Sats, Sats)> {
+        let outcome_amount: Sats = bets
+            .iter()
+            .filter(|bet| bet.outcome == outcome)
+            .map(|bet| bet.amount)
+            .sum();
+        let non_outcome_amount: Sats = bets
+            .iter()
+            .filter(|bet| bet.outcome != outcome)
+            .map(|bet| bet.amount)
+            .sum();
+        let bet_amount = outcome_amount + non_outcome_amount;
+        let judge_amount =
+            calculate_fee(bet_amount, self.db.get_judge_share_ppm(prediction).await?);
+        let mut user_cash_outs: HashMap<UserPubKey, Settlement> = HashMap::new();
+        let mut dust = 0;
+
+        // Calculate fees
+        let fee_cash_out_amount = self
+            .calculate_fee_cash_outs(prediction, bet_amount, &mut user_cash_outs)
+            .await?;
+
+        // Calculate users
+        let winnings = bet_amount - judge_amount - fee_cash_out_amount;
+        let weights: Vec<Sats> = bets
+            .iter()
+            .map(|bet| {
+                if bet.outcome == outcome {
+                    bet.amount
+                } else {
+                    0
+                }
+            })
+            .collect();
+        if outcome_amount == 0 {
+            dust += winnings;
         }
-        match self.db.get_prediction_state(prediction).await? {
-            MarketState::Trading => {
-                warn!(
-                    "{} forced the end of the decision period for prediction {}",
-                    access.user, prediction
-                );
-                self.db
-                    .set_prediction_state(prediction, MarketState::WaitingForDecision)
-                    .await
-            }
-            _ => bail!("Wrong market state"),
+        let mut user_cash_out_amount = 0;
+        for (bet, cash_out) in bets.iter().zip(distribute(winnings, &weights)) {
+            user_cash_out_amount += cash_out;
+            let settlement = user_cash_outs.entry(bet.user).or_default();
+            settlement.add(LedgerKind::Stake, Some(bet.id), -bet.amount);
+            settlement.add(LedgerKind::Payout, Some(bet.id), cash_out);
+        }
+
+        // Calculate order book positions. These pay out in full and are
+        // covered by what both sides of every match paid.
+        let mut position_cash_out_amount = 0;
+        let mut position_amount = 0;
+        for position in positions {
+            let cash_out = if position.outcome == outcome {
+                position.contracts * CONTRACT_SATS
+            } else {
+                0
+            };
+            position_cash_out_amount += cash_out;
+            position_amount += position.cost;
+            let settlement = user_cash_outs.entry(position.user).or_default();
+            settlement.add(LedgerKind::Stake, None, -position.cost);
+            settlement.add(LedgerKind::Payout, None, cash_out);
         }
+
+        // Calculate judges
+        let judge_dust = split_evenly(
+            judge_amount,
+            judges,
+            LedgerKind::JudgeFee,
+            &mut user_cash_outs,
+        );
+        let judge_cash_out_amount = judge_amount - judge_dust;
+        dust += judge_dust;
+
+        let cash_out_amount = user_cash_out_amount
+            + judge_cash_out_amount
+            + position_cash_out_amount
+            + fee_cash_out_amount;
+        let pool_amount = bet_amount + position_amount;
+        Ok((user_cash_outs, dust, cash_out_amount, pool_amount))
     }
-    pub async fn check_access(&self, access: AccessRequest) -> Result<UserRole> {
-        if self.disable_auth {
-            return Ok(UserRole::Root);
+    /// The pool without the judge share and fees gets split between long and
+    /// short bets by where `value` lies inside of the range. If nobody bet on
+    /// one side the other side gets the whole pool.
+    async fn calculate_scalar_cash_out(
+        &self,
+        prediction: RowId,
+        value: i64,
+    ) -> Result<(HashMap<UserPubKey, Settlement>, Sats)> {
+        let range = self
+            .db
+            .get_scalar_range(prediction)
+            .await?
+            .ok_or(anyhow!("Prediction {} is not a scalar market", prediction))?;
+        let judge_share_ppm = self.db.get_judge_share_ppm(prediction).await?;
+        let bets = self
+            .db
+            .get_bets(Some(prediction), None, false, PageRequest::default())
+            .await?;
+        let long_amount: Sats = bets
+            .iter()
+            .filter(|bet| bet.outcome == SCALAR_LONG)
+            .map(|bet| bet.amount)
+            .sum();
+        let short_amount: Sats = bets
+            .iter()
+            .filter(|bet| bet.outcome == SCALAR_SHORT)
+            .map(|bet| bet.amount)
+            .sum();
+        let pool_amount = long_amount + short_amount;
+        let judge_amount = calculate_fee(pool_amount, judge_share_ppm);
+        let mut user_cash_outs: HashMap<UserPubKey, Settlement> = HashMap::new();
+
+        // Calculate fees
+        let fee_cash_out_amount = self
+            .calculate_fee_cash_outs(prediction, pool_amount, &mut user_cash_outs)
+            .await?;
+
+        // Calculate users
+        let winnings = pool_amount - judge_amount - fee_cash_out_amount;
+        let (long_winnings, short_winnings) = if short_amount == 0 {
+            (winnings, 0)
+        } else if long_amount == 0 {
+            (0, winnings)
+        } else {
+            let value = value.clamp(range.min, range.max);
+            let sides = distribute(winnings, &[value - range.min, range.max - value]);
+            (sides[0], sides[1])
+        };
+        let side_weights = |side: Outcome| -> Vec<Sats> {
+            bets.iter()
+                .map(|bet| if bet.outcome == side { bet.amount } else { 0 })
+                .collect()
+        };
+        let long_cash_outs = distribute(long_winnings, &side_weights(SCALAR_LONG));
+        let short_cash_outs = distribute(short_winnings, &side_weights(SCALAR_SHORT));
+        let mut user_cash_out_amount = 0;
+        for (index, bet) in bets.iter().enumerate() {
+            let cash_out = long_cash_outs[index] + short_cash_outs[index];
+            user_cash_out_amount += cash_out;
+            let settlement = user_cash_outs.entry(bet.user).or_default();
+            settlement.add(LedgerKind::Stake, Some(bet.id), -bet.amount);
+            settlement.add(LedgerKind::Payout, Some(bet.id), cash_out);
         }
-        let (db_sig, last_access) = self
+
+        // Calculate judges. Every judge that voted gets an equal share.
+        let judges: Vec<UserPubKey> = self
             .db
-            .get_last_access(access.user, access.challenge)
-            .await
-            .context("Error getting session from db")?;
-        if access.sig != db_sig {
-            debug!(
-                "User {} tried to access with invalid access token",
-                access.user
+            .get_prediction_judges_mapped(prediction)
+            .await?
+            .into_iter()
+            .filter_map(|(judge, state)| match state {
+                JudgeState::ResolvedScalar(_) => Some(judge),
+                _ => None,
+            })
+            .collect();
+        let dust = split_evenly(
+            judge_amount,
+            &judges,
+            LedgerKind::JudgeFee,
+            &mut user_cash_outs,
+        );
+        let judge_cash_out_amount = judge_amount - dust;
+
+        let cash_out_amount = user_cash_out_amount + judge_cash_out_amount + fee_cash_out_amount;
+        self.check_solvency(prediction, cash_out_amount + dust, pool_amount)
+            .await?;
+        let dust = self.credit_house(dust, &mut user_cash_outs);
+        Ok((user_cash_outs, dust))
+    }
+    /// Pays one sat per winning share. The judges get their share of the
+    /// subsidy, the house gets the platform fee from it and whatever is left
+    /// of the market makers funds goes back to the creator.
+    async fn calculate_amm_cash_out(
+        &self,
+        prediction: RowId,
+        outcome: Outcome,
+    ) -> Result<(HashMap<UserPubKey, Settlement>, Sats)> {
+        let subsidy = self
+            .db
+            .get_amm_subsidy(prediction)
+            .await?
+            .ok_or(anyhow!("Prediction {} has no market maker", prediction))?;
+        let creator = self
+            .db
+            .get_creator(prediction)
+            .await?
+            .ok_or(anyhow!("Prediction {} has no creator", prediction))?;
+        let trade_amount: Sats = self
+            .db
+            .get_prediction_trade_costs(prediction)
+            .await?
+            .values()
+            .sum();
+        let pool_amount = subsidy + trade_amount;
+
+        // Calculate share holders
+        let mut user_cash_outs: HashMap<UserPubKey, Settlement> = HashMap::new();
+        let mut user_cash_out_amount = 0;
+        for holding in self.db.get_shares(Some(prediction), None).await? {
+            if holding.outcome != outcome || holding.shares <= 0 {
+                continue;
+            }
+            user_cash_out_amount += holding.shares;
+            user_cash_outs.entry(holding.user).or_default().add(
+                LedgerKind::Payout,
+                None,
+                holding.shares,
             );
-            bail!("Access token for user {} is invalid", access.user)
         }
-        if last_access < Utc::now() - Duration::days(7) {
-            debug!(
-                "User {} tried to access after more than 7 days",
-                access.user
+
+        // Calculate judges
+        let judge_amount = calculate_fee(subsidy, self.db.get_judge_share_ppm(prediction).await?);
+        let outcome_judges = self.get_outcome_judges(prediction).await?;
+        let judge_cash_out_amount = judge_amount
+            - split_evenly(
+                judge_amount,
+                &outcome_judges,
+                LedgerKind::JudgeFee,
+                &mut user_cash_outs,
+            );
+        self.slash_minority_judges(prediction, outcome, &mut user_cash_outs)
+            .await?;
+        let mut fee_cash_out_amount = 0;
+        if let Some(house) = self.platform_fee.house_account {
+            let (platform_fee_ppm, _) = self.db.get_fee_ppm(prediction).await?;
+            fee_cash_out_amount = calculate_fee(subsidy, platform_fee_ppm);
+            user_cash_outs.entry(house).or_default().add(
+                LedgerKind::PlatformFee,
+                None,
+                fee_cash_out_amount,
             );
-            bail!("Last access was more than 7 days ago")
         }
-        let role = self.db.get_user_role(access.user).await?;
-        Ok(role)
-    }
-    pub async fn create_login_challenge(&mut self, user: UserPubKey) -> Result<String> {
-        let challenge: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(30)
-            .map(char::from)
-            .collect();
-        trace!("Generated login challenge {}", challenge);
-        self.db.create_session(user, challenge.clone()).await?;
-        Ok(challenge)
-    }
-    pub async fn try_login(
-        &mut self,
-        user: UserPubKey,
-        sig: Signature,
-        challenge: String,
-    ) -> Result<()> {
-        sig.verify(
-            &Message::from_hashed_data::<Hash>(challenge.as_bytes()),
-            &user,
-        )?;
-        self.db.update_access_token(user, sig, challenge).await?;
-        debug!("User {} successfully logged in", user);
-        Ok(())
+
+        // Return the rest to the creator
+        let cash_out_amount = user_cash_out_amount + judge_cash_out_amount + fee_cash_out_amount;
+        let rest = (pool_amount - cash_out_amount).max(0);
+        self.check_solvency(prediction, cash_out_amount + rest, pool_amount)
+            .await?;
+        user_cash_outs
+            .entry(creator)
+            .or_default()
+            .add(LedgerKind::Subsidy, None, rest);
+        Ok((user_cash_outs, 0))
     }
-    pub async fn update_user(
+    /// Credits the platform fee to the house account and the creator fee to
+    /// the creator of the prediction. Returns the sats paid out as fees.
+    async fn calculate_fee_cash_outs(
         &self,
-        user: UserPubKey,
-        name: Option<String>,
-        access: AccessRequest,
-    ) -> Result<()> {
-        self.check_access_for_user(user, access).await?;
-        if let Some(name) = name {
-            self.db.update_username(user, name).await?;
+        prediction: RowId,
+        pool_amount: Sats,
+        user_cash_outs: &mut HashMap<UserPubKey, Settlement>,
+    ) -> Result<Sats> {
+        let (platform_fee_ppm, creator_fee_ppm) = self.db.get_fee_ppm(prediction).await?;
+        let mut fee_cash_out_amount = 0;
+        if let Some(house) = self.platform_fee.house_account {
+            let fee = calculate_fee(pool_amount, platform_fee_ppm);
+            fee_cash_out_amount += fee;
+            user_cash_outs
+                .entry(house)
+                .or_default()
+                .add(LedgerKind::PlatformFee, None, fee);
         }
-        Ok(())
+        if let Some(creator) = self.db.get_creator(prediction).await? {
+            let fee = calculate_fee(pool_amount, creator_fee_ppm);
+            fee_cash_out_amount += fee;
+            user_cash_outs
+                .entry(creator)
+                .or_default()
+                .add(LedgerKind::CreatorFee, None, fee);
+        }
+        Ok(fee_cash_out_amount)
     }
-    /// Action can only be executed from logged in users for themselves
-    /// or from logged in admins
-    pub async fn check_access_for_user(
+    /// Credits the dust of a settlement to the house account. Returns the
+    /// dust that has to go to the dust account if there is no house account.
+    fn credit_house(
         &self,
-        user: UserPubKey,
-        access: AccessRequest,
-    ) -> Result<()> {
-        if let UserRole::User = self.check_access(access.clone()).await? {
-            if user != access.user {
-                bail!("Access Denied: Cannot issue request on behalf of other users");
+        dust: Sats,
+        user_cash_outs: &mut HashMap<UserPubKey, Settlement>,
+    ) -> Sats {
+        match self.platform_fee.house_account {
+            Some(house) => {
+                user_cash_outs
+                    .entry(house)
+                    .or_default()
+                    .add(LedgerKind::Remainder, None, dust);
+                0
             }
+            None => dust,
         }
-        Ok(())
-    }
-    pub async fn get_username(&self, user: UserPubKey) -> Result<Option<String>> {
-        self.db.get_username(user).await
-    }
-    pub async fn get_user(&self, user: UserPubKey, access: AccessRequest) -> Result<UserResponse> {
-        self.check_access_for_user(user, access).await?;
-        self.db.get_user(user).await
-    }
-    pub async fn get_judges(
-        &self,
-        prediction: Option<RowId>,
-        user: Option<UserPubKey>,
-    ) -> Result<Vec<JudgePublic>> {
-        self.db.get_judges(prediction, user).await
     }
-    pub async fn get_judge(
+    /// Slashes the bonds of judges that voted against the final outcome and
+    /// splits them between the judges that voted for it
+    async fn slash_minority_judges(
         &self,
         prediction: RowId,
-        user: UserPubKey,
-        access: AccessRequest,
-    ) -> Result<Judge> {
-        self.check_access_for_user(user, access).await?;
-        let state = self.db.get_judge_state(prediction, user).await?;
-        Ok(Judge {
-            user,
-            prediction,
-            state,
-        })
+        outcome: Outcome,
+        user_cash_outs: &mut HashMap<UserPubKey, Settlement>,
+    ) -> Result<()> {
+        let bond = self.db.get_judge_bond(prediction).await?;
+        if bond == 0 {
+            return Ok(());
+        }
+        let mut majority = vec![];
+        let mut minority = vec![];
+        for (judge, state) in self.db.get_prediction_judges_mapped(prediction).await? {
+            match state {
+                JudgeState::Resolved(decision) if decision == outcome => majority.push(judge),
+                // Judges that didn't reveal their commitment in time
+                JudgeState::Resolved(_) | JudgeState::Accepted | JudgeState::Committed => {
+                    minority.push(judge)
+                }
+                _ => {}
+            }
+        }
+        if minority.is_empty() {
+            return Ok(());
+        }
+        // If the appeal overturned every judge the disputer gets the bonds
+        if majority.is_empty() {
+            match self.db.get_dispute(prediction).await? {
+                Some((disputer, _, _)) => majority.push(disputer),
+                None => return Ok(()),
+            }
+        }
+        let slashed = bond * minority.len() as Sats;
+        for judge in minority.iter() {
+            user_cash_outs
+                .entry(*judge)
+                .or_default()
+                .add(LedgerKind::Bond, None, -bond);
+        }
+        split_evenly(slashed, &majority, LedgerKind::Slash, user_cash_outs);
+        info!(
+            "Slashed bonds of {} minority judges on prediction {}",
+            minority.len(),
+            prediction
+        );
+        Ok(())
     }
-    pub async fn get_bets(
-        &self,
-        prediction: Option<RowId>,
-        user: Option<UserPubKey>,
-        access: AccessRequest,
-    ) -> Result<Vec<Bet>> {
-        if let Some(user) = user {
-            self.check_access_for_user(user, access).await?;
+    /// Slashes the bonds of judges that didn't vote before the decision period
+    /// ran out. The bonds are split between the judges that did vote or, if
+    /// nobody voted, between the bettors by their stake.
+    async fn slash_absent_judges(&self, prediction: RowId) -> Result<()> {
+        let bond = self.db.get_judge_bond(prediction).await?;
+        if bond == 0 {
+            return Ok(());
+        }
+        let mut voted = vec![];
+        let mut absent = vec![];
+        for (judge, state) in self.db.get_prediction_judges_mapped(prediction).await? {
+            match state {
+                JudgeState::Resolved(_) | JudgeState::ResolvedScalar(_) => voted.push(judge),
+                JudgeState::Accepted | JudgeState::Committed => absent.push(judge),
+                _ => {}
+            }
+        }
+        if absent.is_empty() {
+            return Ok(());
+        }
+        let slashed = bond * absent.len() as Sats;
+        let mut receivers: HashMap<UserPubKey, Sats> = HashMap::new();
+        if voted.is_empty() {
+            let bets = self
+                .db
+                .get_bets(Some(prediction), None, false, PageRequest::default())
+                .await?;
+            let weights: Vec<Sats> = bets.iter().map(|bet| bet.amount).collect();
+            for (bet, share) in bets.iter().zip(distribute(slashed, &weights)) {
+                *receivers.entry(bet.user).or_default() += share;
+            }
         } else {
-            if let UserRole::User = self.check_access(access).await? {
-                bail!("Access Denied: Getting bets of users is prohibited");
+            for (judge, share) in voted.iter().zip(distribute(slashed, &vec![1; voted.len()])) {
+                receivers.insert(*judge, share);
             }
         }
-        let bets = self.db.get_bets(prediction, user, vec![]).await?;
-        Ok(bets)
+        if receivers.is_empty() {
+            return Ok(());
+        }
+        let mut cash_out: HashMap<UserPubKey, Settlement> = HashMap::new();
+        for judge in absent.iter() {
+            cash_out
+                .entry(*judge)
+                .or_default()
+                .add(LedgerKind::Bond, None, -bond);
+        }
+        for (user, amount) in receivers {
+            cash_out
+                .entry(user)
+                .or_default()
+                .add(LedgerKind::Slash, None, amount);
+        }
+        info!(
+            "Slashed bonds of {} judges that didn't vote on prediction {}",
+            absent.len(),
+            prediction
+        );
+        self.apply_cash_out(prediction, cash_out, 0).await
     }
-    pub async fn get_balance(&self, user: UserPubKey, access: AccessRequest) -> Result<Sats> {
-        self.check_access_for_user(user, access).await?;
-        let balance = self.db.get_user_balance(user).await?;
-        Ok(balance)
+    /// Returns the funds of a prediction that won't be resolved. Bets are
+    /// kept as refunded and market maker trades get reversed including the
+    /// subsidy.
+    async fn refund_prediction(&self, prediction: RowId) -> Result<()> {
+        let mut refunds: HashMap<UserPubKey, Settlement> = HashMap::new();
+        for bet in self
+            .db
+            .get_bets(Some(prediction), None, false, PageRequest::default())
+            .await?
+        {
+            let settlement = refunds.entry(bet.user).or_default();
+            settlement.add(LedgerKind::Stake, Some(bet.id), -bet.amount);
+            settlement.add(LedgerKind::Refund, Some(bet.id), bet.amount);
+        }
+        for position in self.db.get_positions(Some(prediction), None).await? {
+            let settlement = refunds.entry(position.user).or_default();
+            settlement.add(LedgerKind::Stake, None, -position.cost);
+            settlement.add(LedgerKind::Refund, None, position.cost);
+        }
+        self.db
+            .set_bets_status(prediction, BetStatus::Refunded)
+            .await?;
+        self.db.remove_orders(prediction).await?;
+        if let Some(subsidy) = self.db.get_amm_subsidy(prediction).await? {
+            for (user, cost) in self.db.get_prediction_trade_costs(prediction).await? {
+                refunds
+                    .entry(user)
+                    .or_default()
+                    .add(LedgerKind::Refund, None, cost);
+            }
+            self.db.remove_share_trades(prediction).await?;
+            if let Some(creator) = self.db.get_creator(prediction).await? {
+                refunds
+                    .entry(creator)
+                    .or_default()
+                    .add(LedgerKind::Refund, None, subsidy);
+            }
+        }
+        self.apply_cash_out(prediction, refunds, 0).await
     }
-    pub async fn get_available_balance(
+    /// Refunds all bets if a cash out doesn't hand out exactly the sats that
+    /// are in the pool
+    async fn check_solvency(
         &self,
-        user: UserPubKey,
-        access: AccessRequest,
-    ) -> Result<Sats> {
-        self.check_access_for_user(user, access).await?;
-        let balance = self.db.get_user_balance(user).await?;
-        let user_bets: Sats = self.db.get_user_bets_aggregated(user).await?.values().sum();
-        Ok(balance - user_bets)
+        prediction: RowId,
+        cash_out_amount: Sats,
+        pool_amount: Sats,
+    ) -> Result<()> {
+        if cash_out_amount != pool_amount {
+            self.db
+                .set_prediction_state(prediction, MarketState::Refunded(RefundReason::Insolvency))
+                .await?;
+            error!(
+                "For some reason the cash out calculation for prediction {} \
+               didn't add up. Bets are being refunded",
+                prediction
+            );
+            error!("The following should be true but wasn't:");
+            error!("cash_out_amount == pool_amount");
+            error!("{} == {}", cash_out_amount, pool_amount);
+            //Execute refund
+            self.refund_prediction(prediction).await?;
+            bail!(
+                "For some reason the cash out calculation for prediction {} \
+              didn't add up. Bets are being refunded",
+                prediction
+            )
+        }
+        Ok(())
     }
-    pub async fn adjust_balance(
-        &self,
-        user: UserPubKey,
-        amount: Sats,
-        access: AccessRequest,
-    ) -> Result<Sats> {
-        if let UserRole::User = self.check_access(access).await? {
-            bail!("Access Denied: Operation only permitted for admins");
+    async fn try_activate_trading(&mut self, prediction: RowId) -> Result<()> {
+        let mut accepted_count = 0;
+        for state in self
+            .db
+            .get_judge_states(prediction)
+            .await
+            .context("failed to get judge states")?
+        {
+            if state == JudgeState::Accepted {
+                accepted_count += 1;
+            }
         }
-        let new_balance = self.db.adjust_user_balance(user, amount).await?;
-        warn!("Adjusted balance for {}: {}", user, amount);
-        Ok(new_balance)
+        if accepted_count
+            == self
+                .db
+                .get_judge_count(prediction)
+                .await
+                .context("failed to get judge count")?
+        {
+            self.db
+                .set_prediction_state(prediction, MarketState::Trading)
+                .await?;
+        }
+        Ok(())
     }
-    pub async fn init_withdrawal_bolt11(
-        &self,
+    pub async fn add_bet(
+        &mut self,
+        prediction: RowId,
         user: UserPubKey,
-        invoice: Invoice,
+        outcome: Outcome,
         amount: Sats,
         access: AccessRequest,
-    ) -> Result<RowId> {
+    ) -> Result<()> {
         self.check_access_for_user(user, access.clone()).await?;
-        if amount <= 0 {
-            bail!("Amount has to be positive");
+        self.check_outcome(prediction, outcome).await?;
+        if self.db.get_amm_subsidy(prediction).await?.is_some() {
+            bail!("Prediction uses a market maker. Buy shares instead");
         }
-        //TODO make balance check atomic with the balance adjustment
-        let invoice_amount = self.funding.decode_bolt11(invoice.clone()).await?;
-        if invoice_amount != amount {
-            bail!("Invoice and form have differing ammounts")
+        match self.get_active_state(prediction).await? {
+            MarketState::Trading => {
+                if self.end_trading_if_due(prediction).await? {
+                    debug!("Triggered trading end because someone tried betting after trading end");
+                    bail!("Trading ended");
+                }
+            }
+            _ => bail!("Prediction has to be Trading to be able to bet on it"),
         }
-        let balance = self.db.get_user_balance(user).await?;
-        if balance - amount < 0 {
-            bail!("Not enough funds");
+        if amount <= 0 {
+            bail!("Amount has to be positive");
         }
-        self.adjust_balance(user, -amount, access).await?;
-        let hash = self.funding.pay_bolt11(invoice.clone(), amount).await?;
-        let tx = TxType::Bolt11 {
-            details: TxDetailsBolt11 {
-                payment_hash: hash.clone(),
-                payment_request: invoice,
-            },
-            state: TxStateBolt11::PayInit(amount),
-        };
-        let id = self.db.create_tx(user, TxDirection::Withdrawal, tx).await?;
+        self.db
+            .create_bet(prediction, user, outcome, amount)
+            .await?;
+        self.db
+            .add_pool_snapshot(prediction, outcome, amount)
+            .await?;
         debug!(
-            "Initiated Bolt11 Withdrawal: user:{} amount:{}",
-            user, amount
+            "Added {} sats bet on outcome {} and prediction {} for user {} by {}",
+            amount, outcome, prediction, user, access.user
         );
-        Ok(id)
+        Ok(())
     }
-    pub async fn init_deposit_bolt11(
+    /// Quotes the payout of a bet on the current pools with the same math
+    /// as the settlement
+    pub async fn quote_payout(
         &self,
-        user: UserPubKey,
-        amount: Sats,
+        request: PayoutQuoteRequest,
         access: AccessRequest,
-    ) -> Result<(RowId, Invoice)> {
-        self.check_access_for_user(user, access.clone()).await?;
-        if amount <= 0 {
-            bail!("Amount has to be positive");
+    ) -> Result<PayoutQuoteResponse> {
+        match request.user {
+            Some(user) => self.check_access_for_user(user, access).await?,
+            None => {
+                self.check_access(access).await?;
+            }
         }
-        let (hash, invoice) = self.funding.create_bolt11(amount).await?;
-        let tx = TxType::Bolt11 {
-            details: TxDetailsBolt11 {
-                payment_hash: hash.clone(),
-                payment_request: invoice.clone(),
-            },
-            state: TxStateBolt11::PayInit(amount),
-        };
-        let id = self.db.create_tx(user, TxDirection::Deposit, tx).await?;
-        debug!("Initiated Bolt11 Deposit: user:{}, amount:{}", user, amount);
-        Ok((id, invoice))
+        self.project_payout(&request).await
     }
-    pub async fn check_tx(&self, id: RowId, access: AccessRequest) -> Result<Tx> {
-        let mut tx = self.db.get_tx(id).await?;
-        self.check_access_for_user(tx.user, access).await?;
-        match tx.direction {
-            TxDirection::Withdrawal => self.check_withdrawal(id, tx).await,
-            TxDirection::Deposit => self.check_deposit(id, tx).await,
+    async fn project_payout(&self, request: &PayoutQuoteRequest) -> Result<PayoutQuoteResponse> {
+        let prediction = request.prediction;
+        self.check_outcome(prediction, request.outcome).await?;
+        if self.db.get_amm_subsidy(prediction).await?.is_some() {
+            bail!("Prediction uses a market maker. Use the share prices instead");
+        }
+        if self.db.get_scalar_range(prediction).await?.is_some() {
+            bail!("Payouts of scalar predictions depend on the value the judges decide on");
+        }
+        if request.amount < 0 {
+            bail!("Amount can't be negative");
+        }
+        // The quoted bet gets an id no stored bet has. Without a user it is
+        // placed for a throwaway key so it can't mix with anyone's settlement.
+        let user = match request.user {
+            Some(user) => user,
+            None => UserPubKey::from_secret_key_global(&SecretKey::new(&mut rand::thread_rng())),
+        };
+        let quote = Bet {
+            id: 0,
+            user,
+            prediction,
+            outcome: request.outcome,
+            amount: request.amount,
+            sale_price: None,
+            status: BetStatus::Open,
+        };
+        let mut bets = self
+            .db
+            .get_bets(Some(prediction), None, false, PageRequest::default())
+            .await?;
+        let positions = self.db.get_positions(Some(prediction), None).await?;
+        let owned = |user: &UserPubKey| Some(*user) == request.user;
+        let stake = request.amount
+            + bets
+                .iter()
+                .filter(|bet| owned(&bet.user) && bet.outcome == request.outcome)
+                .map(|bet| bet.amount)
+                .sum::<Sats>()
+            + positions
+                .iter()
+                .filter(|position| owned(&position.user) && position.outcome == request.outcome)
+                .map(|position| position.cost)
+                .sum::<Sats>();
+        if stake == 0 {
+            bail!("Nothing to quote without an amount or existing bets");
         }
+        if request.amount > 0 {
+            bets.push(quote);
+        }
+        let total_amount: Sats = bets.iter().map(|bet| bet.amount).sum();
+        let outcome_amount: Sats = bets
+            .iter()
+            .filter(|bet| bet.outcome == request.outcome)
+            .map(|bet| bet.amount)
+            .sum();
+        let (cash_outs, ..) = self
+            .split_outcome_pool(prediction, request.outcome, &bets, &positions, &[])
+            .await?;
+        let payout = cash_outs
+            .iter()
+            .flat_map(|(user, settlement)| {
+                settlement
+                    .entries
+                    .iter()
+                    .filter(move |(kind, bet, _)| {
+                        *kind == LedgerKind::Payout && (*bet == Some(0) || owned(user))
+                    })
+                    .map(|(_, _, amount)| amount)
+            })
+            .sum();
+        Ok(PayoutQuoteResponse {
+            stake,
+            payout,
+            implied_probability: ratio(outcome_amount, total_amount),
+            odds: ratio(payout, stake),
+        })
     }
-    pub async fn check_withdrawal(&self, id: RowId, tx: Tx) -> Result<Tx> {
-        match tx.clone().tx_type {
-            TxType::Bolt11 { details, state } => {
-                if let TxStateBolt11::Settled(_) = state {
-                    return Ok(tx);
+    /// Bets, order book positions and market maker shares of the user in
+    /// every prediction with what they would pay out, together with what the
+    /// user got from settled predictions
+    pub async fn get_portfolio(
+        &self,
+        user: UserPubKey,
+        access: AccessRequest,
+    ) -> Result<PortfolioResponse> {
+        self.check_access_for_user(user, access).await?;
+        let bets = self
+            .db
+            .get_bets(None, Some(user), false, PageRequest::default())
+            .await?;
+        let order_positions = self.db.get_positions(None, Some(user)).await?;
+        let share_trades = self.db.get_user_share_trades(user).await?;
+        let ledger = self
+            .db
+            .get_ledger(None, Some(user), PageRequest::default())
+            .await?;
+        let predictions: BTreeSet<RowId> = bets
+            .iter()
+            .map(|bet| bet.prediction)
+            .chain(order_positions.iter().map(|position| position.prediction))
+            .chain(share_trades.iter().map(|(prediction, ..)| *prediction))
+            .chain(ledger.iter().map(|entry| entry.prediction))
+            .collect();
+        let balance = self.db.get_user_balance(user).await?;
+        let mut portfolio = PortfolioResponse {
+            user,
+            balance,
+            available_balance: balance - self.db.get_user_reserved(user).await?,
+            positions: vec![],
+            open_stake: 0,
+            realized_pnl: 0,
+            refunds: 0,
+            judge_fees: 0,
+        };
+        for prediction in predictions {
+            let state = self.db.get_prediction_state(prediction).await?;
+            let outcomes = self.db.get_outcomes(prediction).await?.len();
+            let mut position = PortfolioPosition {
+                prediction,
+                name: self.db.get_prediction_overview(prediction).await?.name,
+                state,
+                stakes: vec![0; outcomes],
+                contracts: vec![0; outcomes],
+                shares: vec![0; outcomes],
+                projected_payouts: vec![],
+                realized_pnl: 0,
+                refunds: 0,
+                judge_fees: 0,
+            };
+            for bet in bets.iter().filter(|bet| bet.prediction == prediction) {
+                if let Some(stake) = position.stakes.get_mut(bet.outcome as usize) {
+                    *stake += bet.amount;
                 }
-                let mut new_state = self
-                    .funding
-                    .check_bolt11(details.payment_hash.clone())
-                    .await?;
-                if let TxStateBolt11::PayInit(pending_amount) = new_state {
-                    if tx.initiated < Utc::now() - Duration::minutes(10) {
-                        new_state = TxStateBolt11::Failed;
-                        self.db.adjust_user_balance(tx.user, pending_amount).await?;
-                        warn!("Marking withdrawal {} as failed", id);
+            }
+            for order_position in order_positions
+                .iter()
+                .filter(|order_position| order_position.prediction == prediction)
+            {
+                let outcome = order_position.outcome as usize;
+                if outcome < outcomes {
+                    position.stakes[outcome] += order_position.cost;
+                    position.contracts[outcome] += order_position.contracts;
+                }
+            }
+            for (_, outcome, shares, cost) in share_trades
+                .iter()
+                .filter(|(share_prediction, ..)| *share_prediction == prediction)
+            {
+                let outcome = *outcome as usize;
+                if outcome < outcomes {
+                    position.stakes[outcome] += cost;
+                    position.shares[outcome] += shares;
+                }
+            }
+            for entry in ledger.iter().filter(|entry| entry.prediction == prediction) {
+                // Market maker trades are booked when they happen, they only
+                // turn into profit or loss with the settlement
+                if !state.is_settled()
+                    && matches!(entry.kind, LedgerKind::Stake | LedgerKind::Payout)
+                {
+                    continue;
+                }
+                position.realized_pnl += entry.amount;
+                match entry.kind {
+                    LedgerKind::Refund => position.refunds += entry.amount,
+                    LedgerKind::JudgeFee => position.judge_fees += entry.amount,
+                    _ => {}
+                }
+            }
+            if !state.is_settled() {
+                portfolio.open_stake += position.stakes.iter().sum::<Sats>();
+                if self.db.get_amm_subsidy(prediction).await?.is_some() {
+                    // Every share pays out one sat
+                    position.projected_payouts = position.shares.clone();
+                } else if self.db.get_scalar_range(prediction).await?.is_none() {
+                    for outcome in 0..outcomes {
+                        let payout = if position.stakes[outcome] > 0 {
+                            let request = PayoutQuoteRequest {
+                                prediction,
+                                outcome: outcome as Outcome,
+                                amount: 0,
+                                user: Some(user),
+                            };
+                            self.project_payout(&request).await?.payout
+                        } else {
+                            0
+                        };
+                        position.projected_payouts.push(payout);
                     }
                 }
-                if state == new_state {
-                    return Ok(tx);
+            }
+            portfolio.realized_pnl += position.realized_pnl;
+            portfolio.refunds += position.refunds;
+            portfolio.judge_fees += position.judge_fees;
+            portfolio.positions.push(position);
+        }
+        Ok(portfolio)
+    }
+    /// Buys shares from the market maker. Returns the sats paid.
+    pub async fn buy_shares(
+        &mut self,
+        request: TradeSharesRequest,
+        access: AccessRequest,
+    ) -> Result<Sats> {
+        if request.shares <= 0 {
+            bail!("Amount of shares has to be positive");
+        }
+        let cost = self
+            .trade_shares(&request, request.shares, access.clone())
+            .await?;
+        if let Some(limit) = request.limit {
+            if cost > limit {
+                bail!("Buying would cost {} sats which is above {}", cost, limit);
+            }
+        }
+        self.db
+            .create_share_trade(
+                request.prediction,
+                request.user,
+                request.outcome,
+                request.shares,
+                cost,
+            )
+            .await?;
+        self.db
+            .add_ledger_entry(
+                request.user,
+                request.prediction,
+                None,
+                LedgerKind::Stake,
+                -cost,
+                Utc::now(),
+            )
+            .await?;
+        debug!(
+            "{} bought {} shares of outcome {} on prediction {} for {} sats by {}",
+            request.user, request.shares, request.outcome, request.prediction, cost, access.user
+        );
+        Ok(cost)
+    }
+    /// Sells shares to the market maker. Returns the sats received.
+    pub async fn sell_shares(
+        &mut self,
+        request: TradeSharesRequest,
+        access: AccessRequest,
+    ) -> Result<Sats> {
+        if request.shares <= 0 {
+            bail!("Amount of shares has to be positive");
+        }
+        let held: Shares = self
+            .db
+            .get_shares(Some(request.prediction), Some(request.user))
+            .await?
+            .into_iter()
+            .filter(|holding| holding.outcome == request.outcome)
+            .map(|holding| holding.shares)
+            .sum();
+        if held < request.shares {
+            bail!("User only holds {} shares", held);
+        }
+        let proceeds = -self
+            .trade_shares(&request, -request.shares, access.clone())
+            .await?;
+        if let Some(limit) = request.limit {
+            if proceeds < limit {
+                bail!(
+                    "Selling would pay {} sats which is below {}",
+                    proceeds,
+                    limit
+                );
+            }
+        }
+        self.db
+            .create_share_trade(
+                request.prediction,
+                request.user,
+                request.outcome,
+                -request.shares,
+                -proceeds,
+            )
+            .await?;
+        self.db
+            .add_ledger_entry(
+                request.user,
+                request.prediction,
+                None,
+                LedgerKind::Payout,
+                proceeds,
+                Utc::now(),
+            )
+            .await?;
+        debug!(
+            "{} sold {} shares of outcome {} on prediction {} for {} sats by {}",
+            request.user,
+            request.shares,
+            request.outcome,
+            request.prediction,
+            proceeds,
+            access.user
+        );
+        Ok(proceeds)
+    }
+    /// Checks whether the trade is allowed and prices it
+    async fn trade_shares(
+        &self,
+        request: &TradeSharesRequest,
+        shares: Shares,
+        access: AccessRequest,
+    ) -> Result<Sats> {
+        self.check_access_for_user(request.user, access).await?;
+        self.check_outcome(request.prediction, request.outcome)
+            .await?;
+        match self.get_active_state(request.prediction).await? {
+            MarketState::Trading => {
+                if self.end_trading_if_due(request.prediction).await? {
+                    bail!("Trading ended");
                 }
-                self.db
-                    .update_tx_state_bolt11(id, new_state.clone())
-                    .await?;
-                debug!("New state {:?} for withdrawal {}", new_state, id);
-                let tx = Tx {
-                    user: tx.user,
-                    initiated: tx.initiated,
-                    direction: tx.direction,
-                    tx_type: TxType::Bolt11 {
-                        details,
-                        state: new_state,
-                    },
-                };
-                Ok(tx)
             }
+            _ => bail!("Prediction has to be Trading to be able to trade shares"),
         }
+        let liquidity = self.get_liquidity(request.prediction).await?;
+        let quantities = self.db.get_share_quantities(request.prediction).await?;
+        calculate_share_trade(&quantities, request.outcome, shares, liquidity).ok_or(anyhow!(
+            "Market maker can't price a trade of {} shares",
+            shares
+        ))
     }
-    pub async fn check_deposit(&self, id: RowId, tx: Tx) -> Result<Tx> {
-        match tx.clone().tx_type {
-            TxType::Bolt11 { details, state } => {
-                if let TxStateBolt11::Settled(_) = state {
-                    return Ok(tx);
+    async fn get_liquidity(&self, prediction: RowId) -> Result<f64> {
+        let subsidy = self
+            .db
+            .get_amm_subsidy(prediction)
+            .await?
+            .ok_or(anyhow!("Prediction {} has no market maker", prediction))?;
+        let (platform_fee_ppm, _) = self.db.get_fee_ppm(prediction).await?;
+        Ok(lmsr_liquidity(
+            subsidy,
+            self.db.get_judge_share_ppm(prediction).await? + platform_fee_ppm,
+            self.db.get_outcomes(prediction).await?.len(),
+        ))
+    }
+    /// Current prices of one share per outcome in sats
+    pub async fn get_share_prices(&self, prediction: RowId) -> Result<Vec<f64>> {
+        let liquidity = self.get_liquidity(prediction).await?;
+        let quantities = self.db.get_share_quantities(prediction).await?;
+        Ok(lmsr_prices(&quantities, liquidity))
+    }
+    pub async fn get_shares(
+        &self,
+        prediction: Option<RowId>,
+        user: Option<UserPubKey>,
+        access: AccessRequest,
+    ) -> Result<Vec<ShareHolding>> {
+        if let Some(user) = user {
+            self.check_access_for_user(user, access).await?;
+        } else if let UserRole::User = self.check_access(access).await? {
+            bail!("Access Denied: Getting shares of users is prohibited");
+        }
+        self.db.get_shares(prediction, user).await
+    }
+    pub async fn offer_bet(&self, request: OfferBetRequest, access: AccessRequest) -> Result<()> {
+        let bet = self.db.get_bet(request.bet).await?;
+        self.check_access_for_user(bet.user, access.clone()).await?;
+        self.check_bet_tradable(&bet).await?;
+        if let Some(price) = request.price {
+            if price <= 0 {
+                bail!("Price has to be positive");
+            }
+        }
+        self.db.set_bet_sale_price(bet.id, request.price).await?;
+        debug!(
+            "Offered bet {} for {:?} sats by {}",
+            bet.id, request.price, access.user
+        );
+        Ok(())
+    }
+    /// Buys a bet that is offered for sale. The buyer pays the sale price and
+    /// gets paid the cash out of the bet once the prediction resolves.
+    pub async fn buy_bet(&mut self, request: BuyBetRequest, access: AccessRequest) -> Result<()> {
+        self.check_access_for_user(request.buyer, access.clone())
+            .await?;
+        let bet = self.db.get_bet(request.bet).await?;
+        if bet.user == request.buyer {
+            bail!("Bet already belongs to the buyer");
+        }
+        self.check_bet_tradable(&bet).await?;
+        self.db
+            .transfer_bet(bet.id, request.buyer, request.price)
+            .await?;
+        debug!(
+            "Transferred bet {} from {} to {} for {} sats by {}",
+            bet.id, bet.user, request.buyer, request.price, access.user
+        );
+        Ok(())
+    }
+    async fn check_bet_tradable(&self, bet: &Bet) -> Result<()> {
+        match self.get_active_state(bet.prediction).await? {
+            MarketState::Trading | MarketState::WaitingForDecision => Ok(()),
+            _ => bail!("Bets can only be sold before the prediction is resolved"),
+        }
+    }
+    pub async fn get_bet_transfers(
+        &self,
+        bet: RowId,
+        access: AccessRequest,
+    ) -> Result<Vec<BetTransfer>> {
+        let owner = self.db.get_bet(bet).await?.user;
+        self.check_access_for_user(owner, access).await?;
+        self.db.get_bet_transfers(bet).await
+    }
+    /// Places a limit order and matches it against the open orders of the
+    /// prediction. Fills happen at the price of the resting order.
+    pub async fn place_order(
+        &mut self,
+        request: PlaceOrderRequest,
+        access: AccessRequest,
+    ) -> Result<RowId> {
+        self.check_access_for_user(request.user, access.clone())
+            .await?;
+        self.check_outcome(request.prediction, request.outcome)
+            .await?;
+        if self.db.get_outcomes(request.prediction).await?.len() != 2
+            || self
+                .db
+                .get_scalar_range(request.prediction)
+                .await?
+                .is_some()
+            || self.db.get_amm_subsidy(request.prediction).await?.is_some()
+        {
+            bail!("Orders are only possible on yes/no predictions");
+        }
+        match self.get_active_state(request.prediction).await? {
+            MarketState::Trading => {
+                if self.end_trading_if_due(request.prediction).await? {
+                    bail!("Trading ended");
                 }
-                let new_state = self
-                    .funding
-                    .check_bolt11(details.payment_hash.clone())
-                    .await?;
-                if state == new_state {
-                    return Ok(tx);
+            }
+            _ => bail!("Prediction has to be Trading to be able to place orders"),
+        }
+        if request.price <= 0 || request.price >= CONTRACT_SATS {
+            bail!(
+                "Price has to be between 0 and {} exclusively",
+                CONTRACT_SATS
+            );
+        }
+        if request.quantity <= 0 {
+            bail!("Quantity has to be positive");
+        }
+        let order_reserve = request.quantity * order_price(request.side, request.price);
+        if self
+            .get_available_balance(request.user, access.clone())
+            .await?
+            < order_reserve
+        {
+            bail!("Not enough funds");
+        }
+        let id = self.db.create_order(&request).await?;
+        debug!(
+            "Placed order {} for {} on prediction {} by {}",
+            id, request.user, request.prediction, access.user
+        );
+        self.match_order(id).await?;
+        Ok(id)
+    }
+    async fn match_order(&self, id: RowId) -> Result<()> {
+        let order = self.db.get_order(id).await?;
+        let buys_first = buys_first_outcome(&order);
+        let limit = first_outcome_price(&order);
+        let mut counter_orders: Vec<Order> = self
+            .db
+            .get_orders(Some(order.prediction), None, true)
+            .await?
+            .into_iter()
+            .filter(|other| other.user != order.user && buys_first_outcome(other) != buys_first)
+            .filter(|other| {
+                if buys_first {
+                    first_outcome_price(other) <= limit
+                } else {
+                    first_outcome_price(other) >= limit
                 }
-                self.db
-                    .update_tx_state_bolt11(id, new_state.clone())
-                    .await?;
-                if let TxStateBolt11::Settled(amount) = new_state {
-                    self.db.adjust_user_balance(tx.user, amount).await?;
+            })
+            .collect();
+        // Best price first, then oldest first
+        counter_orders.sort_by_key(|other| {
+            let price = first_outcome_price(other);
+            (if buys_first { price } else { -price }, other.id)
+        });
+        let mut remaining = order.quantity - order.filled;
+        for other in counter_orders {
+            if remaining == 0 {
+                break;
+            }
+            let quantity = remaining.min(other.quantity - other.filled);
+            let price = first_outcome_price(&other);
+            let (buyer, seller) = if buys_first {
+                (order.user, other.user)
+            } else {
+                (other.user, order.user)
+            };
+            self.db
+                .create_order_fill(order.prediction, buyer, 0, quantity, quantity * price)
+                .await?;
+            self.db
+                .create_order_fill(
+                    order.prediction,
+                    seller,
+                    1,
+                    quantity,
+                    quantity * (CONTRACT_SATS - price),
+                )
+                .await?;
+            self.db.fill_order(order.id, quantity).await?;
+            self.db.fill_order(other.id, quantity).await?;
+            remaining -= quantity;
+            debug!(
+                "Matched order {} with order {}: {} contracts at {}",
+                order.id, other.id, quantity, price
+            );
+        }
+        Ok(())
+    }
+    pub async fn cancel_order(&mut self, id: RowId, access: AccessRequest) -> Result<()> {
+        let order = self.db.get_order(id).await?;
+        self.check_access_for_user(order.user, access.clone())
+            .await?;
+        let state = self.get_active_state(order.prediction).await?;
+        if state != MarketState::Trading {
+            bail!(
+                "Orders of prediction {} got closed when it went {}",
+                order.prediction,
+                state
+            );
+        }
+        if order.cancelled || order.filled == order.quantity {
+            bail!("Order is not open anymore");
+        }
+        self.db.cancel_order(id).await?;
+        debug!("Cancelled order {} by {}", id, access.user);
+        Ok(())
+    }
+    pub async fn get_order_book(&self, prediction: RowId) -> Result<OrderBookResponse> {
+        let mut bids: Vec<OrderBookLevel> = vec![];
+        let mut asks: Vec<OrderBookLevel> = vec![];
+        for order in self.db.get_orders(Some(prediction), None, true).await? {
+            let levels = if buys_first_outcome(&order) {
+                &mut bids
+            } else {
+                &mut asks
+            };
+            let price = first_outcome_price(&order);
+            let quantity = order.quantity - order.filled;
+            match levels.iter_mut().find(|level| level.price == price) {
+                Some(level) => level.quantity += quantity,
+                None => levels.push(OrderBookLevel { price, quantity }),
+            }
+        }
+        bids.sort_by_key(|level| -level.price);
+        asks.sort_by_key(|level| level.price);
+        Ok(OrderBookResponse { bids, asks })
+    }
+    pub async fn get_orders(
+        &self,
+        prediction: Option<RowId>,
+        user: Option<UserPubKey>,
+        access: AccessRequest,
+    ) -> Result<Vec<Order>> {
+        if let Some(user) = user {
+            self.check_access_for_user(user, access).await?;
+        } else if let UserRole::User = self.check_access(access).await? {
+            bail!("Access Denied: Getting orders of users is prohibited");
+        }
+        self.db.get_orders(prediction, user, false).await
+    }
+    pub async fn get_positions(
+        &self,
+        prediction: Option<RowId>,
+        user: Option<UserPubKey>,
+        access: AccessRequest,
+    ) -> Result<Vec<Position>> {
+        if let Some(user) = user {
+            self.check_access_for_user(user, access).await?;
+        } else if let UserRole::User = self.check_access(access).await? {
+            bail!("Access Denied: Getting positions of users is prohibited");
+        }
+        self.db.get_positions(prediction, user).await
+    }
+    pub async fn cancel_bet(&mut self, id: RowId, access: AccessRequest) -> Result<()> {
+        let bet = self.db.get_bet(id).await?;
+        self.check_access_for_user(bet.user, access.clone()).await?;
+        let market_state = self.get_active_state(bet.prediction).await?;
+        match market_state {
+            MarketState::Trading => {
+                if self.end_trading_if_due(bet.prediction).await? {
+                    bail!("Wrong market state");
                 }
-                debug!("New state {:?} for Deposit {}", new_state, id);
-                let tx = Tx {
-                    user: tx.user,
-                    initiated: tx.initiated,
-                    direction: tx.direction,
-                    tx_type: TxType::Bolt11 {
-                        details,
-                        state: new_state,
-                    },
-                };
-                Ok(tx)
             }
-        }
+            MarketState::Refunded(_) => bail!("Bet {} was already refunded", id),
+            _ => bail!("Wrong market state"),
+        }
+        self.db.remove_bet(id).await?;
+        self.db
+            .add_pool_snapshot(bet.prediction, bet.outcome, -bet.amount)
+            .await?;
+        debug!("Cancelled bet {} by {}", id, access.user);
+        Ok(())
+    }
+    /// Moves a trading market to [`MarketState::WaitingForDecision`] once
+    /// `trading_end` has passed. Returns whether the state was changed.
+    async fn end_trading_if_due(&self, prediction: RowId) -> Result<bool> {
+        if self.db.get_trading_end(prediction).await? < Utc::now() {
+            self.db
+                .set_prediction_state(prediction, MarketState::WaitingForDecision)
+                .await?;
+            debug!("Trading for prediction {} ended", prediction);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+    /// Decides a prediction whose decision deadline passed with the votes
+    /// cast if the rules allow it. Otherwise the decision period gets extended
+    /// or the prediction refunded. Returns whether the decision is over.
+    async fn finish_decision_period_if_over(&mut self, prediction: RowId) -> Result<bool> {
+        if self.get_decision_deadline(prediction).await? >= Utc::now() {
+            return Ok(false);
+        }
+        if self.db.get_reveal_period(prediction).await? > Duration::zero()
+            || self.db.get_resolution_rules(prediction).await?.quorum > 0
+        {
+            if let Err(e) = self.try_resolve(prediction).await {
+                warn!("Resolving prediction {} failed: {}", prediction, e);
+            }
+            if self.db.get_prediction_state(prediction).await? != MarketState::WaitingForDecision {
+                return Ok(true);
+            }
+        }
+        self.refund_if_decision_period_over(prediction).await
+    }
+    /// Extends the decision period by its length if the rules of the
+    /// prediction allow it and it wasn't extended before. With `reset_votes`
+    /// all judges have to vote again. Returns whether it was extended.
+    async fn extend_decision_period(&self, prediction: RowId, reset_votes: bool) -> Result<bool> {
+        if self
+            .db
+            .get_resolution_rules(prediction)
+            .await?
+            .on_no_consensus
+            != NoConsensusAction::Extend
+            || self.db.get_decision_extended(prediction).await?
+        {
+            return Ok(false);
+        }
+        let period = self.db.get_decision_period(prediction).await?;
+        self.db
+            .extend_decision_period(prediction, period + period)
+            .await?;
+        if reset_votes {
+            for (judge, state) in self.db.get_prediction_judges_mapped(prediction).await? {
+                if let JudgeState::Committed
+                | JudgeState::Resolved(_)
+                | JudgeState::ResolvedScalar(_) = state
+                {
+                    self.db
+                        .set_judge_state(prediction, judge, JudgeState::Accepted)
+                        .await?;
+                }
+            }
+        }
+        info!(
+            "Judges didn't reach a decision on prediction {}. Extended the decision period",
+            prediction
+        );
+        Ok(true)
+    }
+    /// Refunds all bets of a market whose judges didn't decide before the
+    /// end of the decision period, unless its resolution rules extend the
+    /// period instead. Returns whether the market was refunded.
+    async fn refund_if_decision_period_over(&self, prediction: RowId) -> Result<bool> {
+        if self.get_decision_deadline(prediction).await? < Utc::now() {
+            if self.extend_decision_period(prediction, false).await? {
+                return Ok(false);
+            }
+            self.db
+                .set_prediction_state(
+                    prediction,
+                    MarketState::Refunded(RefundReason::TimeForDecisionRanOut),
+                )
+                .await?;
+            info!(
+                "Time for decision for prediction {} ran out. Refunding bets",
+                prediction
+            );
+            self.slash_absent_judges(prediction).await?;
+            //Execute refund
+            self.refund_prediction(prediction).await?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+    /// Closes a market that didn't get enough judges before `trading_end`.
+    /// Returns whether the market was closed.
+    async fn close_if_judges_missing(&self, prediction: RowId) -> Result<bool> {
+        if self.db.get_trading_end(prediction).await? < Utc::now() {
+            self.db
+                .set_prediction_state(
+                    prediction,
+                    MarketState::Refunded(RefundReason::NotEnoughJudges),
+                )
+                .await?;
+            info!(
+                "Prediction {} didn't get enough judges before trading end. Closing it",
+                prediction
+            );
+            self.refund_prediction(prediction).await?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+    /// Applies all time based state transitions without waiting for a user
+    /// to interact with the markets. Gets called periodically by the server.
+    /// A market that fails to advance is logged and doesn't hold up the
+    /// others.
+    pub async fn advance_markets(&mut self) -> Result<()> {
+        for prediction in self
+            .db
+            .get_predictions_in_state(MarketState::WaitingForJudges)
+            .await?
+        {
+            let result = self.close_if_judges_missing(prediction).await;
+            log_advance_error(prediction, result);
+        }
+        for prediction in self
+            .db
+            .get_predictions_in_state(MarketState::Trading)
+            .await?
+        {
+            let result = self.end_trading_if_due(prediction).await;
+            log_advance_error(prediction, result);
+        }
+        for prediction in self
+            .db
+            .get_predictions_in_state(MarketState::WaitingForDecision)
+            .await?
+        {
+            let result = match self.replace_silent_judges(prediction).await {
+                Ok(()) => self.finish_decision_period_if_over(prediction).await,
+                Err(e) => Err(e),
+            };
+            log_advance_error(prediction, result);
+        }
+        for prefix in ["WaitingForDisputes(", "Disputed("] {
+            for prediction in self.db.get_predictions_with_state_prefix(prefix).await? {
+                let result = self.finish_dispute_period_if_over(prediction).await;
+                log_advance_error(prediction, result);
+            }
+        }
+        Ok(())
+    }
+    /// Judges that decided on the outcome the prediction resolved to. If the
+    /// appeal overturned all of them these are the appeal judges that voted
+    /// for the outcome.
+    async fn get_outcome_judges(&self, prediction: RowId) -> Result<Vec<UserPubKey>> {
+        if let MarketState::Resolved(outcome) = self.db.get_prediction_state(prediction).await? {
+            let judges: Vec<UserPubKey> = self
+                .db
+                .get_prediction_judges_mapped(prediction)
+                .await?
+                .into_iter()
+                .filter(|(_, state)| *state == JudgeState::Resolved(outcome))
+                .map(|(judge, _)| judge)
+                .collect();
+            if !judges.is_empty() {
+                return Ok(judges);
+            }
+            return Ok(self
+                .db
+                .get_appeal_votes(prediction)
+                .await?
+                .into_iter()
+                .filter(|(_, decision)| *decision == outcome)
+                .map(|(judge, _)| judge)
+                .collect());
+        }
+        bail!("Wrong market state")
+    }
+    pub async fn get_predictions(
+        &self,
+        request: PredictionListRequest,
+    ) -> Result<PredictionListResponse> {
+        let limit = page_limit(request.limit)?;
+        self.db.get_predictions(&request, limit).await
+    }
+    pub async fn get_prediction_overview(
+        &self,
+        prediction: RowId,
+    ) -> Result<PredictionOverviewResponse> {
+        self.db.get_prediction_overview(prediction).await
+    }
+
+    pub async fn get_prediction_bets_aggregated(
+        &self,
+        prediction: RowId,
+        outcome: Outcome,
+    ) -> Result<Sats> {
+        let bets = self
+            .db
+            .get_prediction_bets_aggregated(prediction, outcome)
+            .await?;
+        Ok(bets.values().sum())
+    }
+    async fn check_outcome(&self, prediction: RowId, outcome: Outcome) -> Result<()> {
+        let outcomes = self.db.get_outcomes(prediction).await?;
+        if outcome as usize >= outcomes.len() {
+            bail!(
+                "Outcome {} doesn't exist. Prediction {} only has {} outcomes",
+                outcome,
+                prediction,
+                outcomes.len()
+            );
+        }
+        Ok(())
+    }
+    pub async fn get_prediction_ratio(&self, prediction: RowId) -> Result<Vec<Sats>> {
+        self.db.get_prediction_ratio(prediction).await
+    }
+    /// Pools of the prediction over time. Buckets without bets or
+    /// cancellations are left out.
+    pub async fn get_prediction_history(
+        &self,
+        request: PredictionHistoryRequest,
+    ) -> Result<Vec<HistoryBucket>> {
+        if request.bucket_sec == 0 {
+            bail!("Buckets have to be at least one second long");
+        }
+        let bucket_sec = request.bucket_sec as i64;
+        let mut buckets: Vec<HistoryBucket> = vec![];
+        for (time, outcome, amount, pools) in self
+            .db
+            .get_pool_snapshots(request.prediction, request.from, request.to)
+            .await?
+        {
+            let start = time.timestamp() - time.timestamp().rem_euclid(bucket_sec);
+            let start = DateTime::from_timestamp(start, 0).unwrap();
+            let bucket = match buckets.last_mut() {
+                Some(bucket) if bucket.start == start => bucket,
+                _ => {
+                    let mut open = pools.clone();
+                    if let Some(pool) = open.get_mut(outcome as usize) {
+                        *pool -= amount;
+                    }
+                    buckets.push(HistoryBucket {
+                        start,
+                        open,
+                        close: vec![],
+                        volume: 0,
+                        bets: 0,
+                    });
+                    buckets.last_mut().unwrap()
+                }
+            };
+            if amount > 0 {
+                bucket.volume += amount;
+                bucket.bets += 1;
+            }
+            bucket.close = pools;
+        }
+        Ok(buckets)
+    }
+    pub async fn get_prediction_judges(
+        &self,
+        prediction: RowId,
+        reputation: bool,
+    ) -> Result<Vec<Judge>> {
+        let mut judges = self.db.get_prediction_judges(prediction).await?;
+        if reputation {
+            for judge in judges.iter_mut() {
+                judge.reputation = Some(self.get_judge_reputation(judge.user).await?);
+            }
+        }
+        Ok(judges)
+    }
+    /// Ranks users that set a username by their bets in resolved predictions
+    pub async fn get_bettor_leaderboard(
+        &self,
+        request: BettorLeaderboardRequest,
+    ) -> Result<Vec<BettorRank>> {
+        let limit = page_limit(request.limit)?;
+        self.db.get_bettor_leaderboard(&request, limit).await
+    }
+    /// Ranks judges that set a username by their votes in resolved
+    /// predictions
+    pub async fn get_judge_leaderboard(
+        &self,
+        request: JudgeLeaderboardRequest,
+    ) -> Result<Vec<JudgeRank>> {
+        let limit = page_limit(request.limit)?;
+        self.db.get_judge_leaderboard(&request, limit).await
+    }
+    pub async fn get_judge_reputation(&self, user: UserPubKey) -> Result<JudgeReputation> {
+        let mut reputation = JudgeReputation {
+            user,
+            nominations: 0,
+            accepted: 0,
+            refused: 0,
+            votes: 0,
+            majority_votes: 0,
+            minority_votes: 0,
+            missed_decisions: 0,
+            predictions_judged: 0,
+            sats_judged: 0,
+            score_ppm: None,
+        };
+        for (prediction, judge_state, market_state) in self.db.get_judge_history(user).await? {
+            reputation.nominations += 1;
+            match judge_state {
+                JudgeState::Nominated => continue,
+                JudgeState::Refused => {
+                    reputation.refused += 1;
+                    continue;
+                }
+                JudgeState::Standby => {
+                    reputation.accepted += 1;
+                    continue;
+                }
+                JudgeState::Replaced => {
+                    reputation.accepted += 1;
+                    reputation.missed_decisions += 1;
+                    continue;
+                }
+                JudgeState::Accepted | JudgeState::Committed => {
+                    reputation.accepted += 1;
+                    if matches!(
+                        market_state,
+                        MarketState::Refunded(RefundReason::TimeForDecisionRanOut)
+                            | MarketState::WaitingForDisputes(_)
+                            | MarketState::Disputed(_)
+                            | MarketState::Resolved(_)
+                            | MarketState::ResolvedScalar(_)
+                    ) {
+                        reputation.missed_decisions += 1;
+                    }
+                    continue;
+                }
+                JudgeState::Resolved(_) | JudgeState::ResolvedScalar(_) => {
+                    reputation.accepted += 1;
+                    reputation.votes += 1;
+                }
+            }
+            match (judge_state, market_state) {
+                (JudgeState::Resolved(decision), MarketState::Resolved(outcome)) => {
+                    if decision == outcome {
+                        reputation.majority_votes += 1;
+                    } else {
+                        reputation.minority_votes += 1;
+                    }
+                }
+                (_, MarketState::ResolvedScalar(_)) => {}
+                _ => continue,
+            }
+            reputation.predictions_judged += 1;
+            reputation.sats_judged += self.db.get_prediction_volume(prediction).await?;
+        }
+        let compared =
+            reputation.majority_votes + reputation.minority_votes + reputation.missed_decisions;
+        if compared > 0 {
+            reputation.score_ppm = Some(
+                (u64::from(reputation.majority_votes) * 1_000_000 / u64::from(compared)) as u32,
+            );
+        }
+        Ok(reputation)
+    }
+    pub async fn force_decision_period(
+        &self,
+        prediction: RowId,
+        access: AccessRequest,
+    ) -> Result<()> {
+        if let UserRole::User = self.check_access(access.clone()).await? {
+            bail!("Access Denied: Admin only API");
+        }
+        match self.db.get_prediction_state(prediction).await? {
+            MarketState::Trading => {
+                warn!(
+                    "{} forced the end of the decision period for prediction {}",
+                    access.user, prediction
+                );
+                self.db
+                    .set_prediction_state(prediction, MarketState::WaitingForDecision)
+                    .await
+            }
+            _ => bail!("Wrong market state"),
+        }
+    }
+    /// Stops an unsettled prediction. Bets, trades, cancellations and votes
+    /// fail with `reason` until the prediction gets resumed or refunded.
+    pub async fn halt_prediction(
+        &mut self,
+        prediction: RowId,
+        reason: String,
+        access: AccessRequest,
+    ) -> Result<()> {
+        if let UserRole::User = self.check_access(access.clone()).await? {
+            bail!("Access Denied: Admin only API");
+        }
+        if reason.trim().is_empty() {
+            bail!("A reason is required to stop a prediction");
+        }
+        match self.db.get_prediction_state(prediction).await? {
+            MarketState::Stopped => bail!("Prediction is already stopped"),
+            state if state.is_settled() => bail!("Wrong market state"),
+            _ => {}
+        }
+        warn!(
+            "{} stopped prediction {}: {}",
+            access.user, prediction, reason
+        );
+        self.db
+            .halt_prediction(prediction, reason.as_str(), Utc::now())
+            .await
+    }
+    /// Puts a stopped prediction back into its previous state. With `extend`
+    /// the deadline that was running gets pushed back by the time the
+    /// prediction was stopped.
+    pub async fn resume_prediction(
+        &mut self,
+        prediction: RowId,
+        extend: bool,
+        access: AccessRequest,
+    ) -> Result<()> {
+        if let UserRole::User = self.check_access(access.clone()).await? {
+            bail!("Access Denied: Admin only API");
+        }
+        let Some((state, _, halted_at)) = self.db.get_halt(prediction).await? else {
+            bail!("Prediction is not stopped");
+        };
+        if self.db.get_prediction_state(prediction).await? != MarketState::Stopped {
+            bail!("Prediction is not stopped");
+        }
+        let halted = Utc::now() - halted_at;
+        if extend {
+            match state {
+                MarketState::WaitingForJudges | MarketState::Trading => {
+                    let trading_end = self.db.get_trading_end(prediction).await?;
+                    self.db
+                        .set_trading_end(prediction, trading_end + halted)
+                        .await?;
+                }
+                MarketState::WaitingForDecision => {
+                    let period = self.db.get_decision_period(prediction).await?;
+                    self.db
+                        .set_decision_period(prediction, period + halted)
+                        .await?;
+                }
+                MarketState::WaitingForDisputes(_) => {
+                    let resolution_time = self.db.get_resolution_time(prediction).await?;
+                    self.db
+                        .set_resolution_time(prediction, resolution_time + halted)
+                        .await?;
+                }
+                // The dispute period of a disputed prediction runs from the dispute
+                MarketState::Disputed(_) => {
+                    let period = self.db.get_dispute_period(prediction).await?;
+                    self.db
+                        .set_dispute_period(prediction, period + halted)
+                        .await?;
+                }
+                _ => {}
+            }
+        }
+        warn!(
+            "{} resumed prediction {} after {} seconds",
+            access.user,
+            prediction,
+            halted.num_seconds()
+        );
+        self.db.resume_prediction(prediction).await
+    }
+    /// Refunds all bets of a stopped prediction. Judge and dispute bonds are
+    /// returned as well.
+    pub async fn force_refund(&mut self, prediction: RowId, access: AccessRequest) -> Result<()> {
+        if let UserRole::User = self.check_access(access.clone()).await? {
+            bail!("Access Denied: Admin only API");
+        }
+        if self.db.get_prediction_state(prediction).await? != MarketState::Stopped {
+            bail!("Only stopped predictions can be refunded");
+        }
+        warn!("{} refunded prediction {}", access.user, prediction);
+        self.db
+            .set_prediction_state(prediction, MarketState::Refunded(RefundReason::Admin))
+            .await?;
+        self.refund_prediction(prediction).await
+    }
+    /// Verifies the double-entry journal and returns the balances of all
+    /// accounts that aren't users
+    pub async fn check_books(&self, access: AccessRequest) -> Result<BooksResponse> {
+        if let UserRole::User = self.check_access(access).await? {
+            bail!("Access Denied: Admin only API");
+        }
+        let books = self.db.check_books().await?;
+        if !books.is_balanced() {
+            error!(
+                "Books are not balanced: {} unbalanced transactions, {} balance mismatches",
+                books.unbalanced_transactions.len(),
+                books.balance_mismatches.len()
+            );
+        }
+        Ok(books)
+    }
+    /// State of the prediction. Fails with the reason if an admin stopped it.
+    async fn get_active_state(&self, prediction: RowId) -> Result<MarketState> {
+        let state = self.db.get_prediction_state(prediction).await?;
+        if let MarketState::Stopped = state {
+            let reason = self
+                .db
+                .get_halt(prediction)
+                .await?
+                .map(|(_, reason, _)| reason)
+                .unwrap_or_default();
+            bail!("Prediction {} is stopped: {}", prediction, reason);
+        }
+        Ok(state)
+    }
+    pub async fn check_access(&self, access: AccessRequest) -> Result<UserRole> {
+        if self.disable_auth {
+            return Ok(UserRole::Root);
+        }
+        let (db_sig, last_access) = self
+            .db
+            .get_last_access(access.user, access.challenge)
+            .await
+            .context("Error getting session from db")?;
+        if access.sig != db_sig {
+            debug!(
+                "User {} tried to access with invalid access token",
+                access.user
+            );
+            bail!("Access token for user {} is invalid", access.user)
+        }
+        if last_access < Utc::now() - Duration::days(7) {
+            debug!(
+                "User {} tried to access after more than 7 days",
+                access.user
+            );
+            bail!("Last access was more than 7 days ago")
+        }
+        let role = self.db.get_user_role(access.user).await?;
+        Ok(role)
+    }
+    pub async fn create_login_challenge(&mut self, user: UserPubKey) -> Result<String> {
+        let challenge: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(30)
+            .map(char::from)
+            .collect();
+        trace!("Generated login challenge {}", challenge);
+        self.db.create_session(user, challenge.clone()).await?;
+        Ok(challenge)
+    }
+    pub async fn try_login(
+        &mut self,
+        user: UserPubKey,
+        sig: Signature,
+        challenge: String,
+    ) -> Result<()> {
+        sig.verify(
+            &Message::from_hashed_data::<Hash>(challenge.as_bytes()),
+            &user,
+        )?;
+        self.db.update_access_token(user, sig, challenge).await?;
+        debug!("User {} successfully logged in", user);
+        Ok(())
+    }
+    pub async fn update_user(
+        &self,
+        user: UserPubKey,
+        name: Option<String>,
+        access: AccessRequest,
+    ) -> Result<()> {
+        self.check_access_for_user(user, access).await?;
+        if let Some(name) = name {
+            self.db.update_username(user, name).await?;
+        }
+        Ok(())
+    }
+    /// Action can only be executed from logged in users for themselves
+    /// or from logged in admins
+    pub async fn check_access_for_user(
+        &self,
+        user: UserPubKey,
+        access: AccessRequest,
+    ) -> Result<()> {
+        if let UserRole::User = self.check_access(access.clone()).await? {
+            if user != access.user {
+                bail!("Access Denied: Cannot issue request on behalf of other users");
+            }
+        }
+        Ok(())
+    }
+    pub async fn get_username(&self, user: UserPubKey) -> Result<Option<String>> {
+        self.db.get_username(user).await
+    }
+    pub async fn get_user(&self, user: UserPubKey, access: AccessRequest) -> Result<UserResponse> {
+        self.check_access_for_user(user, access).await?;
+        self.db.get_user(user).await
+    }
+    pub async fn get_judges(
+        &self,
+        prediction: Option<RowId>,
+        user: Option<UserPubKey>,
+    ) -> Result<Vec<JudgePublic>> {
+        self.db.get_judges(prediction, user).await
+    }
+    pub async fn get_judge(
+        &self,
+        prediction: RowId,
+        user: UserPubKey,
+        access: AccessRequest,
+    ) -> Result<Judge> {
+        self.check_access_for_user(user, access).await?;
+        let state = self.db.get_judge_state(prediction, user).await?;
+        Ok(Judge {
+            user,
+            prediction,
+            state,
+            reputation: None,
+        })
+    }
+    pub async fn get_bets(
+        &self,
+        prediction: Option<RowId>,
+        user: Option<UserPubKey>,
+        page: PageRequest,
+        access: AccessRequest,
+    ) -> Result<Vec<Bet>> {
+        if let Some(user) = user {
+            self.check_access_for_user(user, access).await?;
+        } else {
+            if let UserRole::User = self.check_access(access).await? {
+                bail!("Access Denied: Getting bets of users is prohibited");
+            }
+        }
+        let page = PageRequest {
+            cursor: page.cursor,
+            limit: Some(page_limit(page.limit)?),
+        };
+        let bets = self.db.get_bets(prediction, user, false, page).await?;
+        Ok(bets)
+    }
+    /// Payouts, fees and refunds of settled predictions
+    pub async fn get_ledger(
+        &self,
+        prediction: Option<RowId>,
+        user: Option<UserPubKey>,
+        page: PageRequest,
+        access: AccessRequest,
+    ) -> Result<Vec<LedgerEntry>> {
+        if let Some(user) = user {
+            self.check_access_for_user(user, access).await?;
+        } else {
+            if let UserRole::User = self.check_access(access).await? {
+                bail!("Access Denied: Getting the ledger of all users is prohibited");
+            }
+        }
+        let page = PageRequest {
+            cursor: page.cursor,
+            limit: Some(page_limit(page.limit)?),
+        };
+        self.db.get_ledger(prediction, user, page).await
+    }
+    pub async fn get_balance(&self, user: UserPubKey, access: AccessRequest) -> Result<Sats> {
+        self.check_access_for_user(user, access).await?;
+        let balance = self.db.get_user_balance(user).await?;
+        Ok(balance)
+    }
+    pub async fn get_available_balance(
+        &self,
+        user: UserPubKey,
+        access: AccessRequest,
+    ) -> Result<Sats> {
+        self.check_access_for_user(user, access).await?;
+        let balance = self.db.get_user_balance(user).await?;
+        let reserved = self.db.get_user_reserved(user).await?;
+        Ok(balance - reserved)
+    }
+    pub async fn adjust_balance(
+        &self,
+        user: UserPubKey,
+        amount: Sats,
+        access: AccessRequest,
+    ) -> Result<Sats> {
+        if let UserRole::User = self.check_access(access).await? {
+            bail!("Access Denied: Operation only permitted for admins");
+        }
+        self.db
+            .post_transaction(
+                "Balance adjustment",
+                None,
+                &[
+                    (Account::User(user), amount),
+                    (Account::Adjustments, -amount),
+                ],
+            )
+            .await?;
+        warn!("Adjusted balance for {}: {}", user, amount);
+        self.db.get_user_balance(user).await
+    }
+    pub async fn init_withdrawal_bolt11(
+        &self,
+        user: UserPubKey,
+        invoice: Invoice,
+        amount: Sats,
+        access: AccessRequest,
+    ) -> Result<RowId> {
+        self.check_access_for_user(user, access.clone()).await?;
+        if amount <= 0 {
+            bail!("Amount has to be positive");
+        }
+        //TODO make balance check atomic with the balance adjustment
+        let invoice_amount = self.funding.decode_bolt11(invoice.clone()).await?;
+        if invoice_amount != amount {
+            bail!("Invoice and form have differing ammounts")
+        }
+        let balance = self.db.get_user_balance(user).await?;
+        let reserved = self.db.get_user_reserved(user).await?;
+        if balance - reserved - amount < 0 {
+            bail!("Not enough funds");
+        }
+        self.db
+            .post_transaction(
+                "Withdrawal",
+                None,
+                &[(Account::User(user), -amount), (Account::Funding, amount)],
+            )
+            .await?;
+        let hash = self.funding.pay_bolt11(invoice.clone(), amount).await?;
+        let tx = TxType::Bolt11 {
+            details: TxDetailsBolt11 {
+                payment_hash: hash.clone(),
+                payment_request: invoice,
+            },
+            state: TxStateBolt11::PayInit(amount),
+        };
+        let id = self.db.create_tx(user, TxDirection::Withdrawal, tx).await?;
+        debug!(
+            "Initiated Bolt11 Withdrawal: user:{} amount:{}",
+            user, amount
+        );
+        Ok(id)
+    }
+    pub async fn init_deposit_bolt11(
+        &self,
+        user: UserPubKey,
+        amount: Sats,
+        access: AccessRequest,
+    ) -> Result<(RowId, Invoice)> {
+        self.check_access_for_user(user, access.clone()).await?;
+        if amount <= 0 {
+            bail!("Amount has to be positive");
+        }
+        let (hash, invoice) = self.funding.create_bolt11(amount).await?;
+        let tx = TxType::Bolt11 {
+            details: TxDetailsBolt11 {
+                payment_hash: hash.clone(),
+                payment_request: invoice.clone(),
+            },
+            state: TxStateBolt11::PayInit(amount),
+        };
+        let id = self.db.create_tx(user, TxDirection::Deposit, tx).await?;
+        debug!("Initiated Bolt11 Deposit: user:{}, amount:{}", user, amount);
+        Ok((id, invoice))
+    }
+    pub async fn check_tx(&self, id: RowId, access: AccessRequest) -> Result<Tx> {
+        let mut tx = self.db.get_tx(id).await?;
+        self.check_access_for_user(tx.user, access).await?;
+        match tx.direction {
+            TxDirection::Withdrawal => self.check_withdrawal(id, tx).await,
+            TxDirection::Deposit => self.check_deposit(id, tx).await,
+        }
+    }
+    pub async fn check_withdrawal(&self, id: RowId, tx: Tx) -> Result<Tx> {
+        match tx.clone().tx_type {
+            TxType::Bolt11 { details, state } => {
+                if let TxStateBolt11::Settled(_) = state {
+                    return Ok(tx);
+                }
+                let mut new_state = self
+                    .funding
+                    .check_bolt11(details.payment_hash.clone())
+                    .await?;
+                if let TxStateBolt11::PayInit(pending_amount) = new_state {
+                    if tx.initiated < Utc::now() - Duration::minutes(10) {
+                        new_state = TxStateBolt11::Failed;
+                        self.db
+                            .post_transaction(
+                                "Failed withdrawal",
+                                None,
+                                &[
+                                    (Account::User(tx.user), pending_amount),
+                                    (Account::Funding, -pending_amount),
+                                ],
+                            )
+                            .await?;
+                        warn!("Marking withdrawal {} as failed", id);
+                    }
+                }
+                if state == new_state {
+                    return Ok(tx);
+                }
+                self.db
+                    .update_tx_state_bolt11(id, new_state.clone())
+                    .await?;
+                debug!("New state {:?} for withdrawal {}", new_state, id);
+                let tx = Tx {
+                    user: tx.user,
+                    initiated: tx.initiated,
+                    direction: tx.direction,
+                    tx_type: TxType::Bolt11 {
+                        details,
+                        state: new_state,
+                    },
+                };
+                Ok(tx)
+            }
+        }
+    }
+    pub async fn check_deposit(&self, id: RowId, tx: Tx) -> Result<Tx> {
+        match tx.clone().tx_type {
+            TxType::Bolt11 { details, state } => {
+                if let TxStateBolt11::Settled(_) = state {
+                    return Ok(tx);
+                }
+                let new_state = self
+                    .funding
+                    .check_bolt11(details.payment_hash.clone())
+                    .await?;
+                if state == new_state {
+                    return Ok(tx);
+                }
+                self.db
+                    .update_tx_state_bolt11(id, new_state.clone())
+                    .await?;
+                if let TxStateBolt11::Settled(amount) = new_state {
+                    self.db
+                        .post_transaction(
+                            "Deposit",
+                            None,
+                            &[
+                                (Account::User(tx.user), amount),
+                                (Account::Funding, -amount),
+                            ],
+                        )
+                        .await?;
+                }
+                debug!("New state {:?} for Deposit {}", new_state, id);
+                let tx = Tx {
+                    user: tx.user,
+                    initiated: tx.initiated,
+                    direction: tx.direction,
+                    tx_type: TxType::Bolt11 {
+                        details,
+                        state: new_state,
+                    },
+                };
+                Ok(tx)
+            }
+        }
+    }
+    pub async fn get_txs(
+        &self,
+        user: Option<UserPubKey>,
+        direction: Option<TxDirection>,
+        page: PageRequest,
+        access: AccessRequest,
+    ) -> Result<Vec<RowId>> {
+        if let Some(user) = user {
+            self.check_access_for_user(user, access).await?;
+        } else {
+            if let UserRole::User = self.check_access(access).await? {
+                bail!("Access Denied: Getting bets of users is prohibited");
+            }
+        }
+        let page = PageRequest {
+            cursor: page.cursor,
+            limit: Some(page_limit(page.limit)?),
+        };
+        self.db.get_txs(user, direction, page).await
+    }
+    /// Compares what is owed to users against the balance of the funding
+    /// source and checks every payment against the funding source
+    pub async fn reconcile(&self, access: AccessRequest) -> Result<ReconciliationResponse> {
+        if let UserRole::User = self.check_access(access).await? {
+            bail!("Access Denied: Admin only API");
+        }
+        let (user_balances, escrow) = self.db.get_liabilities().await?;
+        let mut pending_withdrawals = 0;
+        let mut mismatched_txs = vec![];
+        for id in self.db.get_txs(None, None, PageRequest::default()).await? {
+            let tx = self.db.get_tx(id).await?;
+            match tx.tx_type {
+                TxType::Bolt11 { details, state } => {
+                    if let (TxDirection::Withdrawal, TxStateBolt11::PayInit(amount)) =
+                        (&tx.direction, &state)
+                    {
+                        pending_withdrawals += amount;
+                    }
+                    let funding = match self.funding.check_bolt11(details.payment_hash).await {
+                        Ok(funding) => Some(funding),
+                        Err(e) => {
+                            warn!("Couldn't check payment {}: {}", id, e);
+                            None
+                        }
+                    };
+                    if funding.as_ref() != Some(&state) {
+                        mismatched_txs.push(TxMismatch {
+                            id,
+                            stored: state,
+                            funding,
+                        });
+                    }
+                }
+            }
+        }
+        let wallet_balance = self.funding.get_balance().await?;
+        let liabilities = user_balances + escrow + pending_withdrawals;
+        let reconciliation = ReconciliationResponse {
+            user_balances,
+            escrow,
+            pending_withdrawals,
+            liabilities,
+            wallet_balance,
+            discrepancy: wallet_balance - liabilities,
+            mismatched_txs,
+        };
+        if !reconciliation.is_reconciled() {
+            error!(
+                "Funding source doesn't reconcile: discrepancy of {} sats, {} mismatched payments",
+                reconciliation.discrepancy,
+                reconciliation.mismatched_txs.len()
+            );
+        }
+        Ok(reconciliation)
+    }
+}
+
+#[allow(unused)]
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::DB;
+    use crate::funding_source::TestFundingSource;
+    use secp256k1::{generate_keypair, rand};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    fn get_test_access() -> AccessRequest {
+        AccessRequest {
+            user: UserPubKey::from_str("023d51452445aa81ecc3cfcb82dbfe937707db5c89f9f9d21d64835158df405d8c").unwrap(),
+            sig: Signature::from_str("30440220208cef162c7081dafc61004daec32f5a3dadb4c6a1b4c0a479056a4962288d47022069022bc92673f73e9843cea14fa0cc46efa1b1e150339b603444c63035de21ee").unwrap(),
+            challenge: "iT1HqC3oaoGjbSZEjAwpGZiCbzjtyz".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn it_works() {
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let (_, u3) = generate_keypair(&mut rand::thread_rng());
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j2) = generate_keypair(&mut rand::thread_rng());
+        let (_, j3) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let prediction = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "It works".to_string(),
+                    outcomes: vec![],
+                    scalar: None,
+                    judges: vec![j1, j2, j3],
+                    judge_share_ppm: 100000,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 3,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        market
+            .accept_nomination(prediction, j1, access.clone())
+            .await
+            .unwrap();
+        market
+            .accept_nomination(prediction, j2, access.clone())
+            .await
+            .unwrap();
+        market
+            .accept_nomination(prediction, j3, access.clone())
+            .await
+            .unwrap();
+        let balance = market
+            .adjust_balance(u1, 200, access.clone())
+            .await
+            .unwrap();
+        assert_eq!(balance, 200);
+        market
+            .adjust_balance(u2, 200, access.clone())
+            .await
+            .unwrap();
+        market
+            .adjust_balance(u3, 200, access.clone())
+            .await
+            .unwrap();
+        market
+            .add_bet(prediction, u1, 0, 100, access.clone())
+            .await
+            .unwrap();
+        market
+            .add_bet(prediction, u2, 0, 100, access.clone())
+            .await
+            .unwrap();
+        market
+            .add_bet(prediction, u3, 0, 100, access.clone())
+            .await
+            .unwrap();
+        market
+            .add_bet(prediction, u1, 1, 100, access.clone())
+            .await
+            .unwrap();
+        market
+            .add_bet(prediction, u2, 1, 100, access.clone())
+            .await
+            .unwrap();
+        market
+            .add_bet(prediction, u3, 1, 100, access.clone())
+            .await
+            .unwrap();
+        market
+            .force_decision_period(prediction, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_decision(prediction, j1, 0, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_decision(prediction, j2, 0, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_decision(prediction, j3, 0, access.clone())
+            .await
+            .unwrap();
+        assert_eq!(market.get_balance(u1, access.clone()).await.unwrap(), 180);
+        assert_eq!(market.get_balance(u2, access.clone()).await.unwrap(), 180);
+        assert_eq!(market.get_balance(u3, access.clone()).await.unwrap(), 180);
+        assert_eq!(market.get_balance(j1, access.clone()).await.unwrap(), 20);
+        assert_eq!(market.get_balance(j2, access.clone()).await.unwrap(), 20);
+        assert_eq!(market.get_balance(j3, access.clone()).await.unwrap(), 20);
+    }
+    #[tokio::test]
+    async fn advance_markets() {
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j2) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let trading = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "Trading".to_string(),
+                    outcomes: vec![],
+                    scalar: None,
+                    judges: vec![j1],
+                    judge_share_ppm: 100000,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 1,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        let no_judges = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "No judges".to_string(),
+                    outcomes: vec![],
+                    scalar: None,
+                    judges: vec![j1, j2],
+                    judge_share_ppm: 100000,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 2,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        market
+            .accept_nomination(trading, j1, access.clone())
+            .await
+            .unwrap();
+        market
+            .adjust_balance(u1, 100, access.clone())
+            .await
+            .unwrap();
+        market
+            .add_bet(trading, u1, 0, 100, access.clone())
+            .await
+            .unwrap();
+
+        // Nothing happens before trading end
+        market.advance_markets().await.unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(trading).await.unwrap(),
+            MarketState::Trading
+        );
+
+        market
+            .db
+            .set_trading_end(trading, Utc::now() - Duration::hours(1))
+            .await
+            .unwrap();
+        market
+            .db
+            .set_trading_end(no_judges, Utc::now() - Duration::hours(1))
+            .await
+            .unwrap();
+        market.advance_markets().await.unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(trading).await.unwrap(),
+            MarketState::WaitingForDecision
+        );
+        assert_eq!(
+            market.db.get_prediction_state(no_judges).await.unwrap(),
+            MarketState::Refunded(RefundReason::NotEnoughJudges)
+        );
+        assert_eq!(
+            market
+                .get_available_balance(u1, access.clone())
+                .await
+                .unwrap(),
+            0
+        );
+
+        market
+            .db
+            .set_trading_end(trading, Utc::now() - Duration::days(2))
+            .await
+            .unwrap();
+        market.advance_markets().await.unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(trading).await.unwrap(),
+            MarketState::Refunded(RefundReason::TimeForDecisionRanOut)
+        );
+        assert_eq!(
+            market
+                .get_available_balance(u1, access.clone())
+                .await
+                .unwrap(),
+            100
+        );
+    }
+    #[tokio::test]
+    async fn categorical() {
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let (_, u3) = generate_keypair(&mut rand::thread_rng());
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j2) = generate_keypair(&mut rand::thread_rng());
+        let (_, j3) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let prediction = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "Which one wins".to_string(),
+                    outcomes: vec!["A".to_string(), "B".to_string(), "C".to_string()],
+                    scalar: None,
+                    judges: vec![j1, j2, j3],
+                    judge_share_ppm: 100000,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 3,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        for judge in [j1, j2, j3] {
+            market
+                .accept_nomination(prediction, judge, access.clone())
+                .await
+                .unwrap();
+        }
+        for (user, outcome) in [(u1, 0), (u2, 1), (u3, 2)] {
+            market
+                .adjust_balance(user, 100, access.clone())
+                .await
+                .unwrap();
+            market
+                .add_bet(prediction, user, outcome, 100, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .add_bet(prediction, u1, 3, 100, access.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            market.get_prediction_ratio(prediction).await.unwrap(),
+            vec![100, 100, 100]
+        );
+        market
+            .force_decision_period(prediction, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_decision(prediction, j1, 3, access.clone())
+            .await
+            .unwrap_err();
+        for (judge, outcome) in [(j1, 2), (j2, 2), (j3, 0)] {
+            market
+                .make_decision(prediction, judge, outcome, access.clone())
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::Resolved(2)
+        );
+        assert_eq!(market.get_balance(u1, access.clone()).await.unwrap(), 0);
+        assert_eq!(market.get_balance(u2, access.clone()).await.unwrap(), 0);
+        assert_eq!(market.get_balance(u3, access.clone()).await.unwrap(), 270);
+        assert_eq!(market.get_balance(j1, access.clone()).await.unwrap(), 15);
+        assert_eq!(market.get_balance(j2, access.clone()).await.unwrap(), 15);
+        market.get_balance(j3, access.clone()).await.unwrap_err();
+    }
+    #[tokio::test]
+    async fn scalar() {
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j2) = generate_keypair(&mut rand::thread_rng());
+        let (_, j3) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let prediction = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "Temperature in percent of 100 degrees".to_string(),
+                    outcomes: vec![],
+                    scalar: Some(ScalarRange { min: 0, max: 100 }),
+                    judges: vec![j1, j2, j3],
+                    judge_share_ppm: 100000,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 3,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        for judge in [j1, j2, j3] {
+            market
+                .accept_nomination(prediction, judge, access.clone())
+                .await
+                .unwrap();
+        }
+        for (user, outcome) in [(u1, SCALAR_LONG), (u2, SCALAR_SHORT)] {
+            market
+                .adjust_balance(user, 100, access.clone())
+                .await
+                .unwrap();
+            market
+                .add_bet(prediction, user, outcome, 100, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .force_decision_period(prediction, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_decision(prediction, j1, SCALAR_LONG, access.clone())
+            .await
+            .unwrap_err();
+        market
+            .make_scalar_decision(prediction, j1, 101, access.clone())
+            .await
+            .unwrap_err();
+        for (judge, value) in [(j1, 70), (j2, 80), (j3, 75)] {
+            market
+                .make_scalar_decision(prediction, judge, value, access.clone())
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::ResolvedScalar(75)
+        );
+        assert_eq!(market.get_balance(u1, access.clone()).await.unwrap(), 135);
+        assert_eq!(market.get_balance(u2, access.clone()).await.unwrap(), 45);
+        // The judge share of 20 sats can't be split evenly
+        let mut judge_balances = vec![];
+        for judge in [j1, j2, j3] {
+            judge_balances.push(market.get_balance(judge, access.clone()).await.unwrap());
+        }
+        judge_balances.sort();
+        assert_eq!(judge_balances, vec![6, 7, 7]);
+    }
+    #[tokio::test]
+    async fn amm() {
+        let (_, creator) = generate_keypair(&mut rand::thread_rng());
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j2) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        for user in [creator, u1, u2] {
+            market
+                .adjust_balance(user, 1000, access.clone())
+                .await
+                .unwrap();
+        }
+        let mut request = NewAmmPredictionRequest {
+            creator,
+            subsidy: 1001,
+            prediction: NewPredictionRequest {
+                prediction: "It will rain".to_string(),
+                outcomes: vec![],
+                scalar: None,
+                judges: vec![j1, j2],
+                judge_share_ppm: 100000,
+                trading_end: Utc::now() + Duration::days(3),
+                decision_period_sec: 86400,
+                judge_count: 2,
+                judge_bond: 0,
+                dispute_period_sec: 0,
+                dispute_bond: 0,
+                appeal_judges: vec![],
+                reveal_period_sec: 0,
+                judge_replacement_sec: 0,
+                creator_fee_ppm: 0,
+                metadata: PredictionMetadata::default(),
+                resolution_rules: ResolutionRules::default(),
+            },
+        };
+        market
+            .new_amm_prediction(request.clone(), access.clone())
+            .await
+            .unwrap_err();
+        request.subsidy = 1000;
+        request.prediction.judge_share_ppm = 1000000;
+        market
+            .new_amm_prediction(request.clone(), access.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(calculate_share_trade(&[0, 0], 0, 100, 0.0), None);
+        request.prediction.judge_share_ppm = 100000;
+        let prediction = market
+            .new_amm_prediction(request, access.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            market.get_balance(creator, access.clone()).await.unwrap(),
+            0
+        );
+        for judge in [j1, j2] {
+            market
+                .accept_nomination(prediction, judge, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .add_bet(prediction, u1, 0, 100, access.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            market.get_share_prices(prediction).await.unwrap(),
+            vec![0.5, 0.5]
+        );
+
+        let trade = |user, outcome, shares| TradeSharesRequest {
+            prediction,
+            user,
+            outcome,
+            shares,
+            limit: None,
+        };
+        let u1_cost = market
+            .buy_shares(trade(u1, 0, 500), access.clone())
+            .await
+            .unwrap();
+        assert!(u1_cost > 250 && u1_cost < 500);
+        let prices = market.get_share_prices(prediction).await.unwrap();
+        assert!(prices[0] > 0.5 && prices[1] < 0.5);
+        let u2_cost = market
+            .buy_shares(trade(u2, 1, 200), access.clone())
+            .await
+            .unwrap();
+        assert!(u2_cost > 0 && u2_cost < 100);
+        market
+            .sell_shares(trade(u1, 0, 501), access.clone())
+            .await
+            .unwrap_err();
+        let mut sell = trade(u1, 0, 100);
+        sell.limit = Some(100);
+        market
+            .sell_shares(sell.clone(), access.clone())
+            .await
+            .unwrap_err();
+        sell.limit = None;
+        let u1_proceeds = market.sell_shares(sell, access.clone()).await.unwrap();
+        assert!(u1_proceeds > 0 && u1_proceeds < 100);
+
+        market
+            .force_decision_period(prediction, access.clone())
+            .await
+            .unwrap();
+        for judge in [j1, j2] {
+            market
+                .make_decision(prediction, judge, 0, access.clone())
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            market.get_balance(u1, access.clone()).await.unwrap(),
+            1000 - u1_cost + u1_proceeds + 400
+        );
+        assert_eq!(
+            market.get_balance(u2, access.clone()).await.unwrap(),
+            1000 - u2_cost
+        );
+        for judge in [j1, j2] {
+            assert_eq!(market.get_balance(judge, access.clone()).await.unwrap(), 50);
+        }
+        let mut total = 0;
+        for user in [creator, u1, u2, j1, j2] {
+            total += market.get_balance(user, access.clone()).await.unwrap();
+        }
+        assert_eq!(total, 3000);
+
+        // The ledger shows what every user won or lost on the prediction
+        for (user, start) in [(creator, 1000), (u1, 1000), (u2, 1000), (j1, 0), (j2, 0)] {
+            let entries = market
+                .db
+                .get_ledger(Some(prediction), Some(user), PageRequest::default())
+                .await
+                .unwrap();
+            assert_eq!(
+                entries.iter().map(|entry| entry.amount).sum::<Sats>(),
+                market.get_balance(user, access.clone()).await.unwrap() - start
+            );
+        }
+        let creator_entries = market
+            .db
+            .get_ledger(Some(prediction), Some(creator), PageRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(creator_entries[0].kind, LedgerKind::Stake);
+        assert_eq!(creator_entries[0].amount, -1000);
+    }
+    #[tokio::test]
+    async fn order_book() {
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let (_, u3) = generate_keypair(&mut rand::thread_rng());
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let prediction = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "It will rain".to_string(),
+                    outcomes: vec![],
+                    scalar: None,
+                    judges: vec![j1],
+                    judge_share_ppm: 100000,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 1,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        market
+            .accept_nomination(prediction, j1, access.clone())
+            .await
+            .unwrap();
+        for user in [u1, u2, u3] {
+            market
+                .adjust_balance(user, 1000, access.clone())
+                .await
+                .unwrap();
+        }
+        let order = |user, outcome, side, price, quantity| PlaceOrderRequest {
+            prediction,
+            user,
+            outcome,
+            side,
+            price,
+            quantity,
+        };
+
+        let u1_order = market
+            .place_order(order(u1, 0, OrderSide::Bid, 60, 10), access.clone())
+            .await
+            .unwrap();
+        // Bid on No at 45 is an ask on Yes at 55 and gets filled at 60
+        market
+            .place_order(order(u2, 1, OrderSide::Bid, 45, 4), access.clone())
+            .await
+            .unwrap();
+        let u3_order = market
+            .place_order(order(u3, 0, OrderSide::Ask, 70, 5), access.clone())
+            .await
+            .unwrap();
+        market
+            .place_order(order(u3, 0, OrderSide::Bid, 100, 5), access.clone())
+            .await
+            .unwrap_err();
+        market
+            .place_order(order(u2, 0, OrderSide::Bid, 99, 20), access.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            market.get_order_book(prediction).await.unwrap(),
+            OrderBookResponse {
+                bids: vec![OrderBookLevel {
+                    price: 60,
+                    quantity: 6
+                }],
+                asks: vec![OrderBookLevel {
+                    price: 70,
+                    quantity: 5
+                }],
+            }
+        );
+        assert_eq!(
+            market
+                .get_positions(Some(prediction), Some(u2), access.clone())
+                .await
+                .unwrap(),
+            vec![Position {
+                user: u2,
+                prediction,
+                outcome: 1,
+                contracts: 4,
+                cost: 160,
+            }]
+        );
+        for (user, available) in [(u1, 400), (u2, 840), (u3, 850)] {
+            assert_eq!(
+                market
+                    .get_available_balance(user, access.clone())
+                    .await
+                    .unwrap(),
+                available
+            );
+        }
+        market.cancel_order(u1_order, access.clone()).await.unwrap();
+        market
+            .cancel_order(u1_order, access.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            market
+                .get_available_balance(u1, access.clone())
+                .await
+                .unwrap(),
+            760
+        );
+
+        market
+            .force_decision_period(prediction, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_decision(prediction, j1, 0, access.clone())
+            .await
+            .unwrap();
+        for (user, balance) in [(u1, 1160), (u2, 840), (u3, 1000)] {
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap(),
+                balance
+            );
+            assert_eq!(
+                market
+                    .get_available_balance(user, access.clone())
+                    .await
+                    .unwrap(),
+                balance
+            );
+        }
+        assert_eq!(
+            market.get_order_book(prediction).await.unwrap(),
+            OrderBookResponse {
+                bids: vec![],
+                asks: vec![],
+            }
+        );
+        assert!(market.db.get_order(u3_order).await.unwrap().cancelled);
+        market
+            .cancel_order(u3_order, access.clone())
+            .await
+            .unwrap_err();
+    }
+    #[tokio::test]
+    async fn bet_transfer() {
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let (_, u3) = generate_keypair(&mut rand::thread_rng());
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let prediction = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "It will rain".to_string(),
+                    outcomes: vec![],
+                    scalar: None,
+                    judges: vec![j1],
+                    judge_share_ppm: 100000,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 1,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        market
+            .accept_nomination(prediction, j1, access.clone())
+            .await
+            .unwrap();
+        for (user, amount) in [(u1, 100), (u2, 100), (u3, 200)] {
+            market
+                .adjust_balance(user, amount, access.clone())
+                .await
+                .unwrap();
+        }
+        for (user, outcome) in [(u1, 0), (u2, 1)] {
+            market
+                .add_bet(prediction, user, outcome, 100, access.clone())
+                .await
+                .unwrap();
+        }
+        let bet = market
+            .get_bets(
+                Some(prediction),
+                Some(u1),
+                PageRequest::default(),
+                access.clone(),
+            )
+            .await
+            .unwrap()[0]
+            .id;
+        let buy = |price| BuyBetRequest {
+            bet,
+            buyer: u3,
+            price,
+        };
+        market.buy_bet(buy(150), access.clone()).await.unwrap_err();
+        market
+            .offer_bet(
+                OfferBetRequest {
+                    bet,
+                    price: Some(150),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        market.buy_bet(buy(140), access.clone()).await.unwrap_err();
+        market.buy_bet(buy(150), access.clone()).await.unwrap();
+        market.buy_bet(buy(150), access.clone()).await.unwrap_err();
+        for (user, available) in [(u1, 150), (u3, 50)] {
+            assert_eq!(
+                market
+                    .get_available_balance(user, access.clone())
+                    .await
+                    .unwrap(),
+                available
+            );
+        }
+        let transfers = market.get_bet_transfers(bet, access.clone()).await.unwrap();
+        assert_eq!(transfers.len(), 1);
+        assert_eq!((transfers[0].seller, transfers[0].buyer), (u1, u3));
+        assert_eq!(transfers[0].price, 150);
+
+        market
+            .force_decision_period(prediction, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_decision(prediction, j1, 0, access.clone())
+            .await
+            .unwrap();
+        for (user, balance) in [(u1, 150), (u2, 0), (u3, 230), (j1, 20)] {
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap(),
+                balance
+            );
+        }
+    }
+    #[tokio::test]
+    async fn judge_bonds() {
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j2) = generate_keypair(&mut rand::thread_rng());
+        let (_, j3) = generate_keypair(&mut rand::thread_rng());
+        let (_, j4) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        for (user, amount) in [(u1, 100), (u2, 100), (j1, 60), (j2, 60), (j3, 60)] {
+            market
+                .adjust_balance(user, amount, access.clone())
+                .await
+                .unwrap();
+        }
+        let request = NewPredictionRequest {
+            prediction: "It will rain".to_string(),
+            outcomes: vec![],
+            scalar: None,
+            judges: vec![j1, j2, j3, j4],
+            judge_share_ppm: 100000,
+            trading_end: Utc::now() + Duration::days(3),
+            decision_period_sec: 86400,
+            judge_count: 3,
+            judge_bond: 50,
+            dispute_period_sec: 0,
+            dispute_bond: 0,
+            appeal_judges: vec![],
+            reveal_period_sec: 0,
+            judge_replacement_sec: 0,
+            creator_fee_ppm: 0,
+            metadata: PredictionMetadata::default(),
+            resolution_rules: ResolutionRules::default(),
+        };
+        let prediction = market
+            .new_prediction(request.clone(), access.clone())
+            .await
+            .unwrap();
+        market
+            .accept_nomination(prediction, j4, access.clone())
+            .await
+            .unwrap_err();
+        for judge in [j1, j2, j3] {
+            market
+                .accept_nomination(prediction, judge, access.clone())
+                .await
+                .unwrap();
+            assert_eq!(
+                market
+                    .get_available_balance(judge, access.clone())
+                    .await
+                    .unwrap(),
+                10
+            );
+        }
+        market
+            .add_bet(prediction, j1, 0, 11, access.clone())
+            .await
+            .unwrap_err();
+        for (user, outcome) in [(u1, 0), (u2, 1)] {
+            market
+                .add_bet(prediction, user, outcome, 100, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .force_decision_period(prediction, access.clone())
+            .await
+            .unwrap();
+        for (judge, decision) in [(j1, 0), (j2, 0), (j3, 1)] {
+            market
+                .make_decision(prediction, judge, decision, access.clone())
+                .await
+                .unwrap();
+        }
+        for (user, balance) in [(u1, 180), (u2, 0), (j1, 95), (j2, 95), (j3, 10)] {
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap(),
+                balance
+            );
+            assert_eq!(
+                market
+                    .get_available_balance(user, access.clone())
+                    .await
+                    .unwrap(),
+                balance
+            );
+        }
+
+        // Judges that don't vote lose their bond to the judges that did
+        let prediction = market
+            .new_prediction(
+                NewPredictionRequest {
+                    judges: vec![j1, j2],
+                    judge_count: 2,
+                    judge_bond: 20,
+                    ..request
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        for judge in [j1, j2] {
+            market
+                .accept_nomination(prediction, judge, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .force_decision_period(prediction, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_decision(prediction, j1, 0, access.clone())
+            .await
+            .unwrap();
+        market
+            .db
+            .set_trading_end(prediction, Utc::now() - Duration::days(2))
+            .await
+            .unwrap();
+        market.advance_markets().await.unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::Refunded(RefundReason::TimeForDecisionRanOut)
+        );
+        for (user, balance) in [(j1, 115), (j2, 75)] {
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap(),
+                balance
+            );
+        }
+
+        let reputation = market.get_judge_reputation(j1).await.unwrap();
+        assert_eq!(
+            (
+                reputation.nominations,
+                reputation.accepted,
+                reputation.votes
+            ),
+            (2, 2, 2)
+        );
+        assert_eq!(reputation.predictions_judged, 1);
+        assert_eq!(reputation.sats_judged, 200);
+        assert_eq!(reputation.score_ppm, Some(1_000_000));
+        let reputation = market.get_judge_reputation(j2).await.unwrap();
+        assert_eq!((reputation.votes, reputation.missed_decisions), (1, 1));
+        assert_eq!(reputation.score_ppm, Some(500_000));
+        let reputation = market.get_judge_reputation(j3).await.unwrap();
+        assert_eq!(reputation.minority_votes, 1);
+        assert_eq!(reputation.score_ppm, Some(0));
+        let reputation = market.get_judge_reputation(j4).await.unwrap();
+        assert_eq!((reputation.nominations, reputation.accepted), (1, 0));
+        assert_eq!(reputation.score_ppm, None);
+    }
+    #[tokio::test]
+    async fn disputes() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j2) = generate_keypair(&mut rand::thread_rng());
+        let (_, a1) = generate_keypair(&mut rand::thread_rng());
+        let (_, a2) = generate_keypair(&mut rand::thread_rng());
+        let (_, a3) = generate_keypair(&mut rand::thread_rng());
+        let (_, outsider) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let mut predictions = vec![];
+        for _ in 0..3 {
+            let prediction = market
+                .new_prediction(
+                    NewPredictionRequest {
+                        prediction: "It will rain".to_string(),
+                        outcomes: vec![],
+                        scalar: None,
+                        judges: vec![j1, j2],
+                        judge_share_ppm: 100000,
+                        trading_end: Utc::now() + Duration::days(3),
+                        decision_period_sec: 86400,
+                        judge_count: 2,
+                        judge_bond: 0,
+                        dispute_period_sec: 86400,
+                        dispute_bond: 50,
+                        appeal_judges: vec![a1, a2, a3],
+                        reveal_period_sec: 0,
+                        judge_replacement_sec: 0,
+                        creator_fee_ppm: 0,
+                        metadata: PredictionMetadata::default(),
+                        resolution_rules: ResolutionRules::default(),
+                    },
+                    access.clone(),
+                )
+                .await
+                .unwrap();
+            for judge in [j1, j2] {
+                market
+                    .accept_nomination(prediction, judge, access.clone())
+                    .await
+                    .unwrap();
+            }
+            let (_, yes) = generate_keypair(&mut rand::thread_rng());
+            let (_, no) = generate_keypair(&mut rand::thread_rng());
+            for (user, outcome, balance) in [(yes, 0, 100), (no, 1, 150)] {
+                market
+                    .adjust_balance(user, balance, access.clone())
+                    .await
+                    .unwrap();
+                market
+                    .add_bet(prediction, user, outcome, 100, access.clone())
+                    .await
+                    .unwrap();
+            }
+            market
+                .force_decision_period(prediction, access.clone())
+                .await
+                .unwrap();
+            for judge in [j1, j2] {
+                market
+                    .make_decision(prediction, judge, 0, access.clone())
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(
+                market.db.get_prediction_state(prediction).await.unwrap(),
+                MarketState::WaitingForDisputes(0)
+            );
+            assert_eq!(market.get_balance(yes, access.clone()).await.unwrap(), 0);
+            predictions.push((prediction, yes, no));
+        }
+
+        // The appeal overturns the decision
+        let (prediction, yes, no) = predictions[0];
+        market
+            .dispute(
+                DisputeRequest {
+                    prediction,
+                    user: outsider,
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap_err();
+        market
+            .dispute(
+                DisputeRequest {
+                    prediction,
+                    user: no,
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            market
+                .get_available_balance(no, access.clone())
+                .await
+                .unwrap(),
+            0
+        );
+        market
+            .make_appeal_decision(prediction, j1, 1, access.clone())
+            .await
+            .unwrap_err();
+        for judge in [a1, a2] {
+            market
+                .make_appeal_decision(prediction, judge, 1, access.clone())
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::Resolved(1)
+        );
+        for (user, balance) in [(yes, 0), (no, 230), (a1, 10), (a2, 10)] {
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap(),
+                balance
+            );
+        }
+
+        // The appeal confirms the decision and the disputer loses the bond
+        let (prediction, yes, no) = predictions[1];
+        market
+            .dispute(
+                DisputeRequest {
+                    prediction,
+                    user: no,
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        for judge in [a1, a3] {
+            market
+                .make_appeal_decision(prediction, judge, 0, access.clone())
+                .await
+                .unwrap();
+        }
+        for (user, balance) in [(yes, 180), (no, 0), (j1, 35), (j2, 35)] {
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap(),
+                balance
+            );
+        }
+
+        // Nobody disputes and the decision becomes final
+        let (prediction, yes, no) = predictions[2];
+        market
+            .db
+            .set_resolution_time(prediction, Utc::now() - Duration::days(2))
+            .await
+            .unwrap();
+        market.advance_markets().await.unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::Resolved(0)
+        );
+        for (user, balance) in [(yes, 180), (no, 50), (j1, 45), (j2, 45)] {
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap(),
+                balance
+            );
+        }
+    }
+    #[tokio::test]
+    async fn commit_reveal() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j2) = generate_keypair(&mut rand::thread_rng());
+        let (_, j3) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        for judge in [j1, j2, j3] {
+            market
+                .adjust_balance(judge, 40, access.clone())
+                .await
+                .unwrap();
+        }
+        let mut predictions = vec![];
+        for _ in 0..2 {
+            let prediction = market
+                .new_prediction(
+                    NewPredictionRequest {
+                        prediction: "It will rain".to_string(),
+                        outcomes: vec![],
+                        scalar: None,
+                        judges: vec![j1, j2, j3],
+                        judge_share_ppm: 100000,
+                        trading_end: Utc::now() + Duration::days(3),
+                        decision_period_sec: 86400,
+                        judge_count: 3,
+                        judge_bond: 20,
+                        dispute_period_sec: 0,
+                        dispute_bond: 0,
+                        appeal_judges: vec![],
+                        reveal_period_sec: 86400,
+                        judge_replacement_sec: 0,
+                        creator_fee_ppm: 0,
+                        metadata: PredictionMetadata::default(),
+                        resolution_rules: ResolutionRules::default(),
+                    },
+                    access.clone(),
+                )
+                .await
+                .unwrap();
+            for judge in [j1, j2, j3] {
+                market
+                    .accept_nomination(prediction, judge, access.clone())
+                    .await
+                    .unwrap();
+            }
+            let (_, yes) = generate_keypair(&mut rand::thread_rng());
+            let (_, no) = generate_keypair(&mut rand::thread_rng());
+            for (user, outcome) in [(yes, 0), (no, 1)] {
+                market
+                    .adjust_balance(user, 100, access.clone())
+                    .await
+                    .unwrap();
+                market
+                    .add_bet(prediction, user, outcome, 100, access.clone())
+                    .await
+                    .unwrap();
+            }
+            market
+                .force_decision_period(prediction, access.clone())
+                .await
+                .unwrap();
+            predictions.push((prediction, yes, no));
+        }
+        let commit = |prediction, judge, decision: i64| CommitDecisionRequest {
+            prediction,
+            judge,
+            commitment: calculate_decision_commitment(prediction, judge, decision, "salt"),
+        };
+        let reveal = |prediction, judge, decision| RevealDecisionRequest {
+            prediction,
+            judge,
+            decision,
+            salt: "salt".to_string(),
+        };
+
+        // j3 never reveals and counts as not voted
+        let (prediction, yes, no) = predictions[0];
+        market
+            .make_decision(prediction, j1, 0, access.clone())
+            .await
+            .unwrap_err();
+        market
+            .reveal_decision(reveal(prediction, j1, 0), access.clone())
+            .await
+            .unwrap_err();
+        for (judge, decision) in [(j1, 0), (j2, 0)] {
+            market
+                .commit_decision(commit(prediction, judge, decision), access.clone())
+                .await
+                .unwrap();
+        }
+        for judge in market
+            .get_prediction_judges(prediction, false)
+            .await
+            .unwrap()
+        {
+            assert!(matches!(
+                judge.state,
+                JudgeState::Committed | JudgeState::Accepted
+            ));
+        }
+        // The reveal window only starts once all judges committed
+        market
+            .reveal_decision(reveal(prediction, j1, 0), access.clone())
+            .await
+            .unwrap_err();
+        market
+            .commit_decision(commit(prediction, j3, 1), access.clone())
+            .await
+            .unwrap();
+        market
+            .commit_decision(commit(prediction, j3, 0), access.clone())
+            .await
+            .unwrap_err();
+        market
+            .reveal_decision(reveal(prediction, j1, 1), access.clone())
+            .await
+            .unwrap_err();
+        for judge in [j1, j2] {
+            market
+                .reveal_decision(reveal(prediction, judge, 0), access.clone())
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::WaitingForDecision
+        );
+        market
+            .db
+            .set_trading_end(prediction, Utc::now() - Duration::days(3))
+            .await
+            .unwrap();
+        market.advance_markets().await.unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::Resolved(0)
+        );
+        for (user, balance) in [(yes, 180), (no, 0), (j1, 60), (j2, 60), (j3, 20)] {
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap(),
+                balance
+            );
+        }
+        let reputation = market.get_judge_reputation(j3).await.unwrap();
+        assert_eq!(reputation.missed_decisions, 1);
+
+        // Without any reveals the prediction gets refunded
+        let (prediction, yes, no) = predictions[1];
+        market
+            .commit_decision(commit(prediction, j1, 0), access.clone())
+            .await
+            .unwrap();
+        market
+            .db
+            .set_trading_end(prediction, Utc::now() - Duration::days(3))
+            .await
+            .unwrap();
+        market.advance_markets().await.unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::Refunded(RefundReason::TimeForDecisionRanOut)
+        );
+        // The bonds of all three judges go to the bettors
+        for (user, balance) in [(yes, 130), (no, 130), (j1, 40), (j2, 40), (j3, 0)] {
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap(),
+                balance
+            );
+        }
+    }
+    #[tokio::test]
+    async fn standby_judges() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j2) = generate_keypair(&mut rand::thread_rng());
+        let (_, j3) = generate_keypair(&mut rand::thread_rng());
+        let (_, j4) = generate_keypair(&mut rand::thread_rng());
+        let (_, yes) = generate_keypair(&mut rand::thread_rng());
+        let (_, no) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let prediction = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "It will rain".to_string(),
+                    outcomes: vec![],
+                    scalar: None,
+                    judges: vec![j1, j2, j3, j4],
+                    judge_share_ppm: 100000,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 2,
+                    judge_bond: 10,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 43200,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        for judge in [j1, j2, j3, j4] {
+            market
+                .adjust_balance(judge, 10, access.clone())
+                .await
+                .unwrap();
+            market
+                .accept_nomination(prediction, judge, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .accept_nomination(prediction, j3, access.clone())
+            .await
+            .unwrap_err();
+        let states = market
+            .db
+            .get_prediction_judges_mapped(prediction)
+            .await
+            .unwrap();
+        assert_eq!(states[&j2], JudgeState::Accepted);
+        assert_eq!(states[&j3], JudgeState::Standby);
+        assert_eq!(states[&j4], JudgeState::Standby);
+        for (user, outcome) in [(yes, 0), (no, 1)] {
+            market
+                .adjust_balance(user, 100, access.clone())
+                .await
+                .unwrap();
+            market
+                .add_bet(prediction, user, outcome, 100, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .force_decision_period(prediction, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_decision(prediction, j3, 0, access.clone())
+            .await
+            .unwrap_err();
+        market
+            .make_decision(prediction, j1, 0, access.clone())
+            .await
+            .unwrap();
+
+        // j2 stays silent past the replacement point and j3 takes over
+        market
+            .db
+            .set_trading_end(prediction, Utc::now() - Duration::hours(13))
+            .await
+            .unwrap();
+        market.advance_markets().await.unwrap();
+        let states = market
+            .db
+            .get_prediction_judges_mapped(prediction)
+            .await
+            .unwrap();
+        assert_eq!(states[&j2], JudgeState::Replaced);
+        assert_eq!(states[&j3], JudgeState::Accepted);
+        assert_eq!(states[&j4], JudgeState::Standby);
+        for judge in [j2, j4] {
+            market
+                .make_decision(prediction, judge, 1, access.clone())
+                .await
+                .unwrap_err();
+        }
+        market
+            .make_decision(prediction, j3, 0, access.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::Resolved(0)
+        );
+        for (user, balance) in [(yes, 180), (no, 0), (j1, 20), (j2, 0), (j3, 20), (j4, 10)] {
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap(),
+                balance
+            );
+        }
+        for judge in [j1, j2, j3, j4] {
+            assert_eq!(
+                market
+                    .get_available_balance(judge, access.clone())
+                    .await
+                    .unwrap(),
+                market.get_balance(judge, access.clone()).await.unwrap()
+            );
+        }
+        let reputation = market.get_judge_reputation(j2).await.unwrap();
+        assert_eq!(reputation.missed_decisions, 1);
+    }
+    #[tokio::test]
+    async fn fees() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j2) = generate_keypair(&mut rand::thread_rng());
+        let (_, house) = generate_keypair(&mut rand::thread_rng());
+        let (_, a) = generate_keypair(&mut rand::thread_rng());
+        let (_, b) = generate_keypair(&mut rand::thread_rng());
+        let (_, c) = generate_keypair(&mut rand::thread_rng());
+
+        assert!(Mercado::new(
+            Arc::new(DB::new("sqlite::memory:".to_string()).await),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee {
+                fee_ppm: 20000,
+                house_account: None,
+            },
+            true,
+        )
+        .await
+        .is_err());
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee {
+                fee_ppm: 20000,
+                house_account: Some(house),
+            },
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let request = NewPredictionRequest {
+            prediction: "It will rain".to_string(),
+            outcomes: vec![],
+            scalar: None,
+            judges: vec![j1, j2],
+            judge_share_ppm: 100000,
+            trading_end: Utc::now() + Duration::days(3),
+            decision_period_sec: 86400,
+            judge_count: 2,
+            judge_bond: 0,
+            dispute_period_sec: 0,
+            dispute_bond: 0,
+            appeal_judges: vec![],
+            reveal_period_sec: 0,
+            judge_replacement_sec: 0,
+            creator_fee_ppm: 10000,
+            metadata: PredictionMetadata::default(),
+            resolution_rules: ResolutionRules::default(),
+        };
+        market
+            .new_prediction(
+                NewPredictionRequest {
+                    judge_share_ppm: 990000,
+                    ..request.clone()
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap_err();
+        let prediction = market
+            .new_prediction(request, access.clone())
+            .await
+            .unwrap();
+        let overview = market.get_prediction_overview(prediction).await.unwrap();
+        assert_eq!(overview.platform_fee_ppm, 20000);
+        assert_eq!(overview.creator_fee_ppm, 10000);
+        for judge in [j1, j2] {
+            market
+                .accept_nomination(prediction, judge, access.clone())
+                .await
+                .unwrap();
+        }
+        for (user, outcome, amount) in [(a, 0, 33), (b, 0, 67), (c, 1, 150)] {
+            market
+                .adjust_balance(user, amount, access.clone())
+                .await
+                .unwrap();
+            market
+                .add_bet(prediction, user, outcome, amount, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .force_decision_period(prediction, access.clone())
+            .await
+            .unwrap();
+        for judge in [j1, j2] {
+            market
+                .make_decision(prediction, judge, 0, access.clone())
+                .await
+                .unwrap();
+        }
+        // The winners split the 218 sats left after the judge share and fees,
+        // the sat left over from truncating goes to the larger remainder of a
+        let creator = access.user;
+        for (user, balance) in [(a, 72), (b, 146), (c, 0), (house, 5), (creator, 2)] {
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap(),
+                balance
+            );
+        }
+        let mut judge_balances = vec![
+            market.get_balance(j1, access.clone()).await.unwrap(),
+            market.get_balance(j2, access.clone()).await.unwrap(),
+        ];
+        judge_balances.sort();
+        assert_eq!(judge_balances, vec![12, 13]);
+    }
+    #[tokio::test]
+    async fn listings() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j2) = generate_keypair(&mut rand::thread_rng());
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        market
+            .adjust_balance(u1, 1000, access.clone())
+            .await
+            .unwrap();
+        let mut predictions = vec![];
+        for (text, category, judge, days, bet) in [
+            ("Will BTC hit 100k", "Crypto", j1, 5, Some(50)),
+            ("Will ETH flip BTC", "Crypto", j2, 3, Some(200)),
+            ("Rain in Berlin", "Weather", j1, 4, None),
+        ] {
+            let prediction = market
+                .new_prediction(
+                    NewPredictionRequest {
+                        prediction: text.to_string(),
+                        outcomes: vec![],
+                        scalar: None,
+                        judges: vec![judge],
+                        judge_share_ppm: 0,
+                        trading_end: Utc::now() + Duration::days(days),
+                        decision_period_sec: 86400,
+                        judge_count: 1,
+                        judge_bond: 0,
+                        dispute_period_sec: 0,
+                        dispute_bond: 0,
+                        appeal_judges: vec![],
+                        reveal_period_sec: 0,
+                        judge_replacement_sec: 0,
+                        creator_fee_ppm: 0,
+                        metadata: PredictionMetadata {
+                            category: Some(category.to_string()),
+                            ..Default::default()
+                        },
+                        resolution_rules: ResolutionRules::default(),
+                    },
+                    access.clone(),
+                )
+                .await
+                .unwrap();
+            if let Some(bet) = bet {
+                market
+                    .accept_nomination(prediction, judge, access.clone())
+                    .await
+                    .unwrap();
+                market
+                    .add_bet(prediction, u1, 0, bet, access.clone())
+                    .await
+                    .unwrap();
+            }
+            predictions.push(prediction);
+        }
+        let (p1, p2, p3) = (predictions[0], predictions[1], predictions[2]);
+
+        let list = |request: PredictionListRequest| {
+            let market = &market;
+            async move {
+                market
+                    .get_predictions(request)
+                    .await
+                    .unwrap()
+                    .predictions
+                    .into_iter()
+                    .map(|overview| overview.id)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(list(Default::default()).await, vec![p3, p2, p1]);
+        let request = PredictionListRequest {
+            state: Some("Trading".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(list(request).await, vec![p2, p1]);
+        let request = PredictionListRequest {
+            category: Some("Weather".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(list(request).await, vec![p3]);
+        let request = PredictionListRequest {
+            judge: Some(j1),
+            ..Default::default()
+        };
+        assert_eq!(list(request).await, vec![p3, p1]);
+        let request = PredictionListRequest {
+            creator: Some(u1),
+            ..Default::default()
+        };
+        assert!(list(request).await.is_empty());
+        let request = PredictionListRequest {
+            trading_end_before: Some(Utc::now() + Duration::hours(84)),
+            ..Default::default()
+        };
+        assert_eq!(list(request).await, vec![p2]);
+        let request = PredictionListRequest {
+            search: Some("btc".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(list(request).await, vec![p2, p1]);
+        let request = PredictionListRequest {
+            search: Some("BTC eth".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(list(request).await, vec![p2]);
+        let request = PredictionListRequest {
+            sort: PredictionSort::ClosingSoon,
+            ..Default::default()
+        };
+        assert_eq!(list(request).await, vec![p2, p3, p1]);
+
+        // Page through the predictions by volume
+        let mut request = PredictionListRequest {
+            sort: PredictionSort::Volume,
+            limit: Some(1),
+            ..Default::default()
+        };
+        let mut pages = vec![];
+        loop {
+            let page = market.get_predictions(request.clone()).await.unwrap();
+            pages.extend(page.predictions.iter().map(|overview| overview.id));
+            match page.next_cursor {
+                Some(cursor) => request.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(pages, vec![p2, p1, p3]);
+        request.cursor = Some("invalid".to_string());
+        market.get_predictions(request).await.unwrap_err();
+        let request = PredictionListRequest {
+            limit: Some(0),
+            ..Default::default()
+        };
+        market.get_predictions(request).await.unwrap_err();
+
+        let bets = market
+            .get_bets(
+                None,
+                Some(u1),
+                PageRequest {
+                    cursor: None,
+                    limit: Some(1),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(bets.len(), 1);
+        assert_eq!(bets[0].prediction, p1);
+        let bets = market
+            .get_bets(
+                None,
+                Some(u1),
+                PageRequest {
+                    cursor: Some(bets[0].id),
+                    limit: None,
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(bets.len(), 1);
+        assert_eq!(bets[0].prediction, p2);
+    }
+    #[tokio::test]
+    async fn halt_and_resume() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let prediction = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "Halting works".to_string(),
+                    outcomes: vec![],
+                    scalar: None,
+                    judges: vec![j1],
+                    judge_share_ppm: 0,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 1,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        market
+            .accept_nomination(prediction, j1, access.clone())
+            .await
+            .unwrap();
+        market
+            .adjust_balance(u1, 100, access.clone())
+            .await
+            .unwrap();
+        market
+            .add_bet(prediction, u1, 0, 50, access.clone())
+            .await
+            .unwrap();
+        let bet = market
+            .get_bets(
+                Some(prediction),
+                Some(u1),
+                PageRequest::default(),
+                access.clone(),
+            )
+            .await
+            .unwrap()[0]
+            .id;
+
+        market
+            .halt_prediction(prediction, " ".to_string(), access.clone())
+            .await
+            .unwrap_err();
+        market
+            .halt_prediction(prediction, "Oracle is down".to_string(), access.clone())
+            .await
+            .unwrap();
+        market
+            .halt_prediction(prediction, "Oracle is down".to_string(), access.clone())
+            .await
+            .unwrap_err();
+        let overview = market.get_prediction_overview(prediction).await.unwrap();
+        assert_eq!(overview.state, MarketState::Stopped);
+        assert_eq!(overview.halt_reason, Some("Oracle is down".to_string()));
+        let err = market
+            .add_bet(prediction, u1, 0, 50, access.clone())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Oracle is down"));
+        market.cancel_bet(bet, access.clone()).await.unwrap_err();
+        market
+            .make_decision(prediction, j1, 0, access.clone())
+            .await
+            .unwrap_err();
+        market.advance_markets().await.unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::Stopped
+        );
+
+        market
+            .resume_prediction(prediction, false, access.clone())
+            .await
+            .unwrap();
+        market
+            .resume_prediction(prediction, false, access.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::Trading
+        );
+        market
+            .add_bet(prediction, u1, 0, 10, access.clone())
+            .await
+            .unwrap();
+
+        // Resuming with extension pushes back the trading end by the halted time
+        let trading_end = market.db.get_trading_end(prediction).await.unwrap();
+        market
+            .db
+            .halt_prediction(prediction, "Maintenance", Utc::now() - Duration::hours(1))
+            .await
+            .unwrap();
+        market
+            .resume_prediction(prediction, true, access.clone())
+            .await
+            .unwrap();
+        let extension = market.db.get_trading_end(prediction).await.unwrap() - trading_end;
+        assert!(extension >= Duration::hours(1));
+        assert!(extension < Duration::hours(1) + Duration::minutes(1));
+
+        market
+            .force_refund(prediction, access.clone())
+            .await
+            .unwrap_err();
+        market
+            .halt_prediction(prediction, "Ambiguous terms".to_string(), access.clone())
+            .await
+            .unwrap();
+        market
+            .force_refund(prediction, access.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::Refunded(RefundReason::Admin)
+        );
+        assert_eq!(
+            market
+                .get_available_balance(u1, access.clone())
+                .await
+                .unwrap(),
+            100
+        );
+        market
+            .halt_prediction(prediction, "Too late".to_string(), access.clone())
+            .await
+            .unwrap_err();
+    }
+    #[tokio::test]
+    async fn ledger() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let request = NewPredictionRequest {
+            prediction: "Ledger works".to_string(),
+            outcomes: vec![],
+            scalar: None,
+            judges: vec![j1],
+            judge_share_ppm: 100000,
+            trading_end: Utc::now() + Duration::days(3),
+            decision_period_sec: 86400,
+            judge_count: 1,
+            judge_bond: 0,
+            dispute_period_sec: 0,
+            dispute_bond: 0,
+            appeal_judges: vec![],
+            reveal_period_sec: 0,
+            judge_replacement_sec: 0,
+            creator_fee_ppm: 0,
+            metadata: PredictionMetadata::default(),
+            resolution_rules: ResolutionRules::default(),
+        };
+        let resolved = market
+            .new_prediction(request.clone(), access.clone())
+            .await
+            .unwrap();
+        let refunded = market
+            .new_prediction(request, access.clone())
+            .await
+            .unwrap();
+        for user in [u1, u2] {
+            market
+                .adjust_balance(user, 200, access.clone())
+                .await
+                .unwrap();
+        }
+        for prediction in [resolved, refunded] {
+            market
+                .accept_nomination(prediction, j1, access.clone())
+                .await
+                .unwrap();
+            market
+                .add_bet(prediction, u1, 0, 100, access.clone())
+                .await
+                .unwrap();
+            market
+                .add_bet(prediction, u2, 1, 100, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .force_decision_period(resolved, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_decision(resolved, j1, 0, access.clone())
+            .await
+            .unwrap();
+        market
+            .halt_prediction(refunded, "Ambiguous terms".to_string(), access.clone())
+            .await
+            .unwrap();
+        market.force_refund(refunded, access.clone()).await.unwrap();
+
+        let bets = market
+            .get_bets(None, Some(u1), PageRequest::default(), access.clone())
+            .await
+            .unwrap();
+        assert_eq!(bets.len(), 2);
+        assert_eq!(bets[0].status, BetStatus::Settled);
+        assert_eq!(bets[1].status, BetStatus::Refunded);
+        market
+            .cancel_bet(bets[1].id, access.clone())
+            .await
+            .unwrap_err();
+
+        // The ledger explains every balance change
+        for user in [u1, u2, j1] {
+            let entries = market
+                .get_ledger(None, Some(user), PageRequest::default(), access.clone())
+                .await
+                .unwrap();
+            let booked: Sats = entries.iter().map(|entry| entry.amount).sum();
+            let initial = if user == j1 { 0 } else { 200 };
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap() - initial,
+                booked
+            );
+        }
+        let entries = market
+            .get_ledger(
+                Some(resolved),
+                Some(u1),
+                PageRequest::default(),
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].bet, Some(bets[0].id));
+        assert_eq!(
+            (entries[0].kind, entries[0].amount),
+            (LedgerKind::Stake, -100)
+        );
+        assert_eq!(entries[1].kind, LedgerKind::Payout);
+        assert!(entries[1].amount > 100);
+        let entries = market
+            .get_ledger(
+                Some(resolved),
+                Some(j1),
+                PageRequest::default(),
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].kind, LedgerKind::JudgeFee);
+        let entries = market
+            .get_ledger(
+                Some(refunded),
+                Some(u2),
+                PageRequest::default(),
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        let entries: Vec<_> = entries
+            .into_iter()
+            .map(|entry| (entry.kind, entry.amount))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![(LedgerKind::Stake, -100), (LedgerKind::Refund, 100)]
+        );
+    }
+    #[tokio::test]
+    async fn books() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, house) = generate_keypair(&mut rand::thread_rng());
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee {
+                fee_ppm: 20000,
+                house_account: Some(house),
+            },
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        for user in [u1, u2] {
+            market
+                .adjust_balance(user, 1000, access.clone())
+                .await
+                .unwrap();
+        }
+        let request = NewPredictionRequest {
+            prediction: "Books balance".to_string(),
+            outcomes: vec![],
+            scalar: None,
+            judges: vec![j1],
+            judge_share_ppm: 100000,
+            trading_end: Utc::now() + Duration::days(3),
+            decision_period_sec: 86400,
+            judge_count: 1,
+            judge_bond: 0,
+            dispute_period_sec: 0,
+            dispute_bond: 0,
+            appeal_judges: vec![],
+            reveal_period_sec: 0,
+            judge_replacement_sec: 0,
+            creator_fee_ppm: 10000,
+            metadata: PredictionMetadata::default(),
+            resolution_rules: ResolutionRules::default(),
+        };
+        let prediction = market
+            .new_prediction(request.clone(), access.clone())
+            .await
+            .unwrap();
+        let amm = market
+            .new_amm_prediction(
+                NewAmmPredictionRequest {
+                    creator: u1,
+                    subsidy: 100,
+                    prediction: request,
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        for prediction in [prediction, amm] {
+            market
+                .accept_nomination(prediction, j1, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .add_bet(prediction, u1, 0, 333, access.clone())
+            .await
+            .unwrap();
+        market
+            .add_bet(prediction, u2, 1, 101, access.clone())
+            .await
+            .unwrap();
+        market
+            .buy_shares(
+                TradeSharesRequest {
+                    prediction: amm,
+                    user: u2,
+                    outcome: 1,
+                    shares: 50,
+                    limit: None,
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        let books = market.check_books(access.clone()).await.unwrap();
+        assert!(books.is_balanced());
+        assert_eq!(books.accounts[0], (Account::Adjustments, -2000));
+        // Stakes of bets and market maker trades are escrowed right away
+        assert_eq!(books.accounts.len(), 3);
+        assert_eq!(books.accounts[1], (Account::Escrow(prediction), 434));
+        assert_eq!(books.accounts[2].0, Account::Escrow(amm));
+
+        for prediction in [prediction, amm] {
+            market
+                .force_decision_period(prediction, access.clone())
+                .await
+                .unwrap();
+            market
+                .make_decision(prediction, j1, 1, access.clone())
+                .await
+                .unwrap();
+        }
+        let books = market.check_books(access.clone()).await.unwrap();
+        assert!(books.is_balanced());
+        // Everything that was in escrow got paid out
+        assert_eq!(books.accounts, vec![(Account::Adjustments, -2000)]);
+        let mut total = 0;
+        // The prediction creator collects the creator fee
+        for user in [j1, house, u1, u2, access.user] {
+            total += market.get_balance(user, access.clone()).await.unwrap();
+        }
+        assert_eq!(total, 2000);
+    }
+    #[tokio::test]
+    async fn reconcile() {
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+
+        // The test funding source settles the invoice right away
+        let (deposit, _) = market
+            .init_deposit_bolt11(u1, 1000, access.clone())
+            .await
+            .unwrap();
+        let reconciliation = market.reconcile(access.clone()).await.unwrap();
+        assert_eq!(reconciliation.wallet_balance, 1000);
+        assert_eq!(reconciliation.liabilities, 0);
+        assert_eq!(reconciliation.discrepancy, 1000);
+        assert_eq!(
+            reconciliation.mismatched_txs,
+            vec![TxMismatch {
+                id: deposit,
+                stored: TxStateBolt11::PayInit(1000),
+                funding: Some(TxStateBolt11::Settled(1000)),
+            }]
+        );
+        market.check_tx(deposit, access.clone()).await.unwrap();
+        assert!(market
+            .reconcile(access.clone())
+            .await
+            .unwrap()
+            .is_reconciled());
+
+        let withdrawal = market
+            .init_withdrawal_bolt11(u1, "invoice".to_string(), 100, access.clone())
+            .await
+            .unwrap();
+        let reconciliation = market.reconcile(access.clone()).await.unwrap();
+        assert_eq!(reconciliation.user_balances, 900);
+        assert_eq!(reconciliation.pending_withdrawals, 100);
+        assert_eq!(reconciliation.wallet_balance, 900);
+        assert_eq!(reconciliation.discrepancy, -100);
+        assert_eq!(reconciliation.mismatched_txs[0].id, withdrawal);
+        market.check_tx(withdrawal, access.clone()).await.unwrap();
+        assert!(market
+            .reconcile(access.clone())
+            .await
+            .unwrap()
+            .is_reconciled());
+
+        // Balance adjustments aren't backed by the wallet
+        market.adjust_balance(u1, 50, access.clone()).await.unwrap();
+        let reconciliation = market.reconcile(access.clone()).await.unwrap();
+        assert_eq!(reconciliation.liabilities, 950);
+        assert_eq!(reconciliation.discrepancy, -50);
+    }
+    #[tokio::test]
+    async fn exact_payouts() {
+        assert_eq!(distribute(100, &[1, 1, 1]), vec![34, 33, 33]);
+        assert_eq!(distribute(10, &[1, 2, 3, 0]), vec![2, 3, 5, 0]);
+        assert_eq!(distribute(7, &[0, 0]), vec![0, 0]);
+        assert_eq!(distribute(0, &[5]), vec![0]);
+
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let prediction = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "Nobody wins".to_string(),
+                    outcomes: vec![],
+                    scalar: None,
+                    judges: vec![j1],
+                    judge_share_ppm: 100000,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 1,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        market
+            .accept_nomination(prediction, j1, access.clone())
+            .await
+            .unwrap();
+        market
+            .adjust_balance(u1, 100, access.clone())
+            .await
+            .unwrap();
+        market
+            .add_bet(prediction, u1, 0, 100, access.clone())
+            .await
+            .unwrap();
+        market
+            .force_decision_period(prediction, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_decision(prediction, j1, 1, access.clone())
+            .await
+            .unwrap();
+        // Without a house account the winnings nobody is entitled to end up
+        // in the dust account
+        assert_eq!(market.get_balance(u1, access.clone()).await.unwrap(), 0);
+        assert_eq!(market.get_balance(j1, access.clone()).await.unwrap(), 10);
+        let books = market.check_books(access.clone()).await.unwrap();
+        assert!(books.is_balanced());
+        assert_eq!(
+            books.accounts,
+            vec![(Account::Adjustments, -100), (Account::Dust, 90)]
+        );
+    }
+    #[tokio::test]
+    async fn quote_payout() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let (_, u3) = generate_keypair(&mut rand::thread_rng());
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let prediction = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "Quotes match payouts".to_string(),
+                    outcomes: vec![],
+                    scalar: None,
+                    judges: vec![j1],
+                    judge_share_ppm: 100000,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 1,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        market
+            .accept_nomination(prediction, j1, access.clone())
+            .await
+            .unwrap();
+        for (user, outcome) in [(u1, 0), (u2, 1)] {
+            market
+                .adjust_balance(user, 100, access.clone())
+                .await
+                .unwrap();
+            market
+                .add_bet(prediction, user, outcome, 100, access.clone())
+                .await
+                .unwrap();
+        }
+        let request = PayoutQuoteRequest {
+            prediction,
+            outcome: 0,
+            amount: 0,
+            user: None,
+        };
+        market
+            .quote_payout(request.clone(), access.clone())
+            .await
+            .unwrap_err();
+        market
+            .quote_payout(
+                PayoutQuoteRequest {
+                    outcome: 2,
+                    amount: 100,
+                    ..request.clone()
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap_err();
+        let quote = market
+            .quote_payout(
+                PayoutQuoteRequest {
+                    amount: 100,
+                    ..request.clone()
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            quote,
+            PayoutQuoteResponse {
+                stake: 100,
+                payout: 135,
+                implied_probability: Some(200.0 / 300.0),
+                odds: Some(1.35),
+            }
+        );
+        assert_eq!(ratio(100, 0), None);
+        // The existing bet of u1 is part of the quote
+        let quote = market
+            .quote_payout(
+                PayoutQuoteRequest {
+                    amount: 100,
+                    user: Some(u1),
+                    ..request.clone()
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(quote.stake, 200);
+        assert_eq!(quote.payout, 270);
+
+        // Order book positions of the user pay out in full on top
+        for (user, outcome, price, balance) in [(u1, 0, 60, 120), (u2, 1, 40, 80)] {
+            market
+                .adjust_balance(user, balance, access.clone())
+                .await
+                .unwrap();
+            market
+                .place_order(
+                    PlaceOrderRequest {
+                        prediction,
+                        user,
+                        outcome,
+                        side: OrderSide::Bid,
+                        price,
+                        quantity: 2,
+                    },
+                    access.clone(),
+                )
+                .await
+                .unwrap();
+        }
+        let request = PayoutQuoteRequest {
+            user: Some(u1),
+            ..request
+        };
+        let quote = market
+            .quote_payout(request.clone(), access.clone())
+            .await
+            .unwrap();
+        assert_eq!(quote.stake, 220);
+        assert_eq!(quote.payout, 380);
+
+        market
+            .adjust_balance(u3, 100, access.clone())
+            .await
+            .unwrap();
+        market
+            .add_bet(prediction, u3, 0, 100, access.clone())
+            .await
+            .unwrap();
+        let quote = market
+            .quote_payout(request.clone(), access.clone())
+            .await
+            .unwrap();
+        assert_eq!(quote.payout, 335);
+        market
+            .force_decision_period(prediction, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_decision(prediction, j1, 0, access.clone())
+            .await
+            .unwrap();
+        assert_eq!(market.get_balance(u3, access.clone()).await.unwrap(), 135);
+        assert_eq!(market.get_balance(u1, access.clone()).await.unwrap(), 335);
+    }
+    #[tokio::test]
+    async fn prediction_history() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let prediction = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "History gets recorded".to_string(),
+                    outcomes: vec![],
+                    scalar: None,
+                    judges: vec![j1],
+                    judge_share_ppm: 100000,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 1,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        market
+            .accept_nomination(prediction, j1, access.clone())
+            .await
+            .unwrap();
+        for (user, outcome, amount) in [(u1, 0, 100), (u2, 1, 50), (u2, 0, 30)] {
+            market
+                .adjust_balance(user, amount, access.clone())
+                .await
+                .unwrap();
+            market
+                .add_bet(prediction, user, outcome, amount, access.clone())
+                .await
+                .unwrap();
+        }
+        let bets = market
+            .get_bets(
+                Some(prediction),
+                Some(u2),
+                PageRequest::default(),
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        market.cancel_bet(bets[1].id, access.clone()).await.unwrap();
+
+        let request = PredictionHistoryRequest {
+            prediction,
+            bucket_sec: 0,
+            from: None,
+            to: None,
+        };
+        market
+            .get_prediction_history(request.clone())
+            .await
+            .unwrap_err();
+        let history = market
+            .get_prediction_history(PredictionHistoryRequest {
+                bucket_sec: u32::MAX,
+                ..request.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            history,
+            vec![HistoryBucket {
+                start: DateTime::from_timestamp(0, 0).unwrap(),
+                open: vec![0, 0],
+                close: vec![100, 50],
+                volume: 180,
+                bets: 3,
+            }]
+        );
+        let history = market
+            .get_prediction_history(PredictionHistoryRequest {
+                bucket_sec: 60,
+                from: Some(Utc::now() + Duration::hours(1)),
+                ..request
+            })
+            .await
+            .unwrap();
+        assert!(history.is_empty());
+    }
+    #[tokio::test]
+    async fn portfolio() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let request = NewPredictionRequest {
+            prediction: "Resolved".to_string(),
+            outcomes: vec![],
+            scalar: None,
+            judges: vec![j1],
+            judge_share_ppm: 100000,
+            trading_end: Utc::now() + Duration::days(3),
+            decision_period_sec: 86400,
+            judge_count: 1,
+            judge_bond: 0,
+            dispute_period_sec: 0,
+            dispute_bond: 0,
+            appeal_judges: vec![],
+            reveal_period_sec: 0,
+            judge_replacement_sec: 0,
+            creator_fee_ppm: 0,
+            metadata: PredictionMetadata::default(),
+            resolution_rules: ResolutionRules::default(),
+        };
+        let resolved = market
+            .new_prediction(request.clone(), access.clone())
+            .await
+            .unwrap();
+        let open = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "Open".to_string(),
+                    ..request.clone()
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        market
+            .adjust_balance(u2, 200, access.clone())
+            .await
+            .unwrap();
+        let mut amms = vec![];
+        for name in ["Resolved market maker", "Open market maker"] {
+            let amm = market
+                .new_amm_prediction(
+                    NewAmmPredictionRequest {
+                        creator: u2,
+                        subsidy: 100,
+                        prediction: NewPredictionRequest {
+                            prediction: name.to_string(),
+                            ..request.clone()
+                        },
+                    },
+                    access.clone(),
+                )
+                .await
+                .unwrap();
+            amms.push(amm);
+        }
+        let (amm_resolved, amm_open) = (amms[0], amms[1]);
+        for prediction in [resolved, open, amm_resolved, amm_open] {
+            market
+                .accept_nomination(prediction, j1, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .adjust_balance(u1, 150, access.clone())
+            .await
+            .unwrap();
+        market
+            .adjust_balance(u2, 250, access.clone())
+            .await
+            .unwrap();
+        for (prediction, user, outcome, amount) in [
+            (resolved, u1, 0, 100),
+            (resolved, u2, 1, 100),
+            (open, u1, 1, 50),
+            (open, u2, 0, 150),
+        ] {
+            market
+                .add_bet(prediction, user, outcome, amount, access.clone())
+                .await
+                .unwrap();
+        }
+        // u1 also holds an order book contract and market maker shares
+        market
+            .adjust_balance(u1, 200, access.clone())
+            .await
+            .unwrap();
+        market.adjust_balance(u2, 40, access.clone()).await.unwrap();
+        for (user, outcome, price) in [(u1, 0, 60), (u2, 1, 40)] {
+            market
+                .place_order(
+                    PlaceOrderRequest {
+                        prediction: open,
+                        user,
+                        outcome,
+                        side: OrderSide::Bid,
+                        price,
+                        quantity: 1,
+                    },
+                    access.clone(),
+                )
+                .await
+                .unwrap();
+        }
+        let mut costs = vec![];
+        for prediction in [amm_resolved, amm_open] {
+            let cost = market
+                .buy_shares(
+                    TradeSharesRequest {
+                        prediction,
+                        user: u1,
+                        outcome: 0,
+                        shares: 20,
+                        limit: None,
+                    },
+                    access.clone(),
+                )
+                .await
+                .unwrap();
+            costs.push(cost);
+        }
+        for prediction in [resolved, amm_resolved] {
+            market
+                .force_decision_period(prediction, access.clone())
+                .await
+                .unwrap();
+            market
+                .make_decision(prediction, j1, 0, access.clone())
+                .await
+                .unwrap();
+        }
+
+        let portfolio = market.get_portfolio(u1, access.clone()).await.unwrap();
+        let balance = 180 + 200 - 60 - costs[0] - costs[1] + 20;
+        assert_eq!(portfolio.balance, balance);
+        assert_eq!(portfolio.available_balance, balance);
+        assert_eq!(portfolio.open_stake, 50 + 60 + costs[1]);
+        assert_eq!(portfolio.realized_pnl, 80 + 20 - costs[0]);
+        assert_eq!(portfolio.positions.len(), 4);
+        let position = &portfolio.positions[0];
+        assert_eq!(position.state, MarketState::Resolved(0));
+        assert_eq!(position.stakes, vec![100, 0]);
+        assert!(position.projected_payouts.is_empty());
+        assert_eq!(position.realized_pnl, 80);
+        let position = &portfolio.positions[1];
+        assert_eq!(position.name, "Open");
+        assert_eq!(position.state, MarketState::Trading);
+        assert_eq!(position.stakes, vec![60, 50]);
+        assert_eq!(position.contracts, vec![1, 0]);
+        assert_eq!(position.projected_payouts, vec![100, 180]);
+        assert_eq!(position.realized_pnl, 0);
+        // The payout of the shares is measured against what they cost
+        let position = &portfolio.positions[2];
+        assert_eq!(position.prediction, amm_resolved);
+        assert_eq!(position.stakes, vec![costs[0], 0]);
+        assert_eq!(position.shares, vec![20, 0]);
+        assert!(position.projected_payouts.is_empty());
+        assert_eq!(position.realized_pnl, 20 - costs[0]);
+        let position = &portfolio.positions[3];
+        assert_eq!(position.prediction, amm_open);
+        assert_eq!(position.stakes, vec![costs[1], 0]);
+        assert_eq!(position.shares, vec![20, 0]);
+        assert_eq!(position.projected_payouts, vec![20, 0]);
+        assert_eq!(position.realized_pnl, 0);
+
+        let portfolio = market.get_portfolio(j1, access.clone()).await.unwrap();
+        let judge_fee = calculate_fee(100, 100000);
+        assert_eq!(portfolio.judge_fees, 20 + judge_fee);
+        assert_eq!(portfolio.realized_pnl, 20 + judge_fee);
+        assert_eq!(portfolio.positions[0].prediction, resolved);
+    }
+    #[tokio::test]
+    async fn leaderboards() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j2) = generate_keypair(&mut rand::thread_rng());
+        let (_, j3) = generate_keypair(&mut rand::thread_rng());
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let (_, u3) = generate_keypair(&mut rand::thread_rng());
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        // u3 and j3 stay anonymous
+        for (user, name) in [(u1, "u1"), (u2, "u2"), (j1, "j1"), (j2, "j2")] {
+            market
+                .adjust_balance(user, 200, access.clone())
+                .await
+                .unwrap();
+            market
+                .update_user(user, Some(name.to_string()), access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .adjust_balance(u3, 200, access.clone())
+            .await
+            .unwrap();
+        let request = NewPredictionRequest {
+            prediction: "Sports".to_string(),
+            outcomes: vec![],
+            scalar: None,
+            judges: vec![j1, j2, j3],
+            judge_share_ppm: 100000,
+            trading_end: Utc::now() + Duration::days(3),
+            decision_period_sec: 86400,
+            judge_count: 3,
+            judge_bond: 0,
+            dispute_period_sec: 0,
+            dispute_bond: 0,
+            appeal_judges: vec![],
+            reveal_period_sec: 0,
+            judge_replacement_sec: 0,
+            creator_fee_ppm: 0,
+            metadata: PredictionMetadata {
+                category: Some("sports".to_string()),
+                ..Default::default()
+            },
+            resolution_rules: ResolutionRules::default(),
+        };
+        let sports = market
+            .new_prediction(request.clone(), access.clone())
+            .await
+            .unwrap();
+        let politics = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "Politics".to_string(),
+                    judges: vec![j1],
+                    judge_count: 1,
+                    metadata: PredictionMetadata {
+                        category: Some("politics".to_string()),
+                        ..Default::default()
+                    },
+                    ..request.clone()
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        for (prediction, judges) in [(sports, vec![j1, j2, j3]), (politics, vec![j1])] {
+            for judge in judges {
+                market
+                    .accept_nomination(prediction, judge, access.clone())
+                    .await
+                    .unwrap();
+            }
+        }
+        for (prediction, user, outcome, amount) in [
+            (sports, u1, 0, 100),
+            (sports, u2, 1, 100),
+            (sports, u3, 0, 50),
+            (politics, u2, 0, 100),
+            (politics, u1, 1, 50),
+        ] {
+            market
+                .add_bet(prediction, user, outcome, amount, access.clone())
+                .await
+                .unwrap();
+        }
+        for prediction in [sports, politics] {
+            market
+                .force_decision_period(prediction, access.clone())
+                .await
+                .unwrap();
+        }
+        for (prediction, judge, outcome) in [(sports, j1, 0), (sports, j2, 1), (sports, j3, 0)] {
+            market
+                .make_decision(prediction, judge, outcome, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .make_decision(politics, j1, 0, access.clone())
+            .await
+            .unwrap();
+
+        let leaderboard = market
+            .get_bettor_leaderboard(BettorLeaderboardRequest::default())
+            .await
+            .unwrap();
+        let ranks: Vec<(UserPubKey, Sats, Sats, u32)> = leaderboard
+            .iter()
+            .map(|rank| (rank.user, rank.profit, rank.volume, rank.correct))
+            .collect();
+        assert_eq!(ranks, vec![(u1, 0, 150, 1), (u2, -65, 200, 1)]);
+        let leaderboard = market
+            .get_bettor_leaderboard(BettorLeaderboardRequest {
+                rank_by: BettorRanking::Volume,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(leaderboard[0].user, u2);
+        let leaderboard = market
+            .get_bettor_leaderboard(BettorLeaderboardRequest {
+                category: Some("sports".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(leaderboard[0].profit, 50);
+        assert_eq!(leaderboard[0].roi, 0.5);
+        assert_eq!(leaderboard[1].profit, -100);
+        assert!(market
+            .get_bettor_leaderboard(BettorLeaderboardRequest {
+                from: Some(Utc::now() + Duration::hours(1)),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .is_empty());
+        // Predictions count from their settlement, not from the first stake
+        market
+            .db
+            .add_ledger_entry(
+                u1,
+                sports,
+                None,
+                LedgerKind::Stake,
+                0,
+                Utc::now() - Duration::days(2),
+            )
+            .await
+            .unwrap();
+        let leaderboard = market
+            .get_bettor_leaderboard(BettorLeaderboardRequest {
+                from: Some(Utc::now() - Duration::hours(1)),
+                category: Some("sports".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(leaderboard.len(), 2);
+
+        let leaderboard = market
+            .get_judge_leaderboard(JudgeLeaderboardRequest {
+                rank_by: JudgeRanking::Agreement,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(leaderboard.len(), 2);
+        assert_eq!(leaderboard[0].user, j1);
+        assert_eq!(leaderboard[0].votes, 2);
+        assert_eq!(leaderboard[0].agreement, Some(1.0));
+        assert!(leaderboard[0].fees >= 15 + 12);
+        assert_eq!(leaderboard[1].user, j2);
+        assert_eq!(leaderboard[1].fees, 0);
+        assert_eq!(leaderboard[1].agreement, Some(0.0));
+
+        // Long and short both pay out but neither is the resolved outcome
+        let scalar = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "Temperature".to_string(),
+                    scalar: Some(ScalarRange { min: 0, max: 100 }),
+                    judges: vec![j1],
+                    judge_count: 1,
+                    metadata: PredictionMetadata {
+                        category: Some("weather".to_string()),
+                        ..Default::default()
+                    },
+                    ..request
+                },
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        market
+            .accept_nomination(scalar, j1, access.clone())
+            .await
+            .unwrap();
+        for (user, outcome) in [(u1, SCALAR_LONG), (u2, SCALAR_SHORT)] {
+            market
+                .add_bet(scalar, user, outcome, 50, access.clone())
+                .await
+                .unwrap();
+        }
+        market
+            .force_decision_period(scalar, access.clone())
+            .await
+            .unwrap();
+        market
+            .make_scalar_decision(scalar, j1, 70, access.clone())
+            .await
+            .unwrap();
+        let leaderboard = market
+            .get_bettor_leaderboard(BettorLeaderboardRequest {
+                category: Some("weather".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(leaderboard.len(), 2);
+        assert!(leaderboard.iter().all(|rank| rank.correct == 0));
+    }
+    #[tokio::test]
+    async fn resolution_rules() {
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let judges: Vec<_> = (0..3)
+            .map(|_| generate_keypair(&mut rand::thread_rng()).1)
+            .collect();
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let request = |resolution_rules| NewPredictionRequest {
+            prediction: "Rules".to_string(),
+            outcomes: vec![],
+            scalar: None,
+            judges: judges.clone(),
+            judge_share_ppm: 0,
+            trading_end: Utc::now() + Duration::days(3),
+            decision_period_sec: 86400,
+            judge_count: 3,
+            judge_bond: 0,
+            dispute_period_sec: 0,
+            dispute_bond: 0,
+            appeal_judges: vec![],
+            reveal_period_sec: 0,
+            judge_replacement_sec: 0,
+            creator_fee_ppm: 0,
+            metadata: PredictionMetadata::default(),
+            resolution_rules,
+        };
+        assert!(market
+            .new_prediction(
+                request(ResolutionRules {
+                    quorum: 4,
+                    ..Default::default()
+                }),
+                access.clone()
+            )
+            .await
+            .is_err());
+        assert!(market
+            .new_prediction(
+                request(ResolutionRules {
+                    supermajority_ppm: 1000001,
+                    ..Default::default()
+                }),
+                access.clone()
+            )
+            .await
+            .is_err());
+
+        let extend_rules = ResolutionRules {
+            supermajority_ppm: 750000,
+            quorum: 2,
+            on_no_consensus: NoConsensusAction::Extend,
+        };
+        let extended = market
+            .new_prediction(request(extend_rules), access.clone())
+            .await
+            .unwrap();
+        let refunded = market
+            .new_prediction(
+                request(ResolutionRules {
+                    supermajority_ppm: 750000,
+                    ..Default::default()
+                }),
+                access.clone(),
+            )
+            .await
+            .unwrap();
+        for user in [u1, u2] {
+            market
+                .adjust_balance(user, 200, access.clone())
+                .await
+                .unwrap();
+        }
+        for prediction in [extended, refunded] {
+            for judge in judges.iter() {
+                market
+                    .accept_nomination(prediction, *judge, access.clone())
+                    .await
+                    .unwrap();
+            }
+            market
+                .add_bet(prediction, u1, 0, 100, access.clone())
+                .await
+                .unwrap();
+            market
+                .add_bet(prediction, u2, 1, 100, access.clone())
+                .await
+                .unwrap();
+            market
+                .db
+                .set_trading_end(prediction, Utc::now() - Duration::hours(1))
+                .await
+                .unwrap();
+        }
+        market.advance_markets().await.unwrap();
+
+        // Two out of three votes miss the supermajority of 75%
+        for prediction in [extended, refunded] {
+            for (judge, decision) in judges.iter().zip([0, 0]) {
+                market
+                    .make_decision(prediction, *judge, decision, access.clone())
+                    .await
+                    .unwrap();
+            }
+        }
+        market
+            .make_decision(extended, judges[2], 1, access.clone())
+            .await
+            .unwrap();
+        assert!(market
+            .make_decision(refunded, judges[2], 1, access.clone())
+            .await
+            .is_err());
+        assert_eq!(
+            market.db.get_prediction_state(refunded).await.unwrap(),
+            MarketState::Refunded(RefundReason::NoConsensus)
+        );
+
+        let overview = market.db.get_prediction_overview(extended).await.unwrap();
+        assert_eq!(overview.state, MarketState::WaitingForDecision);
+        assert_eq!(overview.resolution_rules, extend_rules);
+        assert!(overview.decision_extended);
+        assert_eq!(overview.decision_period_sec, 2 * 86400);
+        assert!(market
+            .db
+            .get_judge_states(extended)
+            .await
+            .unwrap()
+            .iter()
+            .all(|state| *state == JudgeState::Accepted));
+
+        // The quorum decides once the extended decision period is over
+        for judge in judges[..2].iter() {
+            market
+                .make_decision(extended, *judge, 0, access.clone())
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            market.db.get_prediction_state(extended).await.unwrap(),
+            MarketState::WaitingForDecision
+        );
+        market
+            .db
+            .set_trading_end(extended, Utc::now() - Duration::days(3))
+            .await
+            .unwrap();
+        market.advance_markets().await.unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(extended).await.unwrap(),
+            MarketState::Resolved(0)
+        );
+        assert_eq!(market.db.get_user_balance(u1).await.unwrap(), 300);
+        assert_eq!(market.db.get_user_balance(u2).await.unwrap(), 100);
+    }
+    #[tokio::test]
+    async fn boolean_markets_migration() {
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let path = std::env::temp_dir().join(format!("mercado_legacy_{}.db", u1));
+        let url = format!("sqlite://{}?mode=rwc", path.to_str().unwrap());
+
+        // Schema and rows of a database from before categorical predictions
+        let legacy = sqlx::SqlitePool::connect(&url).await.unwrap();
+        for statement in [
+            "CREATE TABLE predictions (id PRIMARY KEY, prediction, judge_share_ppm, state, \
+            trading_end, decision_period, judge_count)",
+            "CREATE TABLE bets (user NOT NULL, prediction NOT NULL, bet NOT NULL, amount)",
+            "CREATE TABLE judges (user, prediction, state NOT NULL, decision, \
+            PRIMARY KEY (user,prediction))",
+            "INSERT INTO predictions (prediction, judge_share_ppm, state, trading_end, \
+            decision_period, judge_count) VALUES ('Legacy', 0, 'Resolved(false)', 0, 86400, 1)",
+        ] {
+            sqlx::query(statement).execute(&legacy).await.unwrap();
+        }
+        for (user, bet, amount) in [(u1, true, 100), (u2, false, 50)] {
+            sqlx::query("INSERT INTO bets (user, prediction, bet, amount) VALUES (?,1,?,?)")
+                .bind(user.to_string())
+                .bind(bet)
+                .bind(amount)
+                .execute(&legacy)
+                .await
+                .unwrap();
+        }
+        sqlx::query("INSERT INTO judges VALUES (?,1,'Resolved(false)',false)")
+            .bind(j1.to_string())
+            .execute(&legacy)
+            .await
+            .unwrap();
+        legacy.close().await;
+
+        let db = DB::new(url.clone()).await;
+        let overview = db.get_prediction_overview(1).await.unwrap();
+        assert_eq!(
+            overview.outcomes,
+            DEFAULT_OUTCOMES.map(String::from).to_vec()
+        );
+        assert_eq!(overview.state, MarketState::Resolved(1));
+        assert_eq!(overview.ratio, vec![100, 50]);
+        assert_eq!(
+            db.get_judge_state(1, j1).await.unwrap(),
+            JudgeState::Resolved(1)
+        );
+        let bets = db
+            .get_bets(Some(1), Some(u2), false, PageRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(bets[0].outcome, 1);
+        drop(db);
+
+        // The migration only runs once
+        let db = DB::new(url).await;
+        assert_eq!(
+            db.get_prediction_overview(1).await.unwrap().ratio,
+            vec![100, 50]
+        );
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            MarketState::from_str("Resolved(true)").unwrap(),
+            MarketState::Resolved(0)
+        );
+        let request: AddBetRequest = serde_json::from_value(serde_json::json!({
+            "prediction": 1,
+            "user": u1,
+            "bet": false,
+            "amount": 10,
+        }))
+        .unwrap();
+        assert_eq!(request.outcome, 1);
+        let request: MakeDecisionRequest = serde_json::from_value(serde_json::json!({
+            "prediction": 1,
+            "judge": j1,
+            "decision": true,
+        }))
+        .unwrap();
+        assert_eq!(request.decision, 0);
+    }
+    #[tokio::test]
+    async fn judge_reputation() {
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, judge) = generate_keypair(&mut rand::thread_rng());
+        let (_, k1) = generate_keypair(&mut rand::thread_rng());
+        let (_, k2) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        market
+            .adjust_balance(u1, 1000, access.clone())
+            .await
+            .unwrap();
+        let request = |judges: Vec<UserPubKey>| NewPredictionRequest {
+            prediction: "Judged".to_string(),
+            outcomes: vec![],
+            scalar: None,
+            judge_count: judges.len() as u32,
+            judges,
+            judge_share_ppm: 0,
+            trading_end: Utc::now() + Duration::days(3),
+            decision_period_sec: 86400,
+            judge_bond: 0,
+            dispute_period_sec: 0,
+            dispute_bond: 0,
+            appeal_judges: vec![],
+            reveal_period_sec: 0,
+            judge_replacement_sec: 0,
+            creator_fee_ppm: 0,
+            metadata: PredictionMetadata::default(),
+            resolution_rules: ResolutionRules::default(),
+        };
+        let refused = market
+            .new_prediction(request(vec![judge]), access.clone())
+            .await
+            .unwrap();
+        let ignored = market
+            .new_prediction(request(vec![judge]), access.clone())
+            .await
+            .unwrap();
+        let majority = market
+            .new_prediction(request(vec![judge, k1]), access.clone())
+            .await
+            .unwrap();
+        let minority = market
+            .new_prediction(request(vec![judge, k1, k2]), access.clone())
+            .await
+            .unwrap();
+        let timeout = market
+            .new_prediction(request(vec![judge]), access.clone())
+            .await
+            .unwrap();
+        market
+            .refuse_nomination(refused, judge, access.clone())
+            .await
+            .unwrap();
+        for (prediction, judges) in [
+            (majority, vec![judge, k1]),
+            (minority, vec![judge, k1, k2]),
+            (timeout, vec![judge]),
+        ] {
+            for judge in judges {
+                market
+                    .accept_nomination(prediction, judge, access.clone())
+                    .await
+                    .unwrap();
+            }
+        }
+        for (prediction, amount) in [(majority, 100), (minority, 30), (timeout, 10)] {
+            market
+                .add_bet(prediction, u1, 0, amount, access.clone())
+                .await
+                .unwrap();
+        }
+        for (prediction, votes) in [
+            (majority, vec![(judge, 0), (k1, 0)]),
+            (minority, vec![(judge, 1), (k1, 0), (k2, 0)]),
+        ] {
+            market
+                .force_decision_period(prediction, access.clone())
+                .await
+                .unwrap();
+            for (judge, decision) in votes {
+                market
+                    .make_decision(prediction, judge, decision, access.clone())
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(
+                market.db.get_prediction_state(prediction).await.unwrap(),
+                MarketState::Resolved(0)
+            );
+        }
+        market
+            .db
+            .set_trading_end(timeout, Utc::now() - Duration::days(2))
+            .await
+            .unwrap();
+        market.advance_markets().await.unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(timeout).await.unwrap(),
+            MarketState::Refunded(RefundReason::TimeForDecisionRanOut)
+        );
+
+        let reputation = market.get_judge_reputation(judge).await.unwrap();
+        assert_eq!(
+            reputation,
+            JudgeReputation {
+                user: judge,
+                nominations: 5,
+                accepted: 3,
+                refused: 1,
+                votes: 2,
+                majority_votes: 1,
+                minority_votes: 1,
+                missed_decisions: 1,
+                predictions_judged: 2,
+                sats_judged: 130,
+                score_ppm: Some(333333),
+            }
+        );
+        let judges = market.get_prediction_judges(majority, true).await.unwrap();
+        let listed = judges.iter().find(|j| j.user == judge).unwrap();
+        assert_eq!(listed.reputation, Some(reputation));
+        let k1_reputation = judges
+            .iter()
+            .find(|j| j.user == k1)
+            .unwrap()
+            .reputation
+            .unwrap();
+        assert_eq!(k1_reputation.majority_votes, 2);
+        assert_eq!(k1_reputation.score_ppm, Some(1000000));
+        assert!(market
+            .get_prediction_judges(majority, false)
+            .await
+            .unwrap()
+            .iter()
+            .all(|judge| judge.reputation.is_none()));
     }
-    pub async fn get_txs(
-        &self,
-        user: Option<UserPubKey>,
-        direction: Option<TxDirection>,
-        access: AccessRequest,
-    ) -> Result<Vec<RowId>> {
-        if let Some(user) = user {
-            self.check_access_for_user(user, access).await?;
-        } else {
-            if let UserRole::User = self.check_access(access).await? {
-                bail!("Access Denied: Getting bets of users is prohibited");
-            }
+    async fn login(market: &mut Mercado, secret_key: &secp256k1::SecretKey) -> AccessRequest {
+        let user = UserPubKey::from_secret_key_global(secret_key);
+        let challenge = market.create_login_challenge(user).await.unwrap();
+        let sig = secret_key.sign_ecdsa(Message::from_hashed_data::<Hash>(challenge.as_bytes()));
+        market
+            .try_login(user, sig, challenge.clone())
+            .await
+            .unwrap();
+        AccessRequest {
+            user,
+            challenge,
+            sig,
         }
-        self.db.get_txs(user, direction).await
     }
-}
+    #[tokio::test]
+    async fn creator_amendments() {
+        let (admin_key, admin) = generate_keypair(&mut rand::thread_rng());
+        let (creator_key, creator) = generate_keypair(&mut rand::thread_rng());
+        let (other_key, _) = generate_keypair(&mut rand::thread_rng());
+        let (j1_key, j1) = generate_keypair(&mut rand::thread_rng());
+        let (j2_key, j2) = generate_keypair(&mut rand::thread_rng());
+        let (_, j3) = generate_keypair(&mut rand::thread_rng());
 
-#[allow(unused)]
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::db::DB;
-    use crate::funding_source::TestFundingSource;
-    use secp256k1::{generate_keypair, rand};
-    use std::sync::Arc;
-    use tokio::sync::Mutex;
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![admin.to_string()],
+            PlatformFee::default(),
+            false,
+        )
+        .await
+        .unwrap();
+        let admin_access = login(&mut market, &admin_key).await;
+        let creator_access = login(&mut market, &creator_key).await;
+        let other_access = login(&mut market, &other_key).await;
+        let j1_access = login(&mut market, &j1_key).await;
+        let j2_access = login(&mut market, &j2_key).await;
+        for judge in [j1, j2] {
+            market
+                .adjust_balance(judge, 100, admin_access.clone())
+                .await
+                .unwrap();
+        }
+        let request = NewPredictionRequest {
+            prediction: "It will rain".to_string(),
+            outcomes: vec![],
+            scalar: None,
+            judges: vec![j1, j2],
+            judge_share_ppm: 0,
+            trading_end: Utc::now() + Duration::days(3),
+            decision_period_sec: 86400,
+            judge_count: 2,
+            judge_bond: 10,
+            dispute_period_sec: 0,
+            dispute_bond: 0,
+            appeal_judges: vec![],
+            reveal_period_sec: 0,
+            judge_replacement_sec: 0,
+            creator_fee_ppm: 0,
+            metadata: PredictionMetadata::default(),
+            resolution_rules: ResolutionRules::default(),
+        };
+        let prediction = market
+            .new_prediction(request.clone(), creator_access.clone())
+            .await
+            .unwrap();
+        market
+            .accept_nomination(prediction, j1, j1_access.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            market
+                .get_available_balance(j1, j1_access.clone())
+                .await
+                .unwrap(),
+            90
+        );
+        let amend =
+            |text: Option<&str>, trading_end, new_judges, metadata| AmendPredictionRequest {
+                prediction,
+                text: text.map(String::from),
+                trading_end,
+                new_judges,
+                metadata,
+            };
 
-    fn get_test_access() -> AccessRequest {
-        AccessRequest {
-            user: UserPubKey::from_str("023d51452445aa81ecc3cfcb82dbfe937707db5c89f9f9d21d64835158df405d8c").unwrap(),
-            sig: Signature::from_str("30440220208cef162c7081dafc61004daec32f5a3dadb4c6a1b4c0a479056a4962288d47022069022bc92673f73e9843cea14fa0cc46efa1b1e150339b603444c63035de21ee").unwrap(),
-            challenge: "iT1HqC3oaoGjbSZEjAwpGZiCbzjtyz".to_string()
+        // Only the creator can change the prediction
+        market
+            .amend_prediction(
+                amend(Some("Other"), None, vec![], None),
+                other_access.clone(),
+            )
+            .await
+            .unwrap_err();
+        market
+            .cancel_prediction(prediction, other_access.clone())
+            .await
+            .unwrap_err();
+
+        // Nominating more judges doesn't change the terms
+        market
+            .amend_prediction(amend(None, None, vec![j3], None), creator_access.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            market.db.get_judge_state(prediction, j1).await.unwrap(),
+            JudgeState::Accepted
+        );
+        assert_eq!(
+            market.db.get_judges(None, Some(j1)).await.unwrap()[0].terms_changed,
+            None
+        );
+        market
+            .amend_prediction(amend(None, None, vec![j3], None), creator_access.clone())
+            .await
+            .unwrap_err();
+        market
+            .amend_prediction(
+                amend(None, Some(Utc::now() + Duration::days(2)), vec![], None),
+                creator_access.clone(),
+            )
+            .await
+            .unwrap_err();
+
+        // Changing the wording, trading end or metadata resets accepted judges
+        let changes = [
+            amend(Some("It will rain a lot"), None, vec![], None),
+            amend(None, Some(Utc::now() + Duration::days(4)), vec![], None),
+            amend(
+                None,
+                None,
+                vec![],
+                Some(PredictionMetadata {
+                    description: "Measured in Berlin".to_string(),
+                    ..Default::default()
+                }),
+            ),
+        ];
+        for change in changes {
+            market
+                .amend_prediction(change, creator_access.clone())
+                .await
+                .unwrap();
+            assert_eq!(
+                market.db.get_judge_state(prediction, j1).await.unwrap(),
+                JudgeState::Nominated
+            );
+            assert_eq!(
+                market
+                    .get_available_balance(j1, j1_access.clone())
+                    .await
+                    .unwrap(),
+                100
+            );
+            let pending = market.db.get_judges(None, Some(j1)).await.unwrap();
+            assert_eq!(pending[0].prediction, prediction);
+            assert!(pending[0].terms_changed.is_some());
+            market
+                .accept_nomination(prediction, j1, j1_access.clone())
+                .await
+                .unwrap();
         }
-    }
+        let overview = market.db.get_prediction_overview(prediction).await.unwrap();
+        assert_eq!(overview.name, "It will rain a lot");
+        assert_eq!(overview.metadata.description, "Measured in Berlin");
+
+        // Nothing can be changed once trading started
+        market
+            .accept_nomination(prediction, j2, j2_access.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(prediction).await.unwrap(),
+            MarketState::Trading
+        );
+        market
+            .amend_prediction(
+                amend(Some("Changed"), None, vec![], None),
+                creator_access.clone(),
+            )
+            .await
+            .unwrap_err();
+        market
+            .cancel_prediction(prediction, creator_access.clone())
+            .await
+            .unwrap_err();
 
+        // Cancelling releases the bonds of accepted judges
+        let cancelled = market
+            .new_prediction(request, creator_access.clone())
+            .await
+            .unwrap();
+        market
+            .accept_nomination(cancelled, j2, j2_access.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            market
+                .get_available_balance(j2, j2_access.clone())
+                .await
+                .unwrap(),
+            80
+        );
+        market
+            .cancel_prediction(cancelled, creator_access.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            market.db.get_prediction_state(cancelled).await.unwrap(),
+            MarketState::Refunded(RefundReason::Cancelled)
+        );
+        assert_eq!(
+            market
+                .get_available_balance(j2, j2_access.clone())
+                .await
+                .unwrap(),
+            90
+        );
+    }
     #[tokio::test]
-    async fn it_works() {
-        let (_, u1) = generate_keypair(&mut rand::thread_rng());
-        let (_, u2) = generate_keypair(&mut rand::thread_rng());
-        let (_, u3) = generate_keypair(&mut rand::thread_rng());
+    async fn prediction_metadata() {
         let (_, j1) = generate_keypair(&mut rand::thread_rng());
-        let (_, j2) = generate_keypair(&mut rand::thread_rng());
-        let (_, j3) = generate_keypair(&mut rand::thread_rng());
 
         let db = DB::new("sqlite::memory:".to_string()).await;
         let mut market = Mercado::new(
             Arc::new(db),
             Box::new(TestFundingSource::default()),
             vec![],
+            PlatformFee::default(),
             true,
         )
         .await
         .unwrap();
         let access = get_test_access();
+        let metadata = PredictionMetadata {
+            description: "Counts the **daily** close on Bitstamp".to_string(),
+            resolution_source: "https://www.bitstamp.net".to_string(),
+            resolution_criteria: "Yes if any daily close is above 100k USD".to_string(),
+            category: Some("Crypto".to_string()),
+            tags: vec!["bitcoin".to_string(), "price".to_string()],
+            image_url: Some("https://example.com/btc.png".to_string()),
+        };
+        let mut predictions = vec![];
+        for (text, metadata) in [
+            ("Will BTC hit 100k", metadata.clone()),
+            ("Rain in Berlin", PredictionMetadata::default()),
+        ] {
+            let prediction = market
+                .new_prediction(
+                    NewPredictionRequest {
+                        prediction: text.to_string(),
+                        outcomes: vec![],
+                        scalar: None,
+                        judges: vec![j1],
+                        judge_share_ppm: 0,
+                        trading_end: Utc::now() + Duration::days(3),
+                        decision_period_sec: 86400,
+                        judge_count: 1,
+                        judge_bond: 0,
+                        dispute_period_sec: 0,
+                        dispute_bond: 0,
+                        appeal_judges: vec![],
+                        reveal_period_sec: 0,
+                        judge_replacement_sec: 0,
+                        creator_fee_ppm: 0,
+                        metadata,
+                        resolution_rules: ResolutionRules::default(),
+                    },
+                    access.clone(),
+                )
+                .await
+                .unwrap();
+            predictions.push(prediction);
+        }
+        let (p1, p2) = (predictions[0], predictions[1]);
+
+        let overview = market.get_prediction_overview(p1).await.unwrap();
+        assert_eq!(overview.name, "Will BTC hit 100k");
+        assert_eq!(overview.metadata, metadata);
+        let overview = market.get_prediction_overview(p2).await.unwrap();
+        assert_eq!(overview.metadata, PredictionMetadata::default());
+
+        let list = market.get_predictions(Default::default()).await.unwrap();
+        let listed = list
+            .predictions
+            .iter()
+            .map(|overview| (overview.id, overview.metadata.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            listed,
+            vec![(p2, PredictionMetadata::default()), (p1, metadata.clone())]
+        );
+        let request = PredictionListRequest {
+            category: Some("Crypto".to_string()),
+            ..Default::default()
+        };
+        let list = market.get_predictions(request).await.unwrap();
+        assert_eq!(list.predictions.len(), 1);
+        assert_eq!(list.predictions[0].metadata, metadata);
+        let request = PredictionListRequest {
+            search: Some("bitstamp".to_string()),
+            ..Default::default()
+        };
+        let list = market.get_predictions(request).await.unwrap();
+        assert_eq!(list.predictions.len(), 1);
+        assert_eq!(list.predictions[0].id, p1);
+    }
+    #[tokio::test]
+    async fn journal_migrations() {
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let path = std::env::temp_dir().join(format!("mercado_journal_{}.db", u1));
+        let url = format!("sqlite://{}?mode=rwc", path.to_str().unwrap());
+        let access = get_test_access();
+        let open = |url: String| async move {
+            Mercado::new(
+                Arc::new(DB::new(url).await),
+                Box::new(TestFundingSource::default()),
+                vec![],
+                PlatformFee::default(),
+                true,
+            )
+            .await
+            .unwrap()
+        };
+
+        let mut market = open(url.clone()).await;
         let prediction = market
             .new_prediction(
-                "It works".to_string(),
-                vec![j1.clone(), j2.clone(), j3.clone()],
-                3,
-                100000,
-                Utc::now() + Duration::days(3),
-                Duration::days(1),
+                NewPredictionRequest {
+                    prediction: "It will rain".to_string(),
+                    outcomes: vec![],
+                    scalar: None,
+                    judges: vec![j1],
+                    judge_share_ppm: 100000,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 1,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
             )
             .await
             .unwrap();
@@ -942,72 +7265,396 @@ mod test {
             .accept_nomination(prediction, j1, access.clone())
             .await
             .unwrap();
-        market
-            .accept_nomination(prediction, j2, access.clone())
+        drop(market);
+
+        // A balance and an open bet from before the journal existed
+        let legacy = sqlx::SqlitePool::connect(&url).await.unwrap();
+        sqlx::query("INSERT INTO users (pubkey, balance) VALUES (?, 1000)")
+            .bind(u1.to_string())
+            .execute(&legacy)
             .await
             .unwrap();
-        market
-            .accept_nomination(prediction, j3, access.clone())
+        sqlx::query("INSERT INTO bets (user, prediction, outcome, amount) VALUES (?,?,0,100)")
+            .bind(u1.to_string())
+            .bind(prediction)
+            .execute(&legacy)
             .await
             .unwrap();
-        let balance = market
-            .adjust_balance(u1, 200, access.clone())
+        sqlx::query("DELETE FROM migrations WHERE name IN ('opening_balances', 'escrowed_stakes')")
+            .execute(&legacy)
             .await
             .unwrap();
-        assert_eq!(balance, 200);
-        market
-            .adjust_balance(u2, 200, access.clone())
+
+        let mut market = open(url.clone()).await;
+        let books = market.check_books(access.clone()).await.unwrap();
+        assert!(books.is_balanced());
+        assert_eq!(
+            books.accounts,
+            vec![
+                (Account::Adjustments, -1000),
+                (Account::Escrow(prediction), 100)
+            ]
+        );
+        assert_eq!(market.get_balance(u1, access.clone()).await.unwrap(), 900);
+        drop(market);
+
+        // Later differences are reported but not booked away
+        sqlx::query("UPDATE users SET balance = balance + 5 WHERE pubkey = ?")
+            .bind(u1.to_string())
+            .execute(&legacy)
             .await
             .unwrap();
+        let mut market = open(url).await;
+        let books = market.check_books(access.clone()).await.unwrap();
+        assert_eq!(
+            books.balance_mismatches,
+            vec![BalanceMismatch {
+                user: u1,
+                cached: 905,
+                journal: 900,
+            }]
+        );
+        assert_eq!(books.accounts[0], (Account::Adjustments, -1000));
+
+        // Judge fees pass through the judge pool in their own transaction
         market
-            .adjust_balance(u3, 200, access.clone())
+            .force_decision_period(prediction, access.clone())
             .await
             .unwrap();
         market
-            .add_bet(prediction, u1, true, 100, access.clone())
+            .make_decision(prediction, j1, 0, access.clone())
+            .await
+            .unwrap();
+        let rows = sqlx::query(
+            "SELECT journal.description, postings.account, postings.amount \
+            FROM postings JOIN journal ON journal.rowid = postings.txn \
+            WHERE postings.account = ? ORDER BY postings.rowid",
+        )
+        .bind(Account::JudgePool(prediction).to_string())
+        .fetch_all(&legacy)
+        .await
+        .unwrap();
+        let postings: Vec<(String, Sats)> = rows
+            .iter()
+            .map(|row| {
+                (
+                    sqlx::Row::get(row, "description"),
+                    sqlx::Row::get(row, "amount"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            postings,
+            vec![
+                ("Settlement".to_string(), 10),
+                ("Judge fees".to_string(), -10)
+            ]
+        );
+        assert_eq!(market.get_balance(j1, access.clone()).await.unwrap(), 10);
+        assert_eq!(market.get_balance(u1, access.clone()).await.unwrap(), 995);
+        legacy.close().await;
+    }
+    #[tokio::test]
+    async fn amm_leaderboard() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+        let (_, creator) = generate_keypair(&mut rand::thread_rng());
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        for (user, name) in [(u1, "u1"), (u2, "u2"), (creator, "creator")] {
+            market
+                .adjust_balance(user, 200, access.clone())
+                .await
+                .unwrap();
+            market
+                .update_user(user, Some(name.to_string()), access.clone())
+                .await
+                .unwrap();
+        }
+        let amm = market
+            .new_amm_prediction(
+                NewAmmPredictionRequest {
+                    creator,
+                    subsidy: 100,
+                    prediction: NewPredictionRequest {
+                        prediction: "Market maker".to_string(),
+                        outcomes: vec![],
+                        scalar: None,
+                        judges: vec![j1],
+                        judge_share_ppm: 100000,
+                        trading_end: Utc::now() + Duration::days(3),
+                        decision_period_sec: 86400,
+                        judge_count: 1,
+                        judge_bond: 0,
+                        dispute_period_sec: 0,
+                        dispute_bond: 0,
+                        appeal_judges: vec![],
+                        reveal_period_sec: 0,
+                        judge_replacement_sec: 0,
+                        creator_fee_ppm: 0,
+                        metadata: PredictionMetadata::default(),
+                        resolution_rules: ResolutionRules::default(),
+                    },
+                },
+                access.clone(),
+            )
             .await
             .unwrap();
         market
-            .add_bet(prediction, u2, true, 100, access.clone())
+            .accept_nomination(amm, j1, access.clone())
+            .await
+            .unwrap();
+        let trade = |user, outcome, shares| TradeSharesRequest {
+            prediction: amm,
+            user,
+            outcome,
+            shares,
+            limit: None,
+        };
+        let u1_cost = market
+            .buy_shares(trade(u1, 0, 30), access.clone())
+            .await
+            .unwrap();
+        let u2_cost = market
+            .buy_shares(trade(u2, 1, 20), access.clone())
+            .await
+            .unwrap();
+        let u1_proceeds = market
+            .sell_shares(trade(u1, 0, 10), access.clone())
             .await
             .unwrap();
         market
-            .add_bet(prediction, u3, true, 100, access.clone())
+            .force_decision_period(amm, access.clone())
             .await
             .unwrap();
         market
-            .add_bet(prediction, u1, false, 100, access.clone())
+            .make_decision(amm, j1, 0, access.clone())
+            .await
+            .unwrap();
+
+        // Profits are what the trades and the subsidy did to the balances
+        let leaderboard = market
+            .get_bettor_leaderboard(BettorLeaderboardRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(leaderboard.len(), 3);
+        for rank in leaderboard.iter() {
+            let balance = market.get_balance(rank.user, access.clone()).await.unwrap();
+            assert_eq!(rank.profit, balance - 200);
+            assert_eq!(rank.roi, rank.profit as f64 / rank.volume as f64);
+            assert_eq!(rank.predictions, 1);
+        }
+        let rank = |user| {
+            leaderboard
+                .iter()
+                .find(|rank| rank.user == user)
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(rank(u1).profit, 20 - u1_cost + u1_proceeds);
+        assert_eq!(rank(u1).volume, u1_cost);
+        assert_eq!(rank(u1).correct, 1);
+        assert_eq!(rank(u2).profit, -u2_cost);
+        assert_eq!(rank(u2).volume, u2_cost);
+        assert_eq!(rank(u2).correct, 0);
+        assert_eq!(rank(creator).volume, 100);
+        assert!(rank(creator).profit > -100);
+    }
+    #[tokio::test]
+    async fn advance_markets_past_failures() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, a1) = generate_keypair(&mut rand::thread_rng());
+        let path = std::env::temp_dir().join(format!("mercado_advance_{}.db", j1));
+        let url = format!("sqlite://{}?mode=rwc", path.to_str().unwrap());
+        let mut market = Mercado::new(
+            Arc::new(DB::new(url.clone()).await),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let mut predictions = vec![];
+        for _ in 0..2 {
+            let prediction = market
+                .new_prediction(
+                    NewPredictionRequest {
+                        prediction: "Waits for disputes".to_string(),
+                        outcomes: vec![],
+                        scalar: None,
+                        judges: vec![j1],
+                        judge_share_ppm: 0,
+                        trading_end: Utc::now() + Duration::days(3),
+                        decision_period_sec: 86400,
+                        judge_count: 1,
+                        judge_bond: 0,
+                        dispute_period_sec: 86400,
+                        dispute_bond: 0,
+                        appeal_judges: vec![a1],
+                        reveal_period_sec: 0,
+                        judge_replacement_sec: 0,
+                        creator_fee_ppm: 0,
+                        metadata: PredictionMetadata::default(),
+                        resolution_rules: ResolutionRules::default(),
+                    },
+                    access.clone(),
+                )
+                .await
+                .unwrap();
+            market
+                .accept_nomination(prediction, j1, access.clone())
+                .await
+                .unwrap();
+            market
+                .force_decision_period(prediction, access.clone())
+                .await
+                .unwrap();
+            market
+                .make_decision(prediction, j1, 0, access.clone())
+                .await
+                .unwrap();
+            predictions.push(prediction);
+        }
+        // Both dispute periods are over but the first prediction can't be read
+        let connection = sqlx::SqlitePool::connect(&url).await.unwrap();
+        sqlx::query("UPDATE predictions SET dispute_period = 0")
+            .execute(&connection)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE predictions SET state = 'WaitingForDisputes(x)' WHERE rowid = ?")
+            .bind(predictions[0])
+            .execute(&connection)
             .await
             .unwrap();
+        market.advance_markets().await.unwrap();
         market
-            .add_bet(prediction, u2, false, 100, access.clone())
+            .db
+            .get_prediction_state(predictions[0])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            market
+                .db
+                .get_prediction_state(predictions[1])
+                .await
+                .unwrap(),
+            MarketState::Resolved(0)
+        );
+        connection.close().await;
+    }
+    #[tokio::test]
+    async fn halted_orders_stay_reserved() {
+        let (_, j1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u1) = generate_keypair(&mut rand::thread_rng());
+        let (_, u2) = generate_keypair(&mut rand::thread_rng());
+
+        let db = DB::new("sqlite::memory:".to_string()).await;
+        let mut market = Mercado::new(
+            Arc::new(db),
+            Box::new(TestFundingSource::default()),
+            vec![],
+            PlatformFee::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        let access = get_test_access();
+        let prediction = market
+            .new_prediction(
+                NewPredictionRequest {
+                    prediction: "It will rain".to_string(),
+                    outcomes: vec![],
+                    scalar: None,
+                    judges: vec![j1],
+                    judge_share_ppm: 0,
+                    trading_end: Utc::now() + Duration::days(3),
+                    decision_period_sec: 86400,
+                    judge_count: 1,
+                    judge_bond: 0,
+                    dispute_period_sec: 0,
+                    dispute_bond: 0,
+                    appeal_judges: vec![],
+                    reveal_period_sec: 0,
+                    judge_replacement_sec: 0,
+                    creator_fee_ppm: 0,
+                    metadata: PredictionMetadata::default(),
+                    resolution_rules: ResolutionRules::default(),
+                },
+                access.clone(),
+            )
             .await
             .unwrap();
         market
-            .add_bet(prediction, u3, false, 100, access.clone())
+            .accept_nomination(prediction, j1, access.clone())
             .await
             .unwrap();
+        for user in [u1, u2] {
+            market
+                .adjust_balance(user, 100, access.clone())
+                .await
+                .unwrap();
+        }
+        let order = |user, side| PlaceOrderRequest {
+            prediction,
+            user,
+            outcome: 0,
+            side,
+            price: 60,
+            quantity: 1,
+        };
         market
-            .force_decision_period(prediction, access.clone())
+            .place_order(order(u1, OrderSide::Bid), access.clone())
             .await
             .unwrap();
+
+        // The halt keeps the order, so its reserve can't be withdrawn
         market
-            .make_decision(prediction, j1, true, access.clone())
+            .halt_prediction(prediction, "Oracle is down".to_string(), access.clone())
             .await
             .unwrap();
+        assert_eq!(
+            market
+                .get_available_balance(u1, access.clone())
+                .await
+                .unwrap(),
+            40
+        );
+        market
+            .init_withdrawal_bolt11(u1, "invoice".to_string(), 100, access.clone())
+            .await
+            .unwrap_err();
         market
-            .make_decision(prediction, j2, true, access.clone())
+            .resume_prediction(prediction, false, access.clone())
             .await
             .unwrap();
         market
-            .make_decision(prediction, j3, true, access.clone())
+            .place_order(order(u2, OrderSide::Ask), access.clone())
             .await
             .unwrap();
-        assert_eq!(market.get_balance(u1, access.clone()).await.unwrap(), 179);
-        assert_eq!(market.get_balance(u2, access.clone()).await.unwrap(), 179);
-        assert_eq!(market.get_balance(u3, access.clone()).await.unwrap(), 179);
-        assert_eq!(market.get_balance(j1, access.clone()).await.unwrap(), 20);
-        assert_eq!(market.get_balance(j2, access.clone()).await.unwrap(), 20);
-        assert_eq!(market.get_balance(j3, access.clone()).await.unwrap(), 20);
+        for (user, balance) in [(u1, 40), (u2, 60)] {
+            assert_eq!(
+                market.get_balance(user, access.clone()).await.unwrap(),
+                balance
+            );
+            assert_eq!(
+                market
+                    .get_available_balance(user, access.clone())
+                    .await
+                    .unwrap(),
+                balance
+            );
+        }
     }
 }